@@ -2,12 +2,13 @@
 #![no_main]
 
 use aya_ebpf::{
-    bindings::{TC_ACT_PIPE, TC_ACT_SHOT, TC_ACT_OK},
+    bindings::{TC_ACT_PIPE, TC_ACT_SHOT, TC_ACT_OK, BPF_NOEXIST},
     macros::{classifier, sock_ops, map},
-    maps::HashMap,
+    maps::{HashMap, LruHashMap, PerCpuArray, PerCpuHashMap, PerfEventArray},
     programs::{TcContext, SockOpsContext},
     bindings::{
         BPF_SOCK_OPS_TCP_CONNECT_CB,
+        BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB,
         BPF_SOCK_OPS_STATE_CB,
 
         BPF_SOCK_OPS_STATE_CB_FLAG,
@@ -18,11 +19,12 @@ use aya_ebpf::{
     helpers::{
         bpf_get_socket_cookie,
         bpf_get_prandom_u32,
+        bpf_get_smp_processor_id,
         bpf_ktime_get_ns,
     },
     EbpfContext,
 };
-use aya_log_ebpf::info;
+use aya_log_ebpf::{debug, info, warn};
 use aya_log_ebpf::WriteToBuf;
 use aya_log_ebpf::macro_support::DefaultFormatter;
 use aya_log_common::Argument;
@@ -31,7 +33,7 @@ use network_types::{
     ip::{Ipv4Hdr, IpProto},
     tcp::TcpHdr,
 };
-use tcp_tester_common::{FlowKey, SocketKey, Direction, FlowConfig, DelayConditioner, DropPacketConditioner, Selector, Conditioner};
+use tcp_tester_common::{latency_histogram_bucket, LATENCY_HISTOGRAM_BUCKETS, EXCEPTION_CNT_PROGRAMS, EXCEPTION_CNT_TC_EGRESS, EXCEPTION_CNT_TC_INGRESS, EXCEPTION_CNT_TC_PASSIVE_EGRESS, EXCEPTION_CNT_TC_PASSIVE_INGRESS, EXCEPTION_CNT_SOCKOPS, FLOW_ERROR_NAMES, FLOW_ERROR_ETH_HEADER, FLOW_ERROR_IPV4_HEADER, FLOW_ERROR_TCP_HEADER, FLOW_ERROR_FLOW_STATS_INSERT, FlowKey, FlowState, FlowStats, PerfSample, SocketKey, Direction, FaultProfile, DelayConditioner, DropPacketConditioner, Selector, Conditioner, PROGRAM_VERSION, EBPF_LOG_LEVEL_DEBUG, EBPF_LOG_LEVEL_INFO, EBPF_LOG_LEVEL_WARN};
 use core::num::{NonZeroUsize, TryFromIntError};
 
 
@@ -53,18 +55,107 @@ use core::num::{NonZeroUsize, TryFromIntError};
 int btf_find_spin_lock(const struct btf *btf, const struct btf_type *t)
 */
 
-#[repr(C)]
-struct FlowState {
-    start_seq: u32,
-    config: FlowConfig,
-}
-
 // atomic updates to values in map
 // https://reviews.llvm.org/D72184
+// The maps holding state are pinned by name, under the directory set by userspace, for the ones
+// pinned by a previous run to be reused with `--pin-maps`.
+#[map]
+static FLOW_CONFIG: HashMap<FlowKey, FlowState> = HashMap::pinned(1024, 0);
+#[map]
+static SOCKET_CONFIG: HashMap<SocketKey, FaultProfile> = HashMap::pinned(1024, 0);
+// Monotonic time at which userspace inserted the SOCKET_CONFIG entries, to expire the orphaned ones.
+#[map]
+static SOCKET_CONFIG_TIMESTAMPS: HashMap<SocketKey, u64> = HashMap::pinned(1024, 0);
+// The stats outlive the flows, so the least recently updated entries are evicted once the map
+// is full.
+#[map]
+static FLOW_STATS: LruHashMap<FlowKey, FlowStats> = LruHashMap::pinned(1024, 0);
+// Start time of the flows, keyed by socket cookie, used to compute the flow duration on close.
+#[map]
+static FLOW_START_TIME: HashMap<u64, u64> = HashMap::pinned(1024, 0);
+// Number of completed flows per duration bucket, see `latency_histogram_bucket`.
+#[map]
+static LATENCY_HISTOGRAM: PerCpuArray<u64> = PerCpuArray::pinned(LATENCY_HISTOGRAM_BUCKETS, 0);
+// Number of error paths hit per program, which would otherwise go unnoticed, see
+// `EXCEPTION_CNT_PROGRAMS`.
+#[map]
+static EXCEPTION_CNT: PerCpuArray<u64> = PerCpuArray::pinned(EXCEPTION_CNT_PROGRAMS.len() as u32, 0);
+// Number of errors hit by the traffic control programs per error code, see `FLOW_ERROR_NAMES`.
+#[map]
+static FLOW_ERRORS: PerCpuHashMap<u32, u64> = PerCpuHashMap::pinned(FLOW_ERROR_NAMES.len() as u32, 0);
+// Number of established client connections, incremented when they are established and
+// decremented when they are closed. A connection may close on another CPU, so the per-CPU values
+// can be negative, only their sum is meaningful.
+#[map]
+static ACTIVE_FLOWS_GAUGE: PerCpuArray<i64> = PerCpuArray::pinned(1, 0);
+// Delayed packets sampled at `--perf-event-sample-rate`, see `PerfSample`.
 #[map]
-static FLOW_CONFIG: HashMap<FlowKey, FlowState> = HashMap::with_max_entries(1024, 0);
+static PERF_OUTPUT: PerfEventArray<PerfSample> = PerfEventArray::new(0);
+// Time of the last `PERF_OUTPUT` sample of the CPU.
 #[map]
-static SOCKET_CONFIG: HashMap<SocketKey, FlowConfig> = HashMap::with_max_entries(1024, 0);
+static PERF_LAST_SAMPLE: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+// Version of the map layouts the program was built with, read by userspace from the object
+// before loading it, see `PROGRAM_VERSION`.
+#[no_mangle]
+#[used]
+static TCP_TESTER_PROGRAM_VERSION: u32 = PROGRAM_VERSION;
+// Most verbose level of the messages logged, one of `EBPF_LOG_LEVEL_*`, set by userspace before
+// loading the programs with `--ebpf-log-level`.
+#[no_mangle]
+static LOG_LEVEL: u32 = EBPF_LOG_LEVEL_INFO;
+
+// Minimum time between two `PERF_OUTPUT` samples of a CPU, 0 disabling the sampling, set by
+// userspace before loading the programs with `--perf-event-sample-rate`.
+#[no_mangle]
+static PERF_SAMPLE_INTERVAL_NS: u64 = 0;
+
+// Outputs a `PERF_OUTPUT` sample of the packet, unless the CPU sampled one less than
+// `PERF_SAMPLE_INTERVAL_NS` ago.
+fn sample_delay(ctx: &TcContext, key: FlowKey, ts: u64, latency_ns: u64) {
+    // Volatile, so that the compiler doesn't assume the global keeps its initial value.
+    let interval = unsafe { core::ptr::read_volatile(&PERF_SAMPLE_INTERVAL_NS) };
+    if interval == 0 {
+        return;
+    }
+    let Some(last_sample) = PERF_LAST_SAMPLE.get_ptr_mut(0) else {
+        return;
+    };
+    // Per-CPU value, no need for atomic operations.
+    if ts.wrapping_sub(unsafe { *last_sample }) < interval {
+        return;
+    }
+    unsafe { *last_sample = ts };
+    let sample = PerfSample {
+        timestamp_ns: ts,
+        key,
+        cpu: unsafe { bpf_get_smp_processor_id() },
+        latency_ns: latency_ns.min(u32::MAX as u64) as u32,
+    };
+    PERF_OUTPUT.output(ctx, &sample, 0);
+}
+
+#[inline(always)]
+fn log_enabled(level: u32) -> bool {
+    // Volatile, so that the compiler doesn't assume the global keeps its initial value.
+    unsafe { core::ptr::read_volatile(&LOG_LEVEL) >= level }
+}
+
+fn record_exception(program: u32) {
+    if let Some(count) = EXCEPTION_CNT.get_ptr_mut(program) {
+        // Per-CPU value, no need for atomic operations.
+        unsafe { *count += 1 };
+    }
+}
+
+fn record_flow_error(code: u32) {
+    match FLOW_ERRORS.get_ptr_mut(&code) {
+        // Per-CPU value, no need for atomic operations.
+        Some(count) => unsafe { *count += 1 },
+        None => {
+            let _ = FLOW_ERRORS.insert(&code, &1, BPF_NOEXIST as u64);
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
@@ -154,16 +245,60 @@ impl DefaultFormatter for &TcpState {}
 
 #[classifier]
 pub fn tcp_tester_tc_egress(ctx: TcContext) -> i32 {
-    match try_tc_egress(ctx) {
+    match try_tc(ctx, Direction::EGRESS) {
+        Ok(ret) => ret,
+        Err(_) => {
+            record_exception(EXCEPTION_CNT_TC_EGRESS);
+            TC_ACT_SHOT
+        }
+    }
+}
+
+#[classifier]
+pub fn tcp_tester_tc_ingress(ctx: TcContext) -> i32 {
+    match try_tc(ctx, Direction::INGRESS) {
         Ok(ret) => ret,
-        Err(_) => TC_ACT_SHOT,
+        Err(_) => {
+            record_exception(EXCEPTION_CNT_TC_INGRESS);
+            TC_ACT_SHOT
+        }
     }
 }
 
-fn get_socket_config(key: SocketKey) -> Option<&'static FlowConfig> {
+// Monitoring-only counterparts of the programs above, used to measure the eBPF overhead without
+// any fault injection. Unparseable packets are let through rather than dropped.
+#[classifier]
+pub fn tcp_tester_tc_passive_egress(ctx: TcContext) -> i32 {
+    try_tc_passive(ctx, Direction::EGRESS).unwrap_or_else(|_| {
+        record_exception(EXCEPTION_CNT_TC_PASSIVE_EGRESS);
+        TC_ACT_PIPE
+    })
+}
+
+#[classifier]
+pub fn tcp_tester_tc_passive_ingress(ctx: TcContext) -> i32 {
+    try_tc_passive(ctx, Direction::INGRESS).unwrap_or_else(|_| {
+        record_exception(EXCEPTION_CNT_TC_PASSIVE_INGRESS);
+        TC_ACT_PIPE
+    })
+}
+
+fn get_socket_config(key: SocketKey) -> Option<&'static FaultProfile> {
     unsafe { SOCKET_CONFIG.get(&key) }
 }
 
+// A userspace bug must not make the TC programs apply garbage, the implausible entries are replaced by
+// a fault free profile.
+fn checked_config(ctx: &SockOpsContext, config: &FaultProfile) -> FaultProfile {
+    if config.is_plausible() {
+        return config.clone();
+    }
+    if log_enabled(EBPF_LOG_LEVEL_WARN) {
+        warn!(ctx, "implausible SOCKET_CONFIG entry, applying no fault");
+    }
+    FaultProfile::NONE
+}
+
 fn get_socket_key(ctx: &SockOpsContext, direction: Direction) -> SocketKey {
     let cookie = unsafe { bpf_get_socket_cookie(ctx.as_ptr())};
     SocketKey::new(cookie, direction)
@@ -190,10 +325,41 @@ pub fn tcp_tester_sockops(ctx: SockOpsContext) -> u32 {
     }
 }
 
+fn record_flow_start(ctx: &SockOpsContext) {
+    let cookie = unsafe { bpf_get_socket_cookie(ctx.as_ptr()) };
+    let now = unsafe { bpf_ktime_get_ns() };
+    if FLOW_START_TIME.insert(&cookie, &now, 0).is_err() {
+        record_exception(EXCEPTION_CNT_SOCKOPS);
+    }
+}
+
+fn record_flow_duration(ctx: &SockOpsContext) {
+    let cookie = unsafe { bpf_get_socket_cookie(ctx.as_ptr()) };
+    let start = match unsafe { FLOW_START_TIME.get(&cookie) } {
+        Some(start) => *start,
+        None => return,
+    };
+    let _ = FLOW_START_TIME.remove(&cookie);
+
+    let duration_us = (unsafe { bpf_ktime_get_ns() } - start) / 1000;
+    if let Some(count) = LATENCY_HISTOGRAM.get_ptr_mut(latency_histogram_bucket(duration_us)) {
+        // Per-CPU value, no need for atomic operations.
+        unsafe { *count += 1 };
+    }
+}
+
+fn update_active_flows(delta: i64) {
+    if let Some(count) = ACTIVE_FLOWS_GAUGE.get_ptr_mut(0) {
+        // Per-CPU value, no need for atomic operations.
+        unsafe { *count += delta };
+    }
+}
+
 fn handle_sockops(ctx: SockOpsContext) -> Option<u32> {
     match ctx.op() {
         BPF_SOCK_OPS_TCP_CONNECT_CB => {
             let _ = ctx.set_cb_flags((BPF_SOCK_OPS_STATE_CB_FLAG | ctx.cb_flags()) as i32);
+            record_flow_start(&ctx);
 
             let ingress_socket_key = get_socket_key(&ctx, Direction::INGRESS);
             let egress_socket_key = ingress_socket_key.reverse();
@@ -207,26 +373,35 @@ fn handle_sockops(ctx: SockOpsContext) -> Option<u32> {
 
             if let Some(config) = get_socket_config(egress_socket_key) {
                 let state = FlowState {
-                    config: config.clone(),
+                    config: checked_config(&ctx, config),
                     start_seq: 0
                 };
 
-                let _ = FLOW_CONFIG.insert(&egress_key, &state, 0);
+                if FLOW_CONFIG.insert(&egress_key, &state, 0).is_err() {
+                    record_exception(EXCEPTION_CNT_SOCKOPS);
+                }
                 // we don't need the socket/cookie config anymore
                 let _ = SOCKET_CONFIG.remove(&egress_socket_key);
+                let _ = SOCKET_CONFIG_TIMESTAMPS.remove(&egress_socket_key);
             };
 
             if let Some(config) = get_socket_config(ingress_socket_key) {
                 let state = FlowState {
-                    config: config.clone(),
+                    config: checked_config(&ctx, config),
                     start_seq: 0
                 };
 
-                let _ = FLOW_CONFIG.insert(&ingress_key, &state, 0);
+                if FLOW_CONFIG.insert(&ingress_key, &state, 0).is_err() {
+                    record_exception(EXCEPTION_CNT_SOCKOPS);
+                }
                 // we don't need the socket/cookie config anymore
                 let _ = SOCKET_CONFIG.remove(&ingress_socket_key);
+                let _ = SOCKET_CONFIG_TIMESTAMPS.remove(&ingress_socket_key);
             };
         },
+        BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB => {
+            update_active_flows(1);
+        },
         BPF_SOCK_OPS_STATE_CB => {
             let old = ctx.arg(0);
             let new = ctx.arg(1);
@@ -237,9 +412,17 @@ fn handle_sockops(ctx: SockOpsContext) -> Option<u32> {
 
             let old_state = TcpState::from(old)?;
             let new_state = TcpState::from(new)?;
-            info!(&ctx, "old: {}, new: {}, seq: {}", &old_state, &new_state, nxt_seq);
+            if log_enabled(EBPF_LOG_LEVEL_INFO) {
+                info!(&ctx, "old: {}, new: {}, seq: {}", &old_state, &new_state, nxt_seq);
+            }
 
             if new_state == TcpState::TCP_CLOSE {
+                record_flow_duration(&ctx);
+                // The connections failing to connect were never counted.
+                if old_state != TcpState::TCP_SYN_SENT {
+                    update_active_flows(-1);
+                }
+
                 let egress_key = get_flow_key(&ctx);
                 let ingress_key = egress_key.reverse();
 
@@ -260,23 +443,52 @@ fn get_config(key: FlowKey) -> Option<*mut FlowState> {
     FLOW_CONFIG.get_ptr_mut(&key)
 }
 
-fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
+fn update_flow_stats(key: &FlowKey, direction: Direction, len: u64) {
+    match FLOW_STATS.get_ptr_mut(key) {
+        Some(stats) => unsafe {
+            if direction == Direction::INGRESS {
+                (*stats).rx_packets += 1;
+                (*stats).rx_bytes += len;
+            } else {
+                (*stats).tx_packets += 1;
+                (*stats).tx_bytes += len;
+            }
+        },
+        None => {
+            let mut stats = FlowStats::default();
+            if direction == Direction::INGRESS {
+                stats.rx_packets = 1;
+                stats.rx_bytes = len;
+            } else {
+                stats.tx_packets = 1;
+                stats.tx_bytes = len;
+            }
+            if FLOW_STATS.insert(key, &stats, 0).is_err() {
+                record_flow_error(FLOW_ERROR_FLOW_STATS_INSERT);
+            }
+        }
+    }
+}
+
+// Parses the packet headers, returning the flow key and the TCP sequence number for IPv4 TCP
+// packets and `None` for any other traffic.
+fn parse_tcp_flow(ctx: &TcContext) -> Result<Option<(FlowKey, u32)>, ()> {
     // TODO: consider getting flow fields from `ctx.skbuff`, rather than parsing, if possible.
-    let ethhdr: EthHdr = ctx.load(0).map_err(|_| ())?;
+    let ethhdr: EthHdr = ctx.load(0).map_err(|_| record_flow_error(FLOW_ERROR_ETH_HEADER))?;
     match ethhdr.ether_type {
         EtherType::Ipv4 => {}
-        _ => return Ok(TC_ACT_PIPE),
+        _ => return Ok(None),
     }
 
-    let ipv4hdr: Ipv4Hdr = ctx.load(EthHdr::LEN).map_err(|_| ())?;
+    let ipv4hdr: Ipv4Hdr = ctx.load(EthHdr::LEN).map_err(|_| record_flow_error(FLOW_ERROR_IPV4_HEADER))?;
     let sip = u32::from_be(ipv4hdr.src_addr);
     let dip = u32::from_be(ipv4hdr.dst_addr);
 
     match ipv4hdr.proto {
         IpProto::Tcp => {}
-        _ => return Ok(TC_ACT_PIPE),
+        _ => return Ok(None),
     }
-    let tcphdr: TcpHdr = ctx.load(EthHdr::LEN + Ipv4Hdr::LEN).map_err(|_| ())?;
+    let tcphdr: TcpHdr = ctx.load(EthHdr::LEN + Ipv4Hdr::LEN).map_err(|_| record_flow_error(FLOW_ERROR_TCP_HEADER))?;
 
     let sport = u16::from_be(tcphdr.source);
     let dport = u16::from_be(tcphdr.dest);
@@ -288,8 +500,26 @@ fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
         dport: dport.into(),
     };
 
+    Ok(Some((key, u32::from_be(tcphdr.seq))))
+}
+
+// Only accounts the packet in `FLOW_STATS`, the packet is never altered nor dropped.
+fn try_tc_passive(ctx: TcContext, direction: Direction) -> Result<i32, ()> {
+    if let Some((key, _)) = parse_tcp_flow(&ctx)? {
+        update_flow_stats(&key, direction, ctx.len() as u64);
+    }
+    Ok(TC_ACT_PIPE)
+}
+
+fn try_tc(ctx: TcContext, direction: Direction) -> Result<i32, ()> {
+    let (key, tcp_seq) = match parse_tcp_flow(&ctx)? {
+        Some(flow) => flow,
+        None => return Ok(TC_ACT_PIPE),
+    };
+
+    update_flow_stats(&key, direction, ctx.len() as u64);
+
     let action = if let Some(state) = get_config(key) {
-        let tcp_seq = u32::from_be(tcphdr.seq);
         let start_seq = unsafe { &mut (*state).start_seq };
 
         // Store the first sequence number we see so we can reference an offset from that.
@@ -298,7 +528,9 @@ fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
         }
         let seq_offset = tcp_seq - *start_seq;
 
-        info!(&ctx, "have config {:i} {:i} {} {}, seq: {}, tcpseq: {}", key.sip, key.dip, key.sport, key.dport, seq_offset, tcp_seq);
+        if log_enabled(EBPF_LOG_LEVEL_DEBUG) {
+            debug!(&ctx, "have config {:i} {:i} {} {}, seq: {}, tcpseq: {}", key.sip, key.dip, key.sport, key.dport, seq_offset, tcp_seq);
+        }
 
         let conditioner = unsafe { &mut (*state).config.conditioner };
         match conditioner {
@@ -307,11 +539,15 @@ fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
                 TC_ACT_PIPE
             },
             Conditioner::DropPacket(drop) => {
-                info!(&ctx, "drop.count: {}", drop.count);
+                if log_enabled(EBPF_LOG_LEVEL_DEBUG) {
+                    debug!(&ctx, "drop.count: {}", drop.count);
+                }
 
                 if drop.count > 0 {
                     drop.count -= 1;
-                    info!(&ctx, "after drop.count: {}", drop.count);
+                    if log_enabled(EBPF_LOG_LEVEL_DEBUG) {
+                        debug!(&ctx, "after drop.count: {}", drop.count);
+                    }
                     TC_ACT_SHOT
                 } else {
                     TC_ACT_PIPE
@@ -332,6 +568,7 @@ fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
                 let tstamp = ts + delay.offset + (jitter) as u64;
 
                 unsafe { (*ctx.skb.skb).tstamp = tstamp };
+                sample_delay(&ctx, key, ts, tstamp - ts);
                 TC_ACT_PIPE
             }
         }
@@ -339,7 +576,9 @@ fn try_tc_egress(ctx: TcContext) -> Result<i32, ()> {
         TC_ACT_PIPE
     };
 
-    info!(&ctx, "DEST {:i}, ACTION {}", sip, action);
+    if log_enabled(EBPF_LOG_LEVEL_DEBUG) {
+        debug!(&ctx, "DEST {:i}, ACTION {}", key.sip, action);
+    }
 
     Ok(action)
 }
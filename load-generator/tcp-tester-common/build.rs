@@ -0,0 +1,12 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+fn main() {
+    // The protobuf equivalent of the flow configuration, generated with protoc. The maps are
+    // BTreeMaps, the crate being no_std.
+    #[cfg(feature = "proto")]
+    prost_build::Config::new()
+        .btree_map(["."])
+        .compile_protos(&["proto/flow_config.proto"], &["proto"])
+        .expect("Failed to compile the flow configuration protobuf schema");
+}
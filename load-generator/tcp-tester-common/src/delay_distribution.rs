@@ -0,0 +1,150 @@
+extern crate std;
+
+use core::time::Duration;
+use rand::{Rng, RngExt};
+
+use crate::{Conditioner, DelayDistribution, FaultProfile, FlowConfig, MAX_DELAY_NS};
+
+/// Largest delay drawn, in milliseconds, the largest the sockops program accepts.
+const MAX_DELAY_MS: f64 = MAX_DELAY_NS as f64 / 1e6;
+
+/// Draws a delay from the distribution, `None` for `DelayDistribution::Constant`, which keeps
+/// the delays configured.
+fn sample<R: Rng>(distribution: DelayDistribution, rng: &mut R) -> Option<Duration> {
+    let delay_ms = match distribution {
+        DelayDistribution::Constant => return None,
+        DelayDistribution::Uniform { min_ms, max_ms } => rng.random_range(min_ms..=max_ms),
+        DelayDistribution::Normal { mean_ms, stddev_ms } => {
+            // Box-Muller transform, 1 - u keeping the logarithm finite.
+            let (u, v): (f64, f64) = (rng.random(), rng.random());
+            let z = (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * core::f64::consts::PI * v).cos();
+            mean_ms + stddev_ms * z
+        }
+        DelayDistribution::Pareto { scale_ms, shape } => {
+            // Inverse transform sampling.
+            let u: f64 = rng.random();
+            scale_ms / (1.0 - u).powf(1.0 / shape)
+        }
+    };
+    // The heavy tails of the distributions overflow a `Duration` with the small shapes and the
+    // large deviations.
+    let delay_ms = match delay_ms.is_finite() {
+        true => delay_ms.clamp(0.0, MAX_DELAY_MS),
+        false => MAX_DELAY_MS,
+    };
+    Some(Duration::from_secs_f64(delay_ms / 1000.0))
+}
+
+fn set_delay(profile: &mut FaultProfile, delay: Duration) {
+    if let Conditioner::Delay(delay_conditioner) = &mut profile.conditioner {
+        delay_conditioner.offset = (delay.as_nanos() as u64).min(MAX_DELAY_NS);
+        delay_conditioner.jitter = 0;
+    }
+}
+
+impl FlowConfig {
+    /// Draws a delay from `delay_distribution` and sets it on all the `Delay` conditioners of
+    /// the flow, egress, ingress and path flaps alike. With `DelayDistribution::Constant`, the
+    /// conditioners are kept and the offset of the egress one is returned, zero when it doesn't
+    /// delay.
+    pub fn sample_delay(&mut self, rng: &mut impl Rng) -> Duration {
+        let Some(delay) = sample(self.delay_distribution, rng) else {
+            return match self.egress.conditioner {
+                Conditioner::Delay(delay) => Duration::from_nanos(delay.offset),
+                _ => Duration::ZERO,
+            };
+        };
+        set_delay(&mut self.egress, delay);
+        set_delay(&mut self.ingress, delay);
+        if let Some(path_flap) = &mut self.path_flap {
+            set_delay(&mut path_flap.primary, delay);
+            set_delay(&mut path_flap.secondary, delay);
+        }
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{validate_fault_profile, DelayConditioner, Selector};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sample_delay_bounds() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(sample(DelayDistribution::Constant, &mut rng), None);
+        for _ in 0..1000 {
+            let uniform = DelayDistribution::Uniform {
+                min_ms: 10.0,
+                max_ms: 20.0,
+            };
+            let delay = sample(uniform, &mut rng).unwrap();
+            assert!((Duration::from_millis(10)..=Duration::from_millis(20)).contains(&delay));
+
+            let pareto = DelayDistribution::Pareto {
+                scale_ms: 5.0,
+                shape: 1.5,
+            };
+            assert!(sample(pareto, &mut rng).unwrap() >= Duration::from_millis(5));
+
+            // The negative samples are clamped.
+            let normal = DelayDistribution::Normal {
+                mean_ms: 1.0,
+                stddev_ms: 10.0,
+            };
+            assert!(sample(normal, &mut rng).is_some());
+        }
+    }
+
+    #[test]
+    fn test_sample_heavy_tail() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let max_delay = Duration::from_nanos(MAX_DELAY_NS);
+        for _ in 0..1000 {
+            let pareto = DelayDistribution::Pareto {
+                scale_ms: 5.0,
+                shape: 0.1,
+            };
+            assert!(sample(pareto, &mut rng).unwrap() <= max_delay);
+
+            let normal = DelayDistribution::Normal {
+                mean_ms: 1.0,
+                stddev_ms: f64::MAX,
+            };
+            assert!(sample(normal, &mut rng).unwrap() <= max_delay);
+        }
+    }
+
+    #[test]
+    fn test_set_delay_stays_plausible() {
+        let mut profile = FaultProfile {
+            conditioner: Conditioner::Delay(DelayConditioner {
+                count: 0,
+                offset: 0,
+                jitter: 0,
+            }),
+            ..FaultProfile::NONE
+        };
+        set_delay(&mut profile, Duration::from_secs(3600));
+        assert!(profile.is_plausible());
+        assert!(validate_fault_profile("egress", &profile).is_none());
+
+        // The sockops program applies no fault instead of these.
+        if let Conditioner::Delay(delay) = &mut profile.conditioner {
+            delay.jitter = 1;
+        }
+        assert!(!profile.is_plausible());
+        assert!(validate_fault_profile("egress", &profile).is_some());
+        let inverted_selector = FaultProfile {
+            selector: Selector {
+                data_offset_min: 10,
+                data_offset_max: 0,
+                flags: 0,
+            },
+            ..FaultProfile::NONE
+        };
+        assert!(!inverted_selector.is_plausible());
+    }
+}
@@ -1,9 +1,28 @@
-#![no_std]
+// The `JsonSchema` derive expects the `std` prelude.
+#![cfg_attr(not(feature = "schema"), no_std)]
+
+#[cfg(feature = "user")]
+extern crate alloc;
+
+#[cfg(feature = "user")]
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+#[cfg(feature = "user")]
+use core::net::SocketAddr;
+use core::net::{IpAddr, Ipv4Addr};
+
+#[cfg(feature = "user")]
+mod delay_distribution;
+#[cfg(feature = "user")]
+mod migrations;
+#[cfg(feature = "proto")]
+pub mod proto;
 
 #[cfg(feature = "user")]
 use aya::Pod;
 #[cfg(feature = "user")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "user")]
+use serde_json::Value;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum Direction {
@@ -22,7 +41,8 @@ pub struct SocketKey {
     // rust adds implicit padding, but doesn't initialize it.
     // when used as a key in bpf world, the rust verifier complains that the value
     // is not initialized. Add the padding explicitly to work around this.
-    pub _pad: [u8; 7],
+    // Private, for the constructors to always zero it.
+    _pad: [u8; 7],
 }
 
 impl SocketKey {
@@ -47,11 +67,37 @@ impl SocketKey {
     }
 }
 
+impl SocketKey {
+    /// Returns the bytes of the key, the ones the BPF maps hash and compare, padding included.
+    pub fn as_bytes(&self) -> &[u8; core::mem::size_of::<SocketKey>()] {
+        // The padding is explicit, all the bytes of the key are initialized.
+        unsafe { &*(self as *const SocketKey as *const [u8; core::mem::size_of::<SocketKey>()]) }
+    }
+}
+
+/// Same semantics as the BPF maps: two keys are equal when all their bytes are, padding
+/// included, so that the keys seen as equal in userspace designate the same map entry. The
+/// padding being zeroed by the constructors, the keys of the same socket and direction are
+/// equal.
+impl PartialEq for SocketKey {
+    fn eq(&self, other: &SocketKey) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for SocketKey {}
+
+impl core::hash::Hash for SocketKey {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
 #[cfg(feature = "user")]
 unsafe impl Pod for SocketKey {}
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub struct FlowKey {
     pub sip: u32,
     pub dip: u32,
@@ -62,6 +108,10 @@ pub struct FlowKey {
 unsafe impl Pod for FlowKey {}
 
 impl FlowKey {
+    pub fn builder() -> FlowKeyBuilder {
+        FlowKeyBuilder::default()
+    }
+
     pub fn reverse(&self) -> FlowKey {
         FlowKey {
             sip: self.dip,
@@ -72,9 +122,135 @@ impl FlowKey {
     }
 }
 
+/// Builder of `FlowKey`. The addresses and ports of the key are in host byte order, the eBPF
+/// programs converting the ones of the packets and sockets, so they are set from the addresses
+/// as they are seen in userspace.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlowKeyBuilder {
+    key: FlowKey,
+}
+
+impl FlowKeyBuilder {
+    pub fn src_ip(mut self, ip: Ipv4Addr) -> Self {
+        self.key.sip = u32::from(ip);
+        self
+    }
+
+    pub fn dst_ip(mut self, ip: Ipv4Addr) -> Self {
+        self.key.dip = u32::from(ip);
+        self
+    }
+
+    pub fn src_port(mut self, port: u16) -> Self {
+        self.key.sport = port.into();
+        self
+    }
+
+    pub fn dst_port(mut self, port: u16) -> Self {
+        self.key.dport = port.into();
+        self
+    }
+
+    pub fn build(self) -> FlowKey {
+        self.key
+    }
+}
+
+/// Packet and byte counters of a flow, as observed by the traffic control programs, keyed by the
+/// addresses of the packets. The packets received by the client, seen by the ingress program, are
+/// accounted as received, the ones sent by the client, seen by the egress program, as
+/// transmitted. Every packet is seen once, by one of the programs.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlowStats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+}
+#[cfg(feature = "user")]
+unsafe impl Pod for FlowStats {}
+
+/// `PERF_OUTPUT` event: a packet delayed by a `Delay` conditioner, sampled by the traffic
+/// control programs at most `PERF_SAMPLE_INTERVAL_NS` apart on every CPU. The fields are ordered
+/// for the struct to have no implicit padding.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PerfSample {
+    /// `bpf_ktime_get_ns` when the packet was conditioned.
+    pub timestamp_ns: u64,
+    pub key: FlowKey,
+    pub cpu: u32,
+    /// Delay added to the packet, saturated to `u32::MAX`.
+    pub latency_ns: u32,
+}
+#[cfg(feature = "user")]
+unsafe impl Pod for PerfSample {}
+
+/// Version of the layout of the types shared with the eBPF program, embedded in the program as
+/// `TCP_TESTER_PROGRAM_VERSION`. Bump it whenever a map key or value type changes, so that a
+/// stale eBPF program is refused instead of reading the new types with the old layout.
+pub const PROGRAM_VERSION: u32 = 3;
+
+/// Levels of the messages logged by the eBPF programs, the values of `aya_log_common::Level`.
+/// The programs only log the messages up to the level set in their `LOG_LEVEL` global, the
+/// info level by default.
+pub const EBPF_LOG_LEVEL_ERROR: u32 = 1;
+pub const EBPF_LOG_LEVEL_WARN: u32 = 2;
+pub const EBPF_LOG_LEVEL_INFO: u32 = 3;
+pub const EBPF_LOG_LEVEL_DEBUG: u32 = 4;
+
+/// Number of buckets of the `LATENCY_HISTOGRAM` map. Bucket `i` counts the flows that lasted
+/// between `2^i` and `2^(i+1)` microseconds, the first and last buckets also count the shorter
+/// and longer flows respectively, covering 1us to 10s.
+pub const LATENCY_HISTOGRAM_BUCKETS: u32 = 24;
+
+/// Returns the `LATENCY_HISTOGRAM` bucket of a flow that lasted `duration_us` microseconds.
+#[inline(always)]
+pub fn latency_histogram_bucket(duration_us: u64) -> u32 {
+    let log2 = 63u32.saturating_sub(duration_us.leading_zeros());
+    if log2 < LATENCY_HISTOGRAM_BUCKETS {
+        log2
+    } else {
+        LATENCY_HISTOGRAM_BUCKETS - 1
+    }
+}
+
+/// Programs accounted in the `EXCEPTION_CNT` map, the slot of each program being its index.
+pub const EXCEPTION_CNT_PROGRAMS: [&str; 5] = [
+    "tcp_tester_tc_egress",
+    "tcp_tester_tc_ingress",
+    "tcp_tester_tc_passive_egress",
+    "tcp_tester_tc_passive_ingress",
+    "tcp_tester_sockops",
+];
+pub const EXCEPTION_CNT_TC_EGRESS: u32 = 0;
+pub const EXCEPTION_CNT_TC_INGRESS: u32 = 1;
+pub const EXCEPTION_CNT_TC_PASSIVE_EGRESS: u32 = 2;
+pub const EXCEPTION_CNT_TC_PASSIVE_INGRESS: u32 = 3;
+pub const EXCEPTION_CNT_SOCKOPS: u32 = 4;
+
+/// Errors accounted in the `FLOW_ERRORS` map by the traffic control programs, the code of each
+/// error being its index.
+pub const FLOW_ERROR_NAMES: [&str; 4] = [
+    "eth_header",
+    "ipv4_header",
+    "tcp_header",
+    "flow_stats_insert",
+];
+/// The Ethernet header of the packet could not be loaded.
+pub const FLOW_ERROR_ETH_HEADER: u32 = 0;
+/// The IPv4 header of the packet could not be loaded.
+pub const FLOW_ERROR_IPV4_HEADER: u32 = 1;
+/// The TCP header of the packet could not be loaded.
+pub const FLOW_ERROR_TCP_HEADER: u32 = 2;
+/// The flow could not be added to `FLOW_STATS`.
+pub const FLOW_ERROR_FLOW_STATS_INSERT: u32 = 3;
+
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct DelayConditioner {
     pub count: u32,
     pub offset: u64,
@@ -85,7 +261,8 @@ unsafe impl Pod for DelayConditioner {}
 
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ClassifyConditioner {
     pub classid: u32,
 }
@@ -94,7 +271,8 @@ unsafe impl Pod for ClassifyConditioner {}
 
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct DropPacketConditioner {
     pub count: u32,
     pub range: u32,
@@ -104,7 +282,8 @@ unsafe impl Pod for DropPacketConditioner {}
 
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Selector {
     pub data_offset_min: u32,
     pub data_offset_max: u32,
@@ -115,7 +294,8 @@ unsafe impl Pod for Selector {}
 
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Conditioner {
     Delay(DelayConditioner),
     DropPacket(DropPacketConditioner),
@@ -124,12 +304,519 @@ pub enum Conditioner {
 #[cfg(feature = "user")]
 unsafe impl Pod for Conditioner {}
 
+/// Faults injected in one direction of a flow, this is the value of the BPF maps.
 #[repr(C)]
 #[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
-#[derive(Copy, Clone, Debug)]
-pub struct FlowConfig {
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FaultProfile {
     pub selector: Selector,
     pub conditioner: Conditioner,
 }
 #[cfg(feature = "user")]
-unsafe impl Pod for FlowConfig {}
+unsafe impl Pod for FaultProfile {}
+
+/// Largest delay, offset and jitter included, a `Delay` conditioner may add to a packet, in
+/// nanoseconds.
+pub const MAX_DELAY_NS: u64 = 60_000_000_000;
+
+impl FaultProfile {
+    /// Profile injecting no fault: no packet of the flow is dropped.
+    pub const NONE: FaultProfile = FaultProfile {
+        selector: Selector {
+            data_offset_min: 0,
+            data_offset_max: 0,
+            flags: 0,
+        },
+        conditioner: Conditioner::DropPacket(DropPacketConditioner { count: 0, range: 0 }),
+    };
+
+    /// Returns whether the fields of the profile are within their bounds. The sockops program
+    /// checks the `SOCKET_CONFIG` entries with it before applying them, the userspace ones
+    /// being rejected earlier by `validate_fault_profile`.
+    pub fn is_plausible(&self) -> bool {
+        if self.selector.data_offset_min > self.selector.data_offset_max {
+            return false;
+        }
+        match self.conditioner {
+            Conditioner::Delay(delay) => delay
+                .offset
+                .checked_add(delay.jitter)
+                .is_some_and(|max_delay| max_delay <= MAX_DELAY_NS),
+            Conditioner::DropPacket(_) | Conditioner::Classify(_) => true,
+        }
+    }
+}
+
+/// `FLOW_CONFIG` map value: the fault profile applied to one direction of an established flow.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct FlowState {
+    /// First sequence number seen, the selector offsets are relative to it.
+    pub start_seq: u32,
+    pub config: FaultProfile,
+}
+#[cfg(feature = "user")]
+unsafe impl Pod for FlowState {}
+
+/// Alternates the fault profile of both directions of a flow between two paths every
+/// `flap_interval_ms`, simulating path failover. The flow starts on the primary path.
+#[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Copy, Clone, Debug)]
+pub struct PathFlapConfig {
+    pub primary: FaultProfile,
+    pub secondary: FaultProfile,
+    pub flap_interval_ms: u64,
+}
+
+/// HTTP/2 requests sent over a flow, instead of the raw data exchange: `num_streams` concurrent
+/// streams, started `request_interval_ms` apart, each one carrying a single request.
+#[cfg(feature = "user")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Http2Config {
+    pub num_streams: u16,
+    pub request_interval_ms: u64,
+    #[serde(default = "default_http2_path")]
+    pub path: String,
+    #[serde(default = "default_http2_method")]
+    pub method: String,
+    /// Header carrying the ID of the flow, `FlowResult::session_id`, in every request, e.g.
+    /// `X-NFM-Flow-ID`, for the server and proxy logs to be correlated with the flow results.
+    /// `--flow-id-header` when not set.
+    #[serde(default)]
+    pub flow_id_header_name: Option<String>,
+}
+
+#[cfg(feature = "user")]
+fn default_http2_path() -> String {
+    "/".into()
+}
+
+#[cfg(feature = "user")]
+fn default_http2_method() -> String {
+    "GET".into()
+}
+
+/// Distribution of the bytes of the data sent by the client, when sending data.
+#[cfg(feature = "user")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum PayloadDistribution {
+    /// Uniformly distributed random bytes.
+    #[default]
+    Uniform,
+    /// Printable ASCII characters.
+    Ascii,
+    /// Repetitive pattern whose bytes have the given entropy, in bits per byte between 0 and 8.
+    Compressible(f64),
+}
+
+/// Bytes of the data sent by the client overwritten with a known pattern, e.g. the magic bytes
+/// of a protocol header, for the server to see a malformed message.
+#[cfg(feature = "user")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CorruptionSpec {
+    /// Offset of the first byte overwritten, from the start of the data sent on the flow.
+    pub offset: u64,
+    /// Number of bytes overwritten.
+    pub length: u64,
+    /// Bytes written instead, repeated when shorter than `length`.
+    pub pattern: Vec<u8>,
+}
+
+/// Distribution of the delay of the `Delay` conditioners of a flow, sampled once per flow by the
+/// client when it sets the flow up.
+#[cfg(feature = "user")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum DelayDistribution {
+    /// The `offset` and `jitter` of the conditioners, as configured.
+    #[default]
+    Constant,
+    /// Uniformly distributed between `min_ms` and `max_ms`.
+    Uniform { min_ms: f64, max_ms: f64 },
+    /// Normally distributed, the negative samples being clamped to 0.
+    Normal { mean_ms: f64, stddev_ms: f64 },
+    /// Pareto distributed, heavy-tailed: at least `scale_ms`, the tail being heavier as `shape`
+    /// gets smaller.
+    Pareto { scale_ms: f64, shape: f64 },
+}
+
+/// Version of the `FlowConfig` schema, bumped along with every migration.
+#[cfg(feature = "user")]
+pub const FLOW_CONFIG_SCHEMA_VERSION: u32 = migrations::MIGRATIONS.len() as u32 + 1;
+
+#[cfg(feature = "user")]
+fn default_schema_version() -> u32 {
+    1
+}
+
+/// Time the client waits after every response of the server by default, see
+/// `FlowConfig::inter_packet_delay_ms`.
+#[cfg(feature = "user")]
+pub const DEFAULT_INTER_PACKET_DELAY_MS: u64 = 10;
+
+/// Highest `FlowConfig::pipeline_depth`. The pipelined messages, of 2 KiB, are all written
+/// before the responses echoed by the server are read, so they must fit in the socket buffers,
+/// 64 KiB at most being in flight, or both ends block writing.
+#[cfg(feature = "user")]
+pub const MAX_PIPELINE_DEPTH: u8 = 32;
+
+#[cfg(feature = "user")]
+fn default_inter_packet_delay_ms() -> u64 {
+    DEFAULT_INTER_PACKET_DELAY_MS
+}
+
+/// Faults injected in a flow: `egress` applies to the packets sent by the client, as they leave
+/// the middle-box towards the server, and `ingress` to the packets it receives, as they leave the
+/// middle-box towards the client.
+#[cfg_attr(feature = "user", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug)]
+pub struct FlowConfig {
+    /// Version of the schema the configuration was written for, the configurations without one
+    /// predate versioning and are version 1. Older configurations are migrated when parsed.
+    #[cfg(feature = "user")]
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    pub ingress: FaultProfile,
+    pub egress: FaultProfile,
+    /// Bytes sent by the client after which its receive buffer is shrunk, so the server sees a
+    /// zero receive window and stalls. Applied in userspace, by the client stream.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub zero_window_after_bytes: Option<u64>,
+    /// Duration of the zero window stall, after which the receive buffer is restored. The
+    /// stall lasts until the connection is closed when not set.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub zero_window_duration_ms: Option<u64>,
+    /// Time after the connection is established at which the client shuts down its write side,
+    /// while it keeps reading until the server closes its own. Applied in userspace, by the
+    /// client.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub half_close_write_after_ms: Option<u64>,
+    /// Time after the connection is established at which the client closes it, whatever the
+    /// exchange is at. `--max-flow-duration` applies instead when it is shorter. Applied in
+    /// userspace, by the client.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub max_flow_duration_ms: Option<u64>,
+    /// Time after the connection is established during which the flow is free of faults, for
+    /// the handshake and the setup of the application protocol to complete. The fault profiles
+    /// are applied afterwards. Applied in userspace, by updating the `FLOW_CONFIG` entries of
+    /// the established flow.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub failure_injection_start_delay_ms: Option<u64>,
+    /// Overrides `ingress` and `egress` with a flapping path. Applied in userspace, by updating
+    /// the `FLOW_CONFIG` entries of the established flow.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub path_flap: Option<PathFlapConfig>,
+    /// Source address the client socket is bound to, overriding `--bind-addr`. The kernel picks
+    /// it when not set.
+    #[cfg_attr(feature = "user", serde(default))]
+    pub bind_addr: Option<IpAddr>,
+    /// Sends HTTP/2 requests over the flow. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub http2: Option<Http2Config>,
+    /// OpenTelemetry context of the trace the flow belongs to, as its W3C propagation headers
+    /// (`traceparent`, and optionally `tracestate` and `baggage`). The client spans are started
+    /// under it, and it is injected in the HTTP/2 requests. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub opentelemetry_context: Option<BTreeMap<String, String>>,
+    /// Distribution of the bytes sent by the client. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub payload_distribution: PayloadDistribution,
+    /// pcap file the client records the data of the flow in, after the faults it injects
+    /// itself, e.g. `/tmp/flow-{port}.pcap`. `{port}` is replaced with the local port of the
+    /// flow, the flows sharing the configuration overwrite each other's capture otherwise.
+    /// Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub capture_path: Option<String>,
+    /// SOCKS5 proxy the client connects through, without authentication. The faults then apply
+    /// to the flow to the proxy, and the selector offsets count the bytes of the SOCKS5
+    /// handshake. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub socks5_proxy: Option<SocketAddr>,
+    /// Sets the connection up with TCP Fast Open (Linux 4.11 or later): the data of the first
+    /// write goes in the SYN once the client has a cookie for the server, which it gets from the
+    /// first connection. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub tcp_fast_open: bool,
+    /// `TCP_USER_TIMEOUT` of the client socket: how long the kernel retransmits unacknowledged
+    /// data before aborting the connection, e.g. to detect quickly the connections stalled by a
+    /// high packet loss. It also overrides the keepalive probe count to decide when a connection
+    /// whose probes go unanswered is aborted. The kernel default applies when not set. Applied in
+    /// userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub tcp_user_timeout_ms: Option<u32>,
+    /// Bandwidth the client sends the data of the flow at, at most, in kilobits per second. The
+    /// writes are paced with a leaky bucket holding up to a millisecond worth of data. Applied in
+    /// userspace, by the client stream.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub bandwidth_kbps: Option<u64>,
+    /// Distribution the delay of the `Delay` conditioners of the flow is drawn from, replacing
+    /// their `offset` and `jitter`. Drawn once per flow, not per packet: the delay is the same
+    /// for all the packets of the flow. Applied in userspace, by the client, when setting the `SOCKET_CONFIG` entries.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub delay_distribution: DelayDistribution,
+    /// Byte ranges of the data sent by the client overwritten with a pattern, in addition to
+    /// the faults of the profiles. Applied in userspace, by the client stream.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub targeted_corruption: Option<Vec<CorruptionSpec>>,
+    /// Time the client waits after every response of the server before sending its next
+    /// message, when exchanging random data. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default = "default_inter_packet_delay_ms")]
+    pub inter_packet_delay_ms: u64,
+    /// Spread of the wait between two messages: every wait is drawn uniformly within
+    /// `inter_packet_delay_ms` plus or minus `inter_packet_jitter_ms`, the waits below zero being
+    /// skipped, so the messages come in bursts. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub inter_packet_jitter_ms: u64,
+    /// Number of messages the client sends back to back before reading their responses, in their
+    /// order, when exchanging random data, as HTTP/1.0 keep-alive pipelining does. The ACKs of the
+    /// server then cover several messages at once. The client waits for every response before
+    /// sending the next message when not set. At most `MAX_PIPELINE_DEPTH`. Applied in
+    /// userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub pipeline_depth: Option<u8>,
+    /// Name of the flows using the configuration, for other flows to depend on them.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Name of the flows that must complete before the flows using the configuration start,
+    /// e.g. for protocols authenticating on a first connection. The flows start once a flow
+    /// with that name completed. Applied in userspace, by the client.
+    #[cfg(feature = "user")]
+    #[serde(default)]
+    pub depends_on: Option<String>,
+}
+
+impl FlowConfig {
+    /// Returns the egress and ingress fault profiles the flow starts with, none during the
+    /// `failure_injection_start_delay_ms`.
+    pub fn initial_profiles(&self) -> (FaultProfile, FaultProfile) {
+        match self.failure_injection_start_delay_ms {
+            Some(_) => (FaultProfile::NONE, FaultProfile::NONE),
+            None => self.injected_profiles(),
+        }
+    }
+
+    /// Returns the egress and ingress fault profiles the fault injection starts with.
+    pub fn injected_profiles(&self) -> (FaultProfile, FaultProfile) {
+        match self.path_flap {
+            Some(path_flap) => (path_flap.primary, path_flap.primary),
+            None => (self.egress, self.ingress),
+        }
+    }
+}
+
+#[cfg(feature = "schema")]
+impl FlowConfig {
+    /// Returns the JSON Schema of the flow configurations of the current schema version, for
+    /// editors to validate and complete the configuration files.
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(FlowConfig)
+    }
+}
+
+#[cfg(feature = "user")]
+impl FlowConfig {
+    /// Parses a flow configuration, migrating it to the current schema version. The legacy flat
+    /// format, where a single `selector` and `conditioner` applied to both directions, is thus
+    /// accepted as well.
+    pub fn from_json(json: &str) -> anyhow::Result<FlowConfig> {
+        FlowConfig::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Same as `from_json` for a configuration already parsed, e.g. from another format.
+    pub fn from_value(config: Value) -> anyhow::Result<FlowConfig> {
+        let version = match config.get("schema_version") {
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid schema_version: {}", version))?,
+            None => default_schema_version(),
+        };
+        if version > FLOW_CONFIG_SCHEMA_VERSION {
+            anyhow::bail!(
+                "Unsupported schema_version {}, the latest one is {}",
+                version,
+                FLOW_CONFIG_SCHEMA_VERSION
+            );
+        }
+        let config = migrate_flow_config(config, version);
+        Ok(serde_json::from_value(config)?)
+    }
+
+    /// Applies a partial configuration on top of `base`, following JSON Merge Patch (RFC 7396)
+    /// semantics: only the fields present in `overlay_json` override the base, and `null`
+    /// removes a field. Switching the conditioner type thus requires nulling out the old one,
+    /// e.g. `{"egress": {"conditioner": {"DropPacket": null, "Delay": {...}}}}`.
+    pub fn with_overlay(base: FlowConfig, overlay_json: &str) -> anyhow::Result<FlowConfig> {
+        let overlay: Value = serde_json::from_str(overlay_json)?;
+        let mut config = serde_json::to_value(base)?;
+        // The overlays are not versioned, they may use the flat format.
+        merge_patch(&mut config, &migrations::v1_to_v2(overlay));
+        Ok(serde_json::from_value(config)?)
+    }
+}
+
+/// Checks the constraints between the fields of a fault profile, named `name` in the errors.
+/// Returns a description of the violated constraint, none when the profile is consistent.
+#[cfg(feature = "user")]
+pub fn validate_fault_profile(name: &str, profile: &FaultProfile) -> Option<String> {
+    let selector = profile.selector;
+    if selector.data_offset_min > selector.data_offset_max {
+        return Some(format!(
+            "{name}.selector.data_offset_min ({}) must not be greater than data_offset_max ({})",
+            selector.data_offset_min, selector.data_offset_max
+        ));
+    }
+    match profile.conditioner {
+        Conditioner::Delay(delay) if !profile.is_plausible() => Some(format!(
+            "{name}.conditioner.Delay offset ({}) and jitter ({}) must add up to at most {}ns",
+            delay.offset, delay.jitter, MAX_DELAY_NS
+        )),
+        _ => None,
+    }
+}
+
+/// Checks the constraints between the fields of the configuration, which deserialization can't
+/// enforce. Returns a description of every violated constraint, none when the configuration is
+/// consistent.
+#[cfg(feature = "user")]
+pub fn validate_cross_fields(config: &FlowConfig) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let mut profiles = Vec::from([("ingress", config.ingress), ("egress", config.egress)]);
+    if let Some(path_flap) = config.path_flap {
+        profiles.push(("path_flap.primary", path_flap.primary));
+        profiles.push(("path_flap.secondary", path_flap.secondary));
+        if path_flap.flap_interval_ms == 0 {
+            errors.push("path_flap.flap_interval_ms must be greater than 0".into());
+        }
+    }
+    for (name, profile) in profiles {
+        errors.extend(validate_fault_profile(name, &profile));
+    }
+
+    match config.pipeline_depth {
+        Some(0) => errors.push("pipeline_depth must be greater than 0".into()),
+        Some(depth) if depth > MAX_PIPELINE_DEPTH => errors.push(format!(
+            "pipeline_depth ({depth}) must be at most {MAX_PIPELINE_DEPTH}"
+        )),
+        _ => {}
+    }
+    if config.zero_window_duration_ms.is_some() && config.zero_window_after_bytes.is_none() {
+        errors.push("zero_window_duration_ms requires zero_window_after_bytes".into());
+    }
+    // The client sockets are IPv4 ones.
+    if let Some(bind_addr @ IpAddr::V6(_)) = config.bind_addr {
+        errors.push(format!("bind_addr ({bind_addr}) must be an IPv4 address"));
+    }
+    if let PayloadDistribution::Compressible(entropy) = config.payload_distribution {
+        if !(0.0..=8.0).contains(&entropy) {
+            errors.push(format!(
+                "payload_distribution.Compressible ({entropy}) must be between 0 and 8 bits per byte"
+            ));
+        }
+    }
+    match config.delay_distribution {
+        DelayDistribution::Uniform { min_ms, max_ms } if !(0.0 <= min_ms && min_ms <= max_ms) => {
+            errors.push(format!(
+                "delay_distribution.Uniform ({min_ms}, {max_ms}) must have 0 <= min_ms <= max_ms"
+            ));
+        }
+        DelayDistribution::Normal { mean_ms, stddev_ms }
+            if !(mean_ms >= 0.0 && stddev_ms >= 0.0) =>
+        {
+            errors.push(format!(
+                "delay_distribution.Normal ({mean_ms}, {stddev_ms}) must have a positive mean_ms \
+                 and stddev_ms"
+            ));
+        }
+        DelayDistribution::Pareto { scale_ms, shape } if !(scale_ms > 0.0 && shape > 0.0) => {
+            errors.push(format!(
+                "delay_distribution.Pareto ({scale_ms}, {shape}) must have scale_ms and shape \
+                 greater than 0"
+            ));
+        }
+        _ => {}
+    }
+    for (index, spec) in config.targeted_corruption.iter().flatten().enumerate() {
+        if spec.length == 0 || spec.pattern.is_empty() {
+            errors.push(format!(
+                "targeted_corruption[{index}] must have a length and a pattern"
+            ));
+        }
+        if spec.offset.checked_add(spec.length).is_none() {
+            errors.push(format!(
+                "targeted_corruption[{index}] ({}, {}) ends past the largest offset",
+                spec.offset, spec.length
+            ));
+        }
+    }
+    if let Some(http2) = &config.http2 {
+        if http2.num_streams == 0 {
+            errors.push("http2.num_streams must be greater than 0".into());
+        }
+    }
+    if let (Some(name), Some(depends_on)) = (&config.name, &config.depends_on) {
+        if name == depends_on {
+            errors.push(format!(
+                "depends_on ({depends_on}) must not be the flow name"
+            ));
+        }
+    }
+
+    errors
+}
+
+/// Brings a configuration written for the schema `from_version` to the current one, by applying
+/// the migrations from that version in order. The configuration is not checked to deserialize.
+#[cfg(feature = "user")]
+pub fn migrate_flow_config(json: Value, from_version: u32) -> Value {
+    let first = from_version.saturating_sub(1) as usize;
+    let mut json = migrations::MIGRATIONS
+        .iter()
+        .skip(first)
+        .fold(json, |json, migration| migration(json));
+    if let Value::Object(config) = &mut json {
+        config.insert("schema_version".into(), FLOW_CONFIG_SCHEMA_VERSION.into());
+    }
+    json
+}
+
+#[cfg(feature = "user")]
+fn merge_patch(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(serde_json::Map::new());
+            }
+            let target = target.as_object_mut().unwrap();
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(key);
+                } else {
+                    merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+                }
+            }
+        }
+        _ => *target = patch.clone(),
+    }
+}
@@ -0,0 +1,31 @@
+//! Migrations of the flow configuration files, each one bringing a configuration from a schema
+//! version to the next. `v<N>_to_v<N+1>` is added along with every change of `FlowConfig` that
+//! an older configuration would not deserialize into.
+
+use serde_json::Value;
+
+/// Migrations indexed by the version they start from minus one.
+pub(crate) const MIGRATIONS: &[fn(Value) -> Value] = &[v1_to_v2];
+
+/// Converts a configuration in the legacy flat format, where a single `selector` and
+/// `conditioner` applied to both directions, into the per-direction one, by applying the same
+/// fault profile to both directions. The fields that are not part of the fault profile are kept
+/// as they are, and a configuration already in the per-direction format is left unchanged.
+pub(crate) fn v1_to_v2(config: Value) -> Value {
+    match config {
+        Value::Object(mut config)
+            if config.contains_key("selector") || config.contains_key("conditioner") =>
+        {
+            let mut profile = serde_json::Map::new();
+            for key in ["selector", "conditioner"] {
+                if let Some(value) = config.remove(key) {
+                    profile.insert(key.into(), value);
+                }
+            }
+            config.insert("ingress".into(), Value::Object(profile.clone()));
+            config.insert("egress".into(), Value::Object(profile));
+            Value::Object(config)
+        }
+        config => config,
+    }
+}
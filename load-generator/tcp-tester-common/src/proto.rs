@@ -0,0 +1,288 @@
+//! Protobuf equivalent of `FlowConfig`, generated from `proto/flow_config.proto`, for the gRPC
+//! control plane.
+
+use alloc::{format, string::ToString};
+use anyhow::{anyhow, Context};
+
+use crate::Conditioner;
+
+include!(concat!(env!("OUT_DIR"), "/flow_config.rs"));
+
+impl crate::FlowConfig {
+    pub fn to_proto(&self) -> FlowConfig {
+        FlowConfig {
+            ingress: Some(fault_profile_to_proto(&self.ingress)),
+            egress: Some(fault_profile_to_proto(&self.egress)),
+            zero_window_after_bytes: self.zero_window_after_bytes,
+            zero_window_duration_ms: self.zero_window_duration_ms,
+            half_close_write_after_ms: self.half_close_write_after_ms,
+            max_flow_duration_ms: self.max_flow_duration_ms,
+            failure_injection_start_delay_ms: self.failure_injection_start_delay_ms,
+            path_flap: self.path_flap.map(|path_flap| PathFlapConfig {
+                primary: Some(fault_profile_to_proto(&path_flap.primary)),
+                secondary: Some(fault_profile_to_proto(&path_flap.secondary)),
+                flap_interval_ms: path_flap.flap_interval_ms,
+            }),
+            bind_addr: self.bind_addr.map(|bind_addr| bind_addr.to_string()),
+            http2: self.http2.as_ref().map(|http2| Http2Config {
+                num_streams: http2.num_streams.into(),
+                request_interval_ms: http2.request_interval_ms,
+                path: Some(http2.path.clone()),
+                method: Some(http2.method.clone()),
+                flow_id_header_name: http2.flow_id_header_name.clone(),
+            }),
+            opentelemetry_context: self.opentelemetry_context.clone().unwrap_or_default(),
+            payload_distribution: Some(payload_distribution_to_proto(self.payload_distribution)),
+            capture_path: self.capture_path.clone(),
+            socks5_proxy: self.socks5_proxy.map(|proxy| proxy.to_string()),
+            tcp_fast_open: self.tcp_fast_open,
+            tcp_user_timeout_ms: self.tcp_user_timeout_ms,
+            bandwidth_kbps: self.bandwidth_kbps,
+            delay_distribution: Some(delay_distribution_to_proto(self.delay_distribution)),
+            targeted_corruption: self
+                .targeted_corruption
+                .iter()
+                .flatten()
+                .map(|spec| CorruptionSpec {
+                    offset: spec.offset,
+                    length: spec.length,
+                    pattern: spec.pattern.clone(),
+                })
+                .collect(),
+            inter_packet_delay_ms: Some(self.inter_packet_delay_ms),
+            inter_packet_jitter_ms: self.inter_packet_jitter_ms,
+            pipeline_depth: self.pipeline_depth.map(u32::from),
+            name: self.name.clone(),
+            depends_on: self.depends_on.clone(),
+        }
+    }
+
+    /// Converts a protobuf flow configuration, failing when a required field is missing or a
+    /// field is out of range. The constraints between the fields are not checked.
+    pub fn from_proto(proto: FlowConfig) -> anyhow::Result<crate::FlowConfig> {
+        let path_flap = proto
+            .path_flap
+            .map(|path_flap| -> anyhow::Result<_> {
+                Ok(crate::PathFlapConfig {
+                    primary: fault_profile_from_proto("path_flap.primary", path_flap.primary)?,
+                    secondary: fault_profile_from_proto(
+                        "path_flap.secondary",
+                        path_flap.secondary,
+                    )?,
+                    flap_interval_ms: path_flap.flap_interval_ms,
+                })
+            })
+            .transpose()?;
+        let bind_addr = proto
+            .bind_addr
+            .map(|bind_addr| {
+                bind_addr
+                    .parse()
+                    .with_context(|| format!("Invalid bind_addr: {}", bind_addr))
+            })
+            .transpose()?;
+        let socks5_proxy = proto
+            .socks5_proxy
+            .map(|proxy| {
+                proxy
+                    .parse()
+                    .with_context(|| format!("Invalid socks5_proxy: {}", proxy))
+            })
+            .transpose()?;
+        let http2 = proto
+            .http2
+            .map(|http2| -> anyhow::Result<_> {
+                Ok(crate::Http2Config {
+                    num_streams: http2.num_streams.try_into().with_context(|| {
+                        format!("http2.num_streams ({}) is too large", http2.num_streams)
+                    })?,
+                    request_interval_ms: http2.request_interval_ms,
+                    path: http2.path.unwrap_or_else(crate::default_http2_path),
+                    method: http2.method.unwrap_or_else(crate::default_http2_method),
+                    flow_id_header_name: http2.flow_id_header_name,
+                })
+            })
+            .transpose()?;
+        let pipeline_depth = proto
+            .pipeline_depth
+            .map(|depth| {
+                u8::try_from(depth)
+                    .with_context(|| format!("pipeline_depth ({}) is too large", depth))
+            })
+            .transpose()?;
+        let payload_distribution = match proto.payload_distribution {
+            Some(distribution) => match distribution.kind() {
+                payload_distribution::Kind::Uniform => crate::PayloadDistribution::Uniform,
+                payload_distribution::Kind::Ascii => crate::PayloadDistribution::Ascii,
+                payload_distribution::Kind::Compressible => {
+                    crate::PayloadDistribution::Compressible(distribution.entropy)
+                }
+            },
+            None => crate::PayloadDistribution::default(),
+        };
+
+        Ok(crate::FlowConfig {
+            schema_version: crate::FLOW_CONFIG_SCHEMA_VERSION,
+            ingress: fault_profile_from_proto("ingress", proto.ingress)?,
+            egress: fault_profile_from_proto("egress", proto.egress)?,
+            zero_window_after_bytes: proto.zero_window_after_bytes,
+            zero_window_duration_ms: proto.zero_window_duration_ms,
+            half_close_write_after_ms: proto.half_close_write_after_ms,
+            max_flow_duration_ms: proto.max_flow_duration_ms,
+            failure_injection_start_delay_ms: proto.failure_injection_start_delay_ms,
+            path_flap,
+            bind_addr,
+            http2,
+            opentelemetry_context: (!proto.opentelemetry_context.is_empty())
+                .then_some(proto.opentelemetry_context),
+            payload_distribution,
+            capture_path: proto.capture_path,
+            socks5_proxy,
+            tcp_fast_open: proto.tcp_fast_open,
+            tcp_user_timeout_ms: proto.tcp_user_timeout_ms,
+            bandwidth_kbps: proto.bandwidth_kbps,
+            delay_distribution: proto
+                .delay_distribution
+                .map(delay_distribution_from_proto)
+                .unwrap_or_default(),
+            targeted_corruption: (!proto.targeted_corruption.is_empty()).then(|| {
+                proto
+                    .targeted_corruption
+                    .into_iter()
+                    .map(|spec| crate::CorruptionSpec {
+                        offset: spec.offset,
+                        length: spec.length,
+                        pattern: spec.pattern,
+                    })
+                    .collect()
+            }),
+            inter_packet_delay_ms: proto
+                .inter_packet_delay_ms
+                .unwrap_or(crate::DEFAULT_INTER_PACKET_DELAY_MS),
+            inter_packet_jitter_ms: proto.inter_packet_jitter_ms,
+            pipeline_depth,
+            name: proto.name,
+            depends_on: proto.depends_on,
+        })
+    }
+}
+
+fn fault_profile_to_proto(profile: &crate::FaultProfile) -> FaultProfile {
+    let selector = profile.selector;
+    let conditioner = match profile.conditioner {
+        Conditioner::Delay(delay) => fault_profile::Conditioner::Delay(DelayConditioner {
+            count: delay.count,
+            offset: delay.offset,
+            jitter: delay.jitter,
+        }),
+        Conditioner::DropPacket(drop) => {
+            fault_profile::Conditioner::DropPacket(DropPacketConditioner {
+                count: drop.count,
+                range: drop.range,
+            })
+        }
+        Conditioner::Classify(classify) => {
+            fault_profile::Conditioner::Classify(ClassifyConditioner {
+                classid: classify.classid,
+            })
+        }
+    };
+    FaultProfile {
+        selector: Some(Selector {
+            data_offset_min: selector.data_offset_min,
+            data_offset_max: selector.data_offset_max,
+            flags: selector.flags,
+        }),
+        conditioner: Some(conditioner),
+    }
+}
+
+/// Converts the fault profile `name`, which is required.
+fn fault_profile_from_proto(
+    name: &str,
+    profile: Option<FaultProfile>,
+) -> anyhow::Result<crate::FaultProfile> {
+    let profile = profile.ok_or_else(|| anyhow!("{} is required", name))?;
+    let selector = profile.selector.unwrap_or_default();
+    let conditioner = match profile.conditioner {
+        Some(fault_profile::Conditioner::Delay(delay)) => {
+            Conditioner::Delay(crate::DelayConditioner {
+                count: delay.count,
+                offset: delay.offset,
+                jitter: delay.jitter,
+            })
+        }
+        Some(fault_profile::Conditioner::DropPacket(drop)) => {
+            Conditioner::DropPacket(crate::DropPacketConditioner {
+                count: drop.count,
+                range: drop.range,
+            })
+        }
+        Some(fault_profile::Conditioner::Classify(classify)) => {
+            Conditioner::Classify(crate::ClassifyConditioner {
+                classid: classify.classid,
+            })
+        }
+        None => return Err(anyhow!("{}.conditioner is required", name)),
+    };
+    Ok(crate::FaultProfile {
+        selector: crate::Selector {
+            data_offset_min: selector.data_offset_min,
+            data_offset_max: selector.data_offset_max,
+            flags: selector.flags,
+        },
+        conditioner,
+    })
+}
+
+fn delay_distribution_to_proto(distribution: crate::DelayDistribution) -> DelayDistribution {
+    let mut proto = DelayDistribution::default();
+    match distribution {
+        crate::DelayDistribution::Constant => {}
+        crate::DelayDistribution::Uniform { min_ms, max_ms } => {
+            proto.set_kind(delay_distribution::Kind::Uniform);
+            (proto.min_ms, proto.max_ms) = (min_ms, max_ms);
+        }
+        crate::DelayDistribution::Normal { mean_ms, stddev_ms } => {
+            proto.set_kind(delay_distribution::Kind::Normal);
+            (proto.mean_ms, proto.stddev_ms) = (mean_ms, stddev_ms);
+        }
+        crate::DelayDistribution::Pareto { scale_ms, shape } => {
+            proto.set_kind(delay_distribution::Kind::Pareto);
+            (proto.scale_ms, proto.shape) = (scale_ms, shape);
+        }
+    }
+    proto
+}
+
+fn delay_distribution_from_proto(proto: DelayDistribution) -> crate::DelayDistribution {
+    match proto.kind() {
+        delay_distribution::Kind::Constant => crate::DelayDistribution::Constant,
+        delay_distribution::Kind::Uniform => crate::DelayDistribution::Uniform {
+            min_ms: proto.min_ms,
+            max_ms: proto.max_ms,
+        },
+        delay_distribution::Kind::Normal => crate::DelayDistribution::Normal {
+            mean_ms: proto.mean_ms,
+            stddev_ms: proto.stddev_ms,
+        },
+        delay_distribution::Kind::Pareto => crate::DelayDistribution::Pareto {
+            scale_ms: proto.scale_ms,
+            shape: proto.shape,
+        },
+    }
+}
+
+fn payload_distribution_to_proto(distribution: crate::PayloadDistribution) -> PayloadDistribution {
+    let (kind, entropy) = match distribution {
+        crate::PayloadDistribution::Uniform => (payload_distribution::Kind::Uniform, 0.0),
+        crate::PayloadDistribution::Ascii => (payload_distribution::Kind::Ascii, 0.0),
+        crate::PayloadDistribution::Compressible(entropy) => {
+            (payload_distribution::Kind::Compressible, entropy)
+        }
+    };
+    PayloadDistribution {
+        kind: kind.into(),
+        entropy,
+    }
+}
@@ -7,6 +7,16 @@ use std::process::Command;
 use which::which;
 
 fn main() {
+    // The gRPC bindings of the control plane, generated with protoc. The flow configuration
+    // messages are the ones of tcp-tester-common.
+    tonic_build::configure()
+        .extern_path(".flow_config", "::tcp_tester_common::proto")
+        .compile_protos(
+            &["proto/flow_controller.proto"],
+            &["proto", "../tcp-tester-common/proto"],
+        )
+        .expect("Failed to compile the control plane protobuf schema");
+
     // We skip the eBPF build if running under tarpaulin, which measures code test coverage.
     // tarpaulin is incompatible with `no_std` and BPF's different target architecture.
     let should_build_ebpf = std::env::var("CARGO_CFG_TARPAULIN").is_err();
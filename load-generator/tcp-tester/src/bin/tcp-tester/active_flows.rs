@@ -0,0 +1,132 @@
+use aya::maps::{MapData, PerCpuArray};
+use log::{debug, info, warn};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Duration;
+
+use crate::client::FlowEvent;
+
+/// Relative deviation from the expected number of active connections beyond which a warning is
+/// logged.
+const MAX_DEVIATION: f64 = 0.2;
+
+/// Number of client connections expected to be established at any time, from the connection
+/// rate and the average duration of the completed flows (Little's law).
+struct ExpectedActiveFlows {
+    connections_per_sec: f64,
+    flows_completed: u64,
+    duration_ms: u64,
+}
+
+impl ExpectedActiveFlows {
+    fn new(connections_per_sec: f64) -> Self {
+        ExpectedActiveFlows {
+            connections_per_sec,
+            flows_completed: 0,
+            duration_ms: 0,
+        }
+    }
+
+    fn push(&mut self, duration_ms: u64) {
+        self.flows_completed += 1;
+        self.duration_ms += duration_ms;
+    }
+
+    /// Returns the expected number of active connections, None until a flow completed.
+    fn expected(&self) -> Option<f64> {
+        (self.flows_completed > 0).then(|| {
+            let avg_duration_secs = self.duration_ms as f64 / self.flows_completed as f64 / 1000.0;
+            self.connections_per_sec * avg_duration_secs
+        })
+    }
+
+    /// Returns whether `active` deviates from the expected number by more than `MAX_DEVIATION`.
+    fn deviates(&self, active: i64) -> bool {
+        self.expected()
+            .is_some_and(|expected| (active as f64 - expected).abs() > expected * MAX_DEVIATION)
+    }
+}
+
+/// Reads the `ACTIVE_FLOWS_GAUGE` map, merging the values of all the CPUs.
+fn read_active_flows(gauge: &PerCpuArray<MapData, i64>) -> Option<i64> {
+    match gauge.get(&0, 0) {
+        Ok(values) => Some(values.iter().sum()),
+        Err(error) => {
+            debug!("Failed to read the active flows gauge: {}", error);
+            None
+        }
+    }
+}
+
+/// Periodically logs the number of established client connections counted by the sockops
+/// program, as the `tcp_active_connections_total` gauge, and warns when it deviates from the
+/// expected one by more than `MAX_DEVIATION`.
+///
+/// # Arguments
+/// * `gauge` - `ACTIVE_FLOWS_GAUGE` map.
+/// * `flow_events` - lifecycle events of the flows, for their average duration.
+/// * `connections_per_sec` - connection rate of all the clients, None when it varies, e.g. in a
+///   scenario, the number of connections is then not checked.
+/// * `interval` - time between two consecutive reports.
+pub async fn log_active_flows_periodically(
+    gauge: PerCpuArray<MapData, i64>,
+    mut flow_events: broadcast::Receiver<FlowEvent>,
+    connections_per_sec: Option<f64>,
+    interval: Duration,
+) {
+    let mut expected = connections_per_sec.map(ExpectedActiveFlows::new);
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let Some(tcp_active_connections_total) = read_active_flows(&gauge) else {
+                    continue;
+                };
+                info!(tcp_active_connections_total; "Active connections");
+                let Some(expected) = &expected else {
+                    continue;
+                };
+                if expected.deviates(tcp_active_connections_total) {
+                    warn!(
+                        "{} active connections, {:.1} expected from the connection rate and \
+                         the average flow duration",
+                        tcp_active_connections_total,
+                        expected.expected().unwrap_or_default()
+                    );
+                }
+            }
+            event = flow_events.recv() => match event {
+                Ok(FlowEvent::Completed(flow)) => {
+                    if let Some(expected) = &mut expected {
+                        expected.push(flow.duration_ms);
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Active connections skipped {} flow events", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_active_flows() {
+        let mut expected = ExpectedActiveFlows::new(10.0);
+        assert_eq!(expected.expected(), None);
+        assert!(!expected.deviates(1000));
+
+        // 10 connections per second lasting 2 seconds on average.
+        expected.push(1000);
+        expected.push(3000);
+        assert_eq!(expected.expected(), Some(20.0));
+        assert!(!expected.deviates(17));
+        assert!(!expected.deviates(24));
+        assert!(expected.deviates(15));
+        assert!(expected.deviates(25));
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::Context;
+use log::warn;
+use nix::fcntl::{flock, FlockArg};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tcp_tester_common::{Direction, FaultProfile, SocketKey};
+use uuid::Uuid;
+
+use crate::client::{FlowConfigChange, FlowConfigHistory};
+
+/// Modification of a `SOCKET_CONFIG` entry.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    /// New entry.
+    Insert,
+    /// Entry replaced by an insertion.
+    Update,
+    Remove,
+}
+
+/// Line of the audit log.
+#[derive(Debug, Serialize)]
+struct AuditEvent<'a> {
+    timestamp_unix_ms: u128,
+    operation: AuditOperation,
+    /// `<cookie>.<direction>`, e.g. `4107.egress`.
+    key: String,
+    old: Option<FaultProfile>,
+    new: Option<FaultProfile>,
+    /// Function which modified the entry.
+    caller: &'a str,
+}
+
+/// Line of the audit log reporting the fault profile updates of a completed flow.
+#[derive(Debug, Serialize)]
+struct FlowConfigTimeline {
+    timestamp_unix_ms: u128,
+    session_id: Uuid,
+    initial_egress: FaultProfile,
+    initial_ingress: FaultProfile,
+    changes: Vec<FlowConfigChange>,
+}
+
+fn unix_time_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn format_key(key: &SocketKey) -> String {
+    let direction = if key.direction == Direction::INGRESS {
+        "ingress"
+    } else {
+        "egress"
+    };
+    format!("{}.{}", key.cookie, direction)
+}
+
+/// Audit trail of the modifications of the `SOCKET_CONFIG` map, written to `--audit-log` as one
+/// JSON line per modification. The file is opened in append mode and locked for every line, so
+/// that the tcp-tester instances sharing the maps pinned with `--pin-maps` can share the log too.
+#[derive(Debug)]
+pub struct AuditLogger {
+    file: Mutex<File>,
+}
+
+impl AuditLogger {
+    pub fn open(path: &str) -> anyhow::Result<AuditLogger> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path))?;
+        Ok(AuditLogger {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Records a modification of the entry of `key`, from `old` to `new`.
+    pub fn log(
+        &self,
+        operation: AuditOperation,
+        key: &SocketKey,
+        old: Option<FaultProfile>,
+        new: Option<FaultProfile>,
+        caller: &str,
+    ) {
+        let event = AuditEvent {
+            timestamp_unix_ms: unix_time_ms(),
+            operation,
+            key: format_key(key),
+            old,
+            new,
+            caller,
+        };
+        if let Err(error) = self.write(&event) {
+            warn!("Failed to write audit log event {:?}: {:?}", event, error);
+        }
+    }
+
+    /// Records the timeline of the fault profile updates of the completed flow of `session_id`.
+    pub fn log_flow_config_history(&self, session_id: Uuid, history: &FlowConfigHistory) {
+        let [initial_egress, initial_ingress] = history.initial();
+        let timeline = FlowConfigTimeline {
+            timestamp_unix_ms: unix_time_ms(),
+            session_id,
+            initial_egress,
+            initial_ingress,
+            changes: history.timeline(),
+        };
+        if let Err(error) = self.write(&timeline) {
+            warn!(
+                "Failed to write the flow config timeline {:?}: {:?}",
+                timeline, error
+            );
+        }
+    }
+
+    fn write(&self, event: &impl Serialize) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        flock(file.as_raw_fd(), FlockArg::LockExclusive).context("Failed to lock audit log")?;
+        // A single write per line, the appends of the other processes don't interleave with it.
+        let written = file.write_all(&line);
+        flock(file.as_raw_fd(), FlockArg::Unlock).context("Failed to unlock audit log")?;
+        Ok(written?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_appends_json_lines() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let logger = AuditLogger::open(path.to_str().unwrap()).unwrap();
+        let key = SocketKey::new(4107, Direction::EGRESS);
+        logger.log(
+            AuditOperation::Insert,
+            &key,
+            None,
+            Some(FaultProfile::NONE),
+            "register_socket",
+        );
+        logger.log(
+            AuditOperation::Remove,
+            &key,
+            Some(FaultProfile::NONE),
+            None,
+            "expire_socket_config",
+        );
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["operation"], "insert");
+        assert_eq!(events[0]["key"], "4107.egress");
+        assert!(events[0]["old"].is_null());
+        assert_eq!(events[1]["operation"], "remove");
+        assert_eq!(events[1]["caller"], "expire_socket_config");
+        assert!(events[1]["new"].is_null());
+    }
+}
@@ -1,6 +1,56 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::Serialize;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use tcp_tester::conditioned_tcp_stream::SockTimestamping;
+use tcp_tester_common::{
+    EBPF_LOG_LEVEL_DEBUG, EBPF_LOG_LEVEL_ERROR, EBPF_LOG_LEVEL_INFO, EBPF_LOG_LEVEL_WARN,
+};
+
+use crate::ebpf_loader::PINNED_MAPS_PATH;
+use crate::flow_limiter::MAX_CONNECTION_RATE;
+use crate::namespaces::DEFAULT_NAMESPACE_PREFIX;
+
+/// Parses a `tc` handle, `major:minor` in hex, into `major << 16 | minor`.
+fn parse_tc_handle(handle: &str) -> Result<u32, String> {
+    let (major, minor) = handle
+        .split_once(':')
+        .ok_or_else(|| format!("{handle} is not major:minor"))?;
+    let parse = |part: &str| {
+        u16::from_str_radix(part, 16).map_err(|error| format!("Invalid {handle}: {error}"))
+    };
+    Ok(u32::from(parse(major)?) << 16 | u32::from(parse(minor)?))
+}
+
+/// Checks that `name` is a valid HTTP header name.
+fn parse_header_name(name: &str) -> Result<String, String> {
+    http::HeaderName::from_bytes(name.as_bytes())
+        .map(|_| name.to_string())
+        .map_err(|error| format!("Invalid header name {name}: {error}"))
+}
+
+/// Parses a list of CPUs, comma separated numbers or ranges, e.g. `0-3,8`.
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let parse = |cpu: &str| {
+        cpu.trim()
+            .parse::<usize>()
+            .map_err(|error| format!("Invalid CPU {cpu}: {error}"))
+    };
+    let mut cpus = Vec::new();
+    for part in list.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("Invalid CPU range {part}"));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(part)?),
+        }
+    }
+    Ok(cpus)
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
 pub enum OnOff {
@@ -17,19 +67,180 @@ impl fmt::Display for OnOff {
     }
 }
 
+/// How the threads of tcp-tester are pinned to the CPUs of `--affinity`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+pub enum AffinityMode {
+    /// One worker thread per CPU, each one pinned to the next CPU of the list. The flows are
+    /// spread across the worker threads, and may move from one to another when idle workers steal
+    /// them.
+    RoundRobin,
+    /// One single-threaded runtime per CPU, pinned to it, each new flow being spawned on the next
+    /// one. A flow stays on its CPU for its entire lifetime.
+    Sticky,
+}
+
+/// Format of the flow configuration files.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+pub enum ConfigFormat {
+    Json,
+    Yaml,
+    #[cfg(feature = "toml")]
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Returns the format of a file from its extension: YAML for `.yaml` and `.yml`, TOML for
+    /// `.toml` with the `toml` feature, JSON otherwise.
+    pub fn from_path(path: &str) -> ConfigFormat {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            #[cfg(feature = "toml")]
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+/// Most verbose level of the messages logged by the eBPF programs.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+pub enum EbpfLogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl EbpfLogLevel {
+    /// Value of the `LOG_LEVEL` global of the eBPF programs.
+    pub fn value(self) -> u32 {
+        match self {
+            EbpfLogLevel::Debug => EBPF_LOG_LEVEL_DEBUG,
+            EbpfLogLevel::Info => EBPF_LOG_LEVEL_INFO,
+            EbpfLogLevel::Warn => EBPF_LOG_LEVEL_WARN,
+            EbpfLogLevel::Error => EBPF_LOG_LEVEL_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for EbpfLogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EbpfLogLevel::Debug => write!(f, "debug"),
+            EbpfLogLevel::Info => write!(f, "info"),
+            EbpfLogLevel::Warn => write!(f, "warn"),
+            EbpfLogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Subcommand)]
+pub enum Command {
+    /// Generates flow configuration files for a parameter sweep, one per combination of the
+    /// parameter values, instead of running the tests.
+    #[command(name = "config_generator")]
+    ConfigGenerator {
+        /// Path of the configuration the parameters are set on.
+        #[arg(long)]
+        base_config: String,
+
+        /// Dotted path of a parameter in the configuration, e.g.
+        /// `egress.conditioner.DropPacket.count`. Repeat for a multi-dimensional sweep, each
+        /// parameter taking the values of the `--range` at the same position.
+        #[arg(long = "param", required = true)]
+        params: Vec<String>,
+
+        /// Values of a parameter, as `start:step:end` with the end included.
+        #[arg(long = "range", required = true)]
+        ranges: Vec<String>,
+
+        /// Directory where the configuration files are written, their paths being printed to
+        /// stdout.
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+    /// Prints the flow stats and duration histogram of the eBPF maps as JSON, instead of running
+    /// the tests.
+    Report {
+        /// Directory of the maps pinned by a tcp-tester running with `--pin-maps`. Reading them
+        /// only requires read access to the pins, the eBPF programs are attached, which
+        /// requires root, when they are unavailable.
+        #[arg(long, default_value = PINNED_MAPS_PATH)]
+        map_pin_path: String,
+        /// Also writes the duration histogram to this file in the HdrHistogram V2 encoding, for
+        /// the HdrHistogram tooling. The flows are recorded at the upper bound of their bucket.
+        #[arg(long)]
+        histogram_output: Option<String>,
+    },
+    /// Attaches the eBPF programs, then prints a table of the eBPF programs loaded in the
+    /// kernel, instead of running the tests.
+    ListPrograms,
+    /// Compares the `FLOW_STATS` map with the TCP connections of the client namespace in
+    /// `/proc/net/tcp`, and prints the number of connections found in both, only in the map
+    /// and only in procfs as JSON, instead of running the tests. The map outlives the flows, the
+    /// connections only in procfs are the ones the traffic control programs missed.
+    Reconcile {
+        /// Directory of the maps pinned by a tcp-tester running with `--pin-maps`.
+        #[arg(long, default_value = PINNED_MAPS_PATH)]
+        map_pin_path: String,
+    },
+    /// Writes the JSON Schema of the flow configuration files, for editors to validate and
+    /// complete them, e.g. through the YAML and JSON language servers, instead of running the
+    /// tests.
+    #[cfg(feature = "schema")]
+    Schema {
+        /// File the schema is written to, its path being printed to stdout, stdout when not set.
+        #[arg(long)]
+        output: Option<String>,
+    },
+}
+
 /// TCP Tester app, used to generate traffic and network fault injection to test the Network
 /// Sonar agent.
 #[derive(Debug, Parser, Serialize)]
 #[command(version, about, long_about = None)]
 pub struct Params {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Number of servers that will be handling the requests.
     #[arg(short, long, default_value_t = 1)]
     pub servers: u8,
 
-    /// Number of connections per second that will be generated (distributed across the servers).
-    #[arg(short, long, default_value_t = 1)]
+    /// Number of connections per second that will be generated (distributed across the servers),
+    /// at most 1000000.
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=MAX_CONNECTION_RATE as i64)
+    )]
     pub connection_rate: u32,
 
+    /// File holding the number of connections per second, a positive integer on one line,
+    /// replacing `--connection-rate`. The file is read every second, so that the connection rate
+    /// can be changed at runtime; invalid contents keep the previous rate.
+    #[arg(long)]
+    pub rate_file: Option<String>,
+
+    /// Maximum number of concurrent flows expected. A warning is logged before starting when the
+    /// connection rate and the estimated flow duration imply more.
+    #[arg(long)]
+    pub max_concurrent: Option<u32>,
+
+    /// CPUs the threads of tcp-tester are pinned to, comma separated numbers or ranges, e.g.
+    /// `0-3,8`. All the threads share all the CPUs unless `--affinity-mode` is set.
+    // Fully qualified for clap to take the list as a single value.
+    #[arg(long, value_parser = parse_cpu_list)]
+    pub affinity: Option<::std::vec::Vec<usize>>,
+
+    /// How the flows are distributed across the CPUs of `--affinity`, e.g. the cores of several
+    /// NUMA nodes, for each flow to keep its caches local.
+    #[arg(long, value_enum, requires = "affinity")]
+    pub affinity_mode: Option<AffinityMode>,
+
     /// The amount of time taken by the server before responding to a request.
     #[arg(short, long, default_value_t = 0)]
     pub response_delay_ms: u64,
@@ -46,9 +257,10 @@ pub struct Params {
     #[arg(short = 'd', long, default_value_t = OnOff::Off)]
     pub send_data: OnOff,
 
-    /// Path of the cgroup where the fault injection is going to be generated.
-    #[arg(short = 'g', long, default_value = "/mnt/cgroup2")]
-    pub cgroup_path: String,
+    /// Path of the cgroup where the fault injection is going to be generated. Defaults to the
+    /// cgroup v2 of tcp-tester, or `/mnt/cgroup2` when it can't be detected.
+    #[arg(short = 'g', long)]
+    pub cgroup_path: Option<String>,
 
     /// Path of the file containing the flow configuration to be applied to all flows.
     #[arg(
@@ -57,4 +269,299 @@ pub struct Params {
         default_value = "tcp-tester/src/config/packet_loss.json"
     )]
     pub config_file_path: String,
+
+    /// Format of the flow configuration files, detected from their extension when not set:
+    /// YAML for `.yaml` and `.yml`, TOML for `.toml` with the `toml` feature, JSON otherwise.
+    #[arg(long)]
+    pub config_format: Option<ConfigFormat>,
+
+    /// Inline JSON merged on top of the flow configuration file (JSON Merge Patch semantics).
+    #[arg(long)]
+    pub config_overlay: Option<String>,
+
+    /// Logs the options of every client socket, at debug level, before connecting.
+    #[arg(long)]
+    pub audit_sockets: bool,
+
+    /// Path of the file where every modification of the `SOCKET_CONFIG` map is appended, as a
+    /// JSON line with its time, key, old and new values, and the function making it. Requires
+    /// traffic shaping to be enabled.
+    #[arg(long)]
+    pub audit_log: Option<String>,
+
+    /// Only monitors the flows, without injecting any fault, to baseline the eBPF overhead.
+    /// Takes precedence over traffic shaping.
+    #[arg(long)]
+    pub passive: bool,
+
+    /// Checks that the traffic control programs process the traffic once attached, by sending a
+    /// SYN from the client namespace to the server through the middle-box, and fails when it
+    /// isn't accounted in `FLOW_STATS` within a second.
+    #[arg(long)]
+    pub self_test: bool,
+
+    /// Loads the eBPF programs in the kernel without attaching them, prints the size and the
+    /// number of instructions processed by the verifier of each, and exits, e.g. to enforce a
+    /// complexity budget in CI.
+    #[arg(long)]
+    pub dry_attach: bool,
+
+    /// Path of the file where the `FLOW_STATS` map contents are periodically written as JSON.
+    /// Requires traffic shaping or passive mode to be enabled.
+    #[arg(long)]
+    pub interface_stats_file: Option<String>,
+
+    /// Seconds between two consecutive comparisons of the `FLOW_STATS` map with the TCP
+    /// connections of the client namespace, see the `reconcile` command. The connections
+    /// missing from the map are counted in `flows_missed_total`. Requires traffic shaping or
+    /// passive mode to be enabled.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub reconcile_interval: Option<u64>,
+
+    /// Seconds between two consecutive stats reports (stats file and flow duration histogram).
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval: u64,
+
+    /// Seconds of the sliding window over which rolling averages of the completed flows are
+    /// logged, on every stats report. Disabled when not set.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_window: Option<u64>,
+
+    /// Seconds between two consecutive reports of the qdisc counters of the middle-box
+    /// interfaces, as reported by `tc -s qdisc show`. Disabled when not set.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub qdisc_stats: Option<u64>,
+
+    /// Middle-box interface whose packets are captured with an `AF_PACKET` socket, e.g. `i2`,
+    /// before traffic control on ingress. The totals are logged on every stats report.
+    #[arg(long)]
+    pub raw_capture: Option<String>,
+
+    /// Number of packets dropped by a qdisc above which a warning is logged.
+    #[arg(long, default_value_t = 0)]
+    pub qdisc_drop_warn: u64,
+
+    /// Seconds between two consecutive reports of the queue depths of the server ports, as
+    /// reported by `ss`. Disabled when not set.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub queue_monitor: Option<u64>,
+
+    /// Bytes in the receive or send queues of a server port above which an error is logged.
+    #[arg(long)]
+    pub queue_warn_threshold: Option<u64>,
+
+    /// Seconds between two consecutive test flows to the first server, not conditioned nor
+    /// accounted by the eBPF programs, to detect a stuck load generator, e.g. broken namespace
+    /// routing. A flow not completing within twice the expected flow duration is logged as an
+    /// error. Disabled when not set.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub health_check_interval: Option<u64>,
+
+    /// URL the failed test flows of `--health-check-interval` are posted to, as JSON, e.g.
+    /// `http://10.0.0.1:8080/alerts`. Only plain HTTP is supported.
+    #[arg(long, value_parser = crate::health_monitor::parse_webhook_url)]
+    pub health_check_alert_webhook: Option<String>,
+
+    /// Address and port of the `FlowController` gRPC service, to update the flow configuration
+    /// and follow the flows remotely. Disabled when not set.
+    #[arg(long)]
+    pub grpc_addr: Option<SocketAddr>,
+
+    /// Endpoint of the OpenTelemetry collector the client spans are exported to, over OTLP/gRPC,
+    /// e.g. `http://localhost:4317`. The OpenTelemetry context of the flow configuration is
+    /// propagated to the servers regardless.
+    #[arg(long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Seconds after which the `SOCKET_CONFIG` entry of a socket that never connected is
+    /// removed, so the entries orphaned by killed clients don't fill up the map. Disabled when
+    /// not set.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub map_entry_ttl_secs: Option<u64>,
+
+    /// Seconds between two consecutive checks of the `SOCKET_CONFIG` entries age.
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    pub map_ttl_check_interval: u64,
+
+    /// Path of the file where the `SOCKET_CONFIG` entries are written as JSON when receiving
+    /// SIGTERM, before exiting, for inspection: the maps outlive the process with `--pin-maps`.
+    /// Requires traffic shaping.
+    #[arg(long)]
+    pub snapshot_on_sigterm: Option<String>,
+
+    /// Keeps generating traffic with plain connections, without fault injection nor monitoring,
+    /// when the eBPF programs can't be set up, instead of exiting.
+    #[arg(long)]
+    pub fallback_to_plain: bool,
+
+    /// Pins the eBPF maps holding state under /sys/fs/bpf/nfm, so their state outlives the
+    /// process. The maps already pinned there by a previous run are reused, with their entries.
+    #[arg(long)]
+    pub pin_maps: bool,
+
+    /// File where the logs are written, in addition to stderr. The file being written is named
+    /// `<name>_rCURRENT.<extension>`, the rotated ones `<name>_r<number>.<extension>`.
+    #[arg(long)]
+    pub log_file: Option<String>,
+
+    /// Size in MB at which the log file is rotated.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_file_max_size: u64,
+
+    /// Number of rotated log files kept, the oldest ones are deleted.
+    #[arg(long, default_value_t = 10)]
+    pub log_file_keep: usize,
+
+    /// Maximum duration in seconds of every flow, after which the client closes the connection.
+    /// The flow configuration `max_flow_duration_ms` takes precedence when it is shorter.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_flow_duration: Option<u64>,
+
+    /// Collects the kernel transmit timestamps of the data sent by the clients with
+    /// `SO_TIMESTAMPING`, to measure the queuing delay in the network stack: the time between
+    /// the send and the transmit timestamp.
+    #[arg(long, value_enum)]
+    pub enable_sock_timestamping: Option<SockTimestamping>,
+
+    /// Header carrying the ID of the flow in every HTTP/2 request, e.g. `X-NFM-Flow-ID`, unless
+    /// the flow configuration sets `http2.flow_id_header_name`.
+    #[arg(long, value_parser = parse_header_name)]
+    pub flow_id_header: Option<String>,
+
+    /// Maximum number of connections per second to every destination IP, shared by all the
+    /// clients connecting to it, with a burst of one second worth of connections.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub per_ip_rate: Option<u32>,
+
+    /// p99 RTT to the server, over the flows completed in the last 5 seconds, above which the
+    /// connection rate is halved. The rate is restored once the p99 RTT drops below 70% of it.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub rtt_backpressure_threshold_ms: Option<u64>,
+
+    /// Most verbose level of the messages logged by the eBPF programs, which are forwarded to
+    /// the log at their level. The debug level logs every conditioned packet.
+    #[arg(long, value_enum, default_value_t = EbpfLogLevel::Info)]
+    pub ebpf_log_level: EbpfLogLevel,
+
+    /// eBPF object file the programs are loaded from, instead of the one embedded at build time,
+    /// to iterate on the programs without rebuilding tcp-tester. The object must be built with
+    /// the same `PROGRAM_VERSION` as the binary.
+    #[arg(long)]
+    pub ebpf_object: Option<String>,
+
+    /// Rate in Hz at which the traffic control programs sample the packets they delay, on every
+    /// CPU, with `bpf_perf_event_output`. The samples are appended to `--perf-samples-file`.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub perf_event_sample_rate: Option<u32>,
+
+    /// Binary file the perf samples are appended to, 32 bytes per sample: the monotonic time in
+    /// nanoseconds, the flow key, the CPU and the delay added in nanoseconds.
+    #[arg(long, default_value = "perf-samples.bin")]
+    pub perf_samples_file: String,
+
+    /// Number of connections set up together when traffic shaping is enabled, sharing a single
+    /// namespace switch and `SOCKET_CONFIG` update. The connection rate is preserved.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_size: u32,
+
+    /// Prefix of the client, tcp-tester and server network namespaces, named
+    /// `<prefix>-client`, `<prefix>-tcp-tester` and `<prefix>-server`. Allows running several
+    /// instances, each one on its own topology (see `bin/network-setup`).
+    #[arg(long, default_value = DEFAULT_NAMESPACE_PREFIX)]
+    pub namespace_prefix: String,
+
+    /// PID of a process of a container whose network namespace, entered through
+    /// `/proc/<pid>/ns/net`, is the one where the traffic control programs are attached instead
+    /// of the tcp-tester namespace. For the containers which are not in a named namespace, e.g.
+    /// with the Docker bridge networking, their interfaces are expected to be `i2` and `i3`.
+    #[arg(long)]
+    pub container_pid: Option<u32>,
+
+    /// Handle of the traffic control filters of the eBPF programs, as `major:minor` in hex, the
+    /// `tc` notation, e.g. `1:10`, for other `tc` rules to reference them. The programs are then
+    /// attached through netlink, tcx links have no handle. The kernel picks one when not set.
+    #[arg(long, value_parser = parse_tc_handle)]
+    pub tc_handle: Option<u32>,
+
+    /// Path of a topology file (YAML or JSON) describing the network namespaces, the veth pairs
+    /// between them and their addresses and routes, created before running when missing. The
+    /// namespaces are named `<namespace-prefix>-<node name>`. See `src/config/topology.yaml`,
+    /// equivalent to `bin/network-setup`. The file is reapplied on SIGHUP, only changing the
+    /// nodes and links that differ, e.g. to remove a link during a test.
+    #[arg(long)]
+    pub topology: Option<String>,
+
+    /// Path of a scenario file (YAML or JSON) listing the flow configuration files to apply one
+    /// after the other, each one for a given duration. Replaces the flow configuration file,
+    /// tcp-tester exits once the scenario is completed.
+    #[arg(long)]
+    pub scenario: Option<String>,
+
+    /// Source address the client sockets are bound to, instead of the one picked by the kernel.
+    /// The flow configuration can override it.
+    #[arg(long)]
+    pub bind_addr: Option<IpAddr>,
+
+    /// VRF (Virtual Routing and Forwarding) master device the client sockets are bound to with
+    /// `SO_BINDTODEVICE`, for them to use its routing table. Requires Linux 4.8 or later. The
+    /// device is looked up in the client namespace. The traffic control programs stay attached
+    /// to the middle-box interfaces, which see the flows whatever the VRF.
+    #[arg(long)]
+    pub vrf: Option<String>,
+
+    /// Size in bytes of both the send and receive buffers of the client sockets
+    /// (`SO_SNDBUF` and `SO_RCVBUF`). The kernel doubles it, and clamps it to
+    /// `net.core.wmem_max` and `net.core.rmem_max`.
+    #[arg(long)]
+    pub tcp_buffer_size: Option<u32>,
+
+    /// Size in bytes of the send buffer of the client sockets, overriding `--tcp-buffer-size`.
+    #[arg(long)]
+    pub tcp_send_buffer: Option<u32>,
+
+    /// Size in bytes of the receive buffer of the client sockets, overriding
+    /// `--tcp-buffer-size`.
+    #[arg(long)]
+    pub tcp_recv_buffer: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tc_handle() {
+        assert_eq!(parse_tc_handle("1:10"), Ok(0x0001_0010));
+        assert_eq!(parse_tc_handle("ffff:ffff"), Ok(0xffff_ffff));
+        assert!(parse_tc_handle("10").is_err());
+        assert!(parse_tc_handle("1:10000").is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("3"), Ok(vec![3]));
+        assert_eq!(parse_cpu_list("0-3,8"), Ok(vec![0, 1, 2, 3, 8]));
+        assert!(parse_cpu_list("3-0").is_err());
+        assert!(parse_cpu_list("0,a").is_err());
+
+        let params = Params::try_parse_from(["tcp-tester", "--affinity", "0-1,4"]).unwrap();
+        assert_eq!(params.affinity, Some(vec![0, 1, 4]));
+        assert!(Params::try_parse_from(["tcp-tester", "--affinity-mode", "sticky"]).is_err());
+    }
+
+    #[test]
+    fn test_zero_intervals_are_rejected() {
+        for arg in ["--stats-interval", "--qdisc-stats", "--queue-monitor"] {
+            assert!(Params::try_parse_from(["tcp-tester", arg, "0"]).is_err());
+            assert!(Params::try_parse_from(["tcp-tester", arg, "5"]).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_connection_rate_range() {
+        for (rate, valid) in [("0", false), ("1000000", true), ("1000001", false)] {
+            let params = Params::try_parse_from(["tcp-tester", "--connection-rate", rate]);
+            assert_eq!(params.is_ok(), valid, "{rate}");
+        }
+    }
 }
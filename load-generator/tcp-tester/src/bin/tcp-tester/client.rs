@@ -1,105 +1,415 @@
 mod client_socket_error;
-mod conditioned_tcp_stream;
+mod flow_config_history;
+mod flow_dependencies;
+mod flow_result;
+mod http2;
+mod path_flap;
+mod payload;
 mod socket_builder;
+mod socks5;
 
+use crate::audit_log::AuditLogger;
+use crate::cli::ConfigFormat;
+use crate::cpu_affinity::FlowRuntimes;
 use crate::ebpf_loader;
-use aya::util::KernelVersion;
+use crate::flow_limiter::{PerDestinationRateLimiter, RateFile, RttBackpressure};
+use crate::kernel_capabilities::KernelCapabilities;
+use crate::logging::{self, FLOW_ID};
+use crate::namespaces::MiddleBoxNamespace;
+use crate::self_test;
+use crate::telemetry;
 
 use anyhow::Context;
-use aya::maps::HashMap;
-use aya::programs::tc::{self as tc, TcAttachOptions};
+use aya::maps::{HashMap, MapData};
+use aya::programs::tc::{self as tc, NlOptions, TcAttachOptions};
 use aya::programs::{CgroupAttachMode, LinkOrder, SchedClassifier, SockOps, TcAttachType};
 use aya::Ebpf;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use netns_rs::NetNs;
-use rand::{Rng, RngExt};
+use opentelemetry::Context as TraceContext;
+use rand::rngs::StdRng;
+use rand::{Rng, RngExt, SeedableRng};
 use std::fs::File;
-use std::io::Read;
-use std::net::SocketAddr;
-use std::time::Duration;
-use tcp_tester_common::{FlowConfig, SocketKey};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tcp_tester::conditioned_tcp_stream::{ConditionedTcpStream, SockTimestamping};
+use tcp_tester_common::{
+    validate_cross_fields, Conditioner, Direction, FaultProfile, FlowConfig, FlowKey, FlowState,
+    PayloadDistribution, DEFAULT_INTER_PACKET_DELAY_MS, MAX_PIPELINE_DEPTH,
+};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::time::sleep;
+use tokio::sync::broadcast;
+use tokio::time::{sleep, sleep_until, timeout, timeout_at};
+use uuid::Uuid;
 
 use self::socket_builder::{connect_sans_tc, ClientSocketBuilder};
 use client_socket_error::ClientSocketError;
-use conditioned_tcp_stream::ConditionedTcpStream;
+pub use client_socket_error::HealthStatus;
+use flow_config_history::SharedFlowConfigHistory;
+pub use flow_config_history::{FlowConfigChange, FlowConfigHistory};
+pub use flow_dependencies::FlowDependencies;
+pub use flow_result::{FlowCounters, FlowEvent, FlowResult, FlowTotals};
+pub use socket_builder::{BufferSizes, ConnectionTimingBreakdown, SocketConfigMaps};
 
-static CLIENT_NAMESPACE: &str = "nfm-perf-test-client";
-static TCP_TESTER_NAMESPACE: &str = "nfm-perf-test-tcp-tester";
+/// `SOCKET_CONFIG` map shared by all the clients, used to hand over the flow configuration to
+/// the sockops program.
+pub type SocketConfigMap = Arc<Mutex<SocketConfigMaps<MapData>>>;
 
-/// Reads a file containing the configuration to be applied to all flows.
+/// `FLOW_CONFIG` map shared by all the clients, used to update the configuration of the
+/// established flows.
+pub type FlowConfigMap = Arc<Mutex<HashMap<MapData, FlowKey, FlowState>>>;
+
+/// Connection to the server, along with the time spent setting it up when traffic shaping is
+/// enabled.
+type ConnectResult =
+    Result<(ConditionedTcpStream, Option<ConnectionTimingBreakdown>), ClientSocketError>;
+
+/// Audit log and history of the fault profiles of a flow, the timeline of the profiles being
+/// logged once the flow completes.
+type FlowConfigAudit = (Arc<AuditLogger>, SharedFlowConfigHistory);
+
+/// Flow configuration set through the control plane, replacing the configuration file.
+pub type RemoteFlowConfig = Arc<RwLock<Option<FlowConfig>>>;
+
+/// eBPF maps shared by all the clients when traffic shaping is enabled.
+#[derive(Clone)]
+pub struct ClientMaps {
+    pub socket_config: SocketConfigMap,
+    pub flow_config: FlowConfigMap,
+}
+
+/// Settings applied to every client connection.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Namespace where the clients run.
+    pub namespace: String,
+    /// Whether random data is exchanged with the server before closing the connection.
+    pub send_data: bool,
+    /// Path to the flow configuration file relative to tcp-tester crate root folder.
+    pub config_file_path: String,
+    /// Format of the flow configuration file, detected from its extension when not set.
+    pub config_format: Option<ConfigFormat>,
+    /// Inline JSON merged on top of the flow configuration file.
+    pub config_overlay: Option<String>,
+    /// Whether the socket options are logged before connecting.
+    pub audit_sockets: bool,
+    /// Number of connections set up together when traffic shaping is enabled.
+    pub batch_size: u32,
+    /// Totals of the flows, shared by all the clients.
+    pub flow_counters: Arc<FlowCounters>,
+    /// Completions of the named flows, shared by all the clients.
+    pub flow_dependencies: Arc<FlowDependencies>,
+    /// Per-CPU runtimes the flows are spawned on with `--affinity-mode sticky`.
+    pub flow_runtimes: Option<Arc<FlowRuntimes>>,
+    /// Source address of the client sockets, unless set in the flow configuration.
+    pub bind_addr: Option<IpAddr>,
+    /// VRF master device the client sockets are bound to, if any.
+    pub vrf: Option<String>,
+    /// Send and receive buffer sizes of the client sockets.
+    pub buffer_sizes: BufferSizes,
+    /// Flow configuration set through the control plane, if any.
+    pub remote_config: RemoteFlowConfig,
+    /// Publishes the lifecycle events of the flows to the control plane.
+    pub flow_events: broadcast::Sender<FlowEvent>,
+    /// Maximum duration of every flow, unless the flow configuration sets a shorter one.
+    pub max_flow_duration: Option<Duration>,
+    /// Transmit timestamps collected on the client sockets, if any.
+    pub sock_timestamping: Option<SockTimestamping>,
+    /// Header carrying the flow ID in the HTTP/2 requests, unless set in the flow configuration.
+    pub flow_id_header: Option<String>,
+    /// Limits the connection rate to every destination IP, shared by all the clients.
+    pub rate_limiter: Option<Arc<PerDestinationRateLimiter>>,
+    /// Halves the connection rate while the RTT to the server is too high, shared by all the
+    /// clients.
+    pub rtt_backpressure: Option<Arc<RttBackpressure>>,
+    /// Connection rate updated at runtime, replacing the one the clients are started with.
+    pub rate_file: Option<Arc<RateFile>>,
+}
+
+/// Parses a flow configuration in the given format.
+pub fn parse_flow_config(contents: &str, format: ConfigFormat) -> anyhow::Result<FlowConfig> {
+    match format {
+        ConfigFormat::Json => FlowConfig::from_json(contents),
+        ConfigFormat::Yaml => FlowConfig::from_value(serde_yaml::from_str(contents)?),
+        #[cfg(feature = "toml")]
+        ConfigFormat::Toml => FlowConfig::from_value(toml::from_str(contents)?),
+    }
+}
+
+/// Returns the path of the user defaults of the flow configurations,
+/// `$XDG_CONFIG_HOME/nfm/defaults.json`, or `~/.config/nfm/defaults.json` when
+/// `XDG_CONFIG_HOME` is not set.
+fn user_defaults_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("nfm").join("defaults.json"))
+}
+
+/// Loads the user defaults of the flow configurations, shared by all the configuration files.
+/// Returns None when there are none, or when they can't be loaded.
+pub fn load_user_defaults() -> Option<FlowConfig> {
+    let path = user_defaults_path()?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => return None,
+        Err(error) => {
+            warn!(
+                "Failed to read flow config defaults {}: {}",
+                path.display(),
+                error
+            );
+            return None;
+        }
+    };
+    match FlowConfig::from_json(&contents) {
+        Ok(defaults) => {
+            debug!("Loaded flow config defaults from {}", path.display());
+            Some(defaults)
+        }
+        Err(error) => {
+            warn!(
+                "Failed to parse flow config defaults {}: {:?}",
+                path.display(),
+                error
+            );
+            None
+        }
+    }
+}
+
+/// Parses a flow configuration on top of the user defaults, if any, with
+/// `FlowConfig::with_overlay`: the fields set in `contents` override the defaults, the fault
+/// profiles being merged field by field.
+fn parse_flow_config_over(
+    defaults: Option<FlowConfig>,
+    contents: &str,
+    format: ConfigFormat,
+) -> anyhow::Result<FlowConfig> {
+    let Some(defaults) = defaults else {
+        return parse_flow_config(contents, format);
+    };
+    let overlay = match format {
+        ConfigFormat::Json => contents.to_string(),
+        ConfigFormat::Yaml => {
+            serde_json::to_string(&serde_yaml::from_str::<serde_json::Value>(contents)?)?
+        }
+        #[cfg(feature = "toml")]
+        ConfigFormat::Toml => {
+            serde_json::to_string(&toml::from_str::<serde_json::Value>(contents)?)?
+        }
+    };
+    FlowConfig::with_overlay(defaults, &overlay)
+}
+
+/// Reads a file containing the configuration to be applied to all flows, on top of the user
+/// defaults (see `load_user_defaults`).
 ///
 /// # Arguments
 /// * `path` - path to the configuration file relative to tcp-tester crate root folder.
-fn get_config_from_file(path: String) -> FlowConfig {
+/// * `format` - format of the file, detected from its extension when not set.
+async fn get_config_from_file(
+    path: String,
+    format: Option<ConfigFormat>,
+) -> anyhow::Result<FlowConfig> {
     println!("Reading config file from {}", path);
-    let mut file = File::open(path).unwrap();
-    let mut json = String::new();
-    file.read_to_string(&mut json).unwrap();
-    let result: FlowConfig = serde_json::from_str(&json).unwrap();
-    result
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Failed to read config file: {}", path))?;
+    let format = format.unwrap_or_else(|| ConfigFormat::from_path(&path));
+    let result = parse_flow_config_over(load_user_defaults(), &contents, format)
+        .with_context(|| format!("Failed to parse config file: {}", path))?;
+    Ok(result)
+}
+
+/// Fails with all the violated constraints when the configuration is inconsistent.
+pub fn check_cross_fields(config: &FlowConfig) -> anyhow::Result<()> {
+    let errors = validate_cross_fields(config);
+    if !errors.is_empty() {
+        anyhow::bail!("Inconsistent flow config: {}", errors.join("; "));
+    }
+    Ok(())
+}
+
+/// Reads the flow configuration file and applies the overlay on top of it, if any. The result is
+/// checked for consistency, the overlay being able to break it.
+async fn load_flow_config(
+    path: String,
+    format: Option<ConfigFormat>,
+    overlay: Option<String>,
+) -> anyhow::Result<FlowConfig> {
+    let config = get_config_from_file(path, format).await?;
+    let config = match overlay {
+        Some(overlay) => FlowConfig::with_overlay(config, &overlay)?,
+        None => config,
+    };
+    check_cross_fields(&config)?;
+    Ok(config)
 }
 
-/// Attaches the eBPF programs for traffic control and sockops in the specified cgroup.
+/// Returns the flow configuration of a new connection: the one set through the control plane if
+/// any, the configuration file with the overlay applied otherwise.
+async fn resolve_flow_config(options: &ClientOptions) -> anyhow::Result<FlowConfig> {
+    if let Some(config) = options.remote_config.read().unwrap().as_ref() {
+        return Ok(config.clone());
+    }
+    load_flow_config(
+        options.config_file_path.clone(),
+        options.config_format,
+        options.config_overlay.clone(),
+    )
+    .await
+}
+
+/// Loads the traffic control program `name` and attaches it to the given interface, through a
+/// tcx link when supported by the kernel and netlink otherwise, or when the filter has a
+/// `handle`.
+fn attach_classifier(
+    bpf: &mut Ebpf,
+    name: &str,
+    interface: &str,
+    attach_type: TcAttachType,
+    capabilities: &KernelCapabilities,
+    handle: Option<u32>,
+) -> anyhow::Result<()> {
+    let program: &mut SchedClassifier = bpf
+        .program_mut(name)
+        .with_context(|| format!("Program {} not found", name))?
+        .try_into()?;
+
+    program
+        .load()
+        .with_context(|| format!("Failed to load {}", name))?;
+
+    let options = match handle {
+        Some(handle) => TcAttachOptions::Netlink(NlOptions {
+            handle,
+            ..NlOptions::default()
+        }),
+        None if capabilities.has_tcx => TcAttachOptions::TcxOrder(LinkOrder::default()),
+        None => TcAttachOptions::Netlink(NlOptions::default()),
+    };
+    program
+        .attach_with_options(interface, attach_type, options)
+        .with_context(|| format!("Failed to attach {} to {}", name, interface))?;
+    Ok(())
+}
+
+/// Interface of the middle-box facing the client.
+const CLIENT_SIDE_INTERFACE: &str = "i2";
+/// Interface of the middle-box facing the server.
+const SERVER_SIDE_INTERFACE: &str = "i3";
+
+/// Returns the interface and hook of the middle-box the traffic control program of `direction`
+/// is attached to. Both programs see the packets leaving the middle-box, which the `Delay`
+/// conditioner requires: the packets sent by the client leave through the interface facing the
+/// server, and the ones it receives through the interface facing the client. The packets of a
+/// direction thus carry the `FLOW_CONFIG` key the sockops program stores its profile under.
+fn classifier_attach_point(direction: Direction) -> (&'static str, TcAttachType) {
+    if direction == Direction::EGRESS {
+        (SERVER_SIDE_INTERFACE, TcAttachType::Egress)
+    } else {
+        (CLIENT_SIDE_INTERFACE, TcAttachType::Egress)
+    }
+}
+
+/// Attaches the eBPF programs for traffic control and sockops in the specified cgroup. The
+/// programs stay attached as long as the returned handle is alive.
 ///
 /// # Arguments
 /// * `cgroup_path` - cgroup file path where the fault injection program is going to be attached.
-fn setup_ebpf(cgroup_path: String) -> Ebpf {
-    let mut bpf = ebpf_loader::load_ebpf_program().unwrap();
+/// * `passive` - attaches the monitoring-only traffic control programs, which only populate
+///   `FLOW_STATS`, and skips the sockops program.
+/// * `load_options` - how the programs are loaded.
+/// * `namespace` - middle-box namespace, where the traffic control programs are attached, a
+///   named one or the one of a container.
+/// * `tc_handle` - handle of the traffic control filters, picked by the kernel when not set.
+/// * `self_test_namespace` - client namespace the traffic control programs are checked from
+///   once attached, see `ebpf_self_test`. Not checked when not set.
+pub fn setup_ebpf(
+    cgroup_path: String,
+    passive: bool,
+    load_options: ebpf_loader::LoadOptions,
+    namespace: &MiddleBoxNamespace,
+    tc_handle: Option<u32>,
+    self_test_namespace: Option<&str>,
+) -> anyhow::Result<Ebpf> {
+    let capabilities = load_options.capabilities;
+    let mut bpf = ebpf_loader::load_ebpf_program(load_options).map_err(anyhow::Error::msg)?;
+    ebpf_loader::verify_map_layout(&bpf)?;
+    let (egress_program, ingress_program) = if passive {
+        (
+            "tcp_tester_tc_passive_egress",
+            "tcp_tester_tc_passive_ingress",
+        )
+    } else {
+        ("tcp_tester_tc_egress", "tcp_tester_tc_ingress")
+    };
 
-    // Attachs the traffic control program to the respective interfaces in the middle-box.
-    let namespace = NetNs::get(TCP_TESTER_NAMESPACE).unwrap();
-    namespace
+    // Attachs the traffic control programs to the respective interfaces in the middle-box.
+    let netns = namespace
+        .get()
+        .with_context(|| format!("Failed to get namespace {}", namespace))?;
+    netns
         .run(|_| {
-            let _ = tc::qdisc_add_clsact("i2");
-            let _ = tc::qdisc_add_clsact("i3");
+            let _ = tc::qdisc_add_clsact(CLIENT_SIDE_INTERFACE);
+            let _ = tc::qdisc_add_clsact(SERVER_SIDE_INTERFACE);
 
-            let program: &mut SchedClassifier = bpf
-                .program_mut("tcp_tester_tc_egress")
-                .unwrap()
-                .try_into()
-                .unwrap();
+            for (program, direction) in [
+                (egress_program, Direction::EGRESS),
+                (ingress_program, Direction::INGRESS),
+            ] {
+                let (interface, attach_type) = classifier_attach_point(direction);
+                attach_classifier(
+                    &mut bpf,
+                    program,
+                    interface,
+                    attach_type,
+                    capabilities,
+                    tc_handle,
+                )?;
+            }
+            Ok::<_, anyhow::Error>(())
+        })
+        .with_context(|| format!("Failed to enter namespace {}", namespace))?
+        .with_context(|| format!("Failed to attach TC program in namespace {}", namespace))?;
 
-            program.load().unwrap();
+    if let Some(self_test_namespace) = self_test_namespace {
+        let ns = NetNs::get(self_test_namespace)
+            .with_context(|| format!("Failed to get namespace {}", self_test_namespace))?;
+        self_test::ebpf_self_test(&bpf, &ns).context("eBPF self-test failed")?;
+    }
 
-            program
-                .attach_with_options(
-                    "i2",
-                    TcAttachType::Egress,
-                    TcAttachOptions::TcxOrder(LinkOrder::default()),
-                )
-                .unwrap();
-            program
-                .attach_with_options(
-                    "i3",
-                    TcAttachType::Ingress,
-                    TcAttachOptions::TcxOrder(LinkOrder::default()),
-                )
-                .unwrap();
-        })
-        .unwrap();
+    if passive {
+        return Ok(bpf);
+    }
 
     // Loads the sockops program in the kernel.
     let program: &mut SockOps = bpf
         .program_mut("tcp_tester_sockops")
-        .unwrap()
-        .try_into()
-        .unwrap();
-    program.load().unwrap();
+        .context("Program tcp_tester_sockops not found")?
+        .try_into()?;
     program
-        .attach(File::open(cgroup_path.clone()).unwrap(), get_attach_mode())
-        .context(format!("Failed to attach to cgroup: {}", cgroup_path))
-        .unwrap();
+        .load()
+        .context("Failed to load tcp_tester_sockops")?;
+    let cgroup = File::open(&cgroup_path)
+        .with_context(|| format!("Failed to open cgroup: {}", cgroup_path))?;
+    program
+        .attach(cgroup, get_attach_mode(capabilities))
+        .with_context(|| format!("Failed to attach to cgroup: {}", cgroup_path))?;
 
-    bpf
+    Ok(bpf)
 }
 
-fn get_attach_mode() -> CgroupAttachMode {
+fn get_attach_mode(capabilities: &KernelCapabilities) -> CgroupAttachMode {
     // Aya uses BPF_LINK_CREATE for Linux >= 5.7.0 (see sock_ops.rs). The only valid value
     // is 0 (CgroupAttachMode), but Kernel uses BPF_F_ALLOW_MULTI to attach the link.
-    if KernelVersion::current().unwrap() >= KernelVersion::new(5, 7, 0) {
+    if capabilities.has_sockops_kern_5_7 {
         CgroupAttachMode::Single
     } else {
         CgroupAttachMode::AllowMultiple
@@ -111,63 +421,720 @@ fn get_attach_mode() -> CgroupAttachMode {
 /// # Arguments
 ///
 /// * `addr` - Address and port of the server.
-/// * `cgroup_path` - cgroup file path where the fault injection program is going to be attached.
-/// * `config_file_path` - path to the configuration file relative to tcp-tester crate root folder.
-async fn run_client(
-    addr: SocketAddr,
-    enable_traffic_shaping: bool,
-    send_data: bool,
-    cgroup_path: String,
-    config_file_path: String,
+/// * `maps` - eBPF maps, only present when traffic shaping is enabled.
+/// * `options` - settings applied to the connection.
+async fn run_client(addr: SocketAddr, maps: Option<ClientMaps>, options: ClientOptions) {
+    let session_id = logging::current_flow_id().unwrap_or_else(Uuid::new_v4);
+    let client_namespace = NetNs::get(&options.namespace).unwrap();
+    let mut flow_config = None;
+    let mut flow_audit = None;
+    let trace_context;
+    let stream_result: ConnectResult = match maps {
+        Some(maps) => {
+            let mut config = match resolve_flow_config(&options).await {
+                Ok(config) => config,
+                Err(error) => {
+                    error!("Failed to load flow config: {:?}", error);
+                    return;
+                }
+            };
+            config.sample_delay(&mut rand::rng());
+            wait_for_dependency(&config, &options).await;
+            trace_context = telemetry::client_context(Some(&config));
+            let mut socket_builder =
+                ClientSocketBuilder::new(client_namespace, maps.socket_config.clone())
+                    .with_socket_audit(options.audit_sockets)
+                    .with_bind_addr(config.bind_addr.or(options.bind_addr))
+                    .with_vrf(options.vrf.clone())
+                    .with_buffer_sizes(options.buffer_sizes)
+                    .with_trace_context(trace_context.clone())
+                    .with_socks5_proxy(config.socks5_proxy)
+                    .with_tcp_fast_open(config.tcp_fast_open)
+                    .with_tcp_user_timeout(config.tcp_user_timeout_ms);
+            let (egress_config, ingress_config) = config.initial_profiles();
+            flow_audit = flow_config_audit(&maps, [egress_config, ingress_config]);
+            let history = flow_audit.as_ref().map(|(_, history)| history.clone());
+            let stream_result = socket_builder
+                .connect(addr, egress_config, ingress_config)
+                .await
+                .map(|(stream, timing)| {
+                    (
+                        condition_stream(stream, &config, &maps.flow_config, history),
+                        Some(timing),
+                    )
+                });
+            flow_config = Some(config);
+            stream_result
+        }
+        None => {
+            trace_context = telemetry::client_context(None);
+            connect_sans_tc(
+                client_namespace,
+                addr,
+                options.audit_sockets,
+                options.bind_addr,
+                options.vrf.as_deref(),
+                options.buffer_sizes,
+            )
+            .await
+            .map(|stream| (stream, None))
+        }
+    };
+
+    run_flow(
+        session_id,
+        addr,
+        stream_result,
+        flow_config,
+        flow_audit,
+        trace_context,
+        options,
+    )
+    .await;
+}
+
+/// Same as `run_client` for a batch of connections, set up all at once with
+/// `ClientSocketBuilder::connect_batch`.
+///
+/// # Arguments
+///
+/// * `addrs` - Address and port of the server of every connection.
+/// * `maps` - eBPF maps.
+/// * `options` - settings applied to the connections.
+async fn run_client_batch(addrs: Vec<SocketAddr>, maps: ClientMaps, options: ClientOptions) {
+    let client_namespace = NetNs::get(&options.namespace).unwrap();
+    let config = match resolve_flow_config(&options).await {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Failed to load flow config: {:?}", error);
+            return;
+        }
+    };
+    wait_for_dependency(&config, &options).await;
+    // The connections of the batch share the span of the batch.
+    let trace_context = telemetry::client_context(Some(&config));
+    let mut socket_builder = ClientSocketBuilder::new(client_namespace, maps.socket_config.clone())
+        .with_socket_audit(options.audit_sockets)
+        .with_bind_addr(config.bind_addr.or(options.bind_addr))
+        .with_vrf(options.vrf.clone())
+        .with_buffer_sizes(options.buffer_sizes)
+        .with_trace_context(trace_context.clone())
+        .with_socks5_proxy(config.socks5_proxy)
+        .with_tcp_fast_open(config.tcp_fast_open)
+        .with_tcp_user_timeout(config.tcp_user_timeout_ms);
+    // Every connection of the batch draws a delay of its own.
+    let configs: Vec<FlowConfig> = addrs
+        .iter()
+        .map(|_| {
+            let mut config = config.clone();
+            config.sample_delay(&mut rand::rng());
+            config
+        })
+        .collect();
+    let requests = addrs
+        .iter()
+        .zip(&configs)
+        .map(|(addr, config)| {
+            let (egress_config, ingress_config) = config.initial_profiles();
+            (*addr, egress_config, ingress_config)
+        })
+        .collect();
+
+    match socket_builder.connect_batch(requests).await {
+        Ok(stream_results) => {
+            for ((addr, config), stream_result) in
+                addrs.into_iter().zip(configs).zip(stream_results)
+            {
+                let (egress_config, ingress_config) = config.initial_profiles();
+                let flow_audit = flow_config_audit(&maps, [egress_config, ingress_config]);
+                let history = flow_audit.as_ref().map(|(_, history)| history.clone());
+                // The steps are shared by the connections of the batch, they are not timed.
+                let stream_result = stream_result.map(|stream| {
+                    (
+                        condition_stream(stream, &config, &maps.flow_config, history),
+                        None,
+                    )
+                });
+                // Every connection of the batch is a client of its own.
+                let session_id = Uuid::new_v4();
+                tokio::spawn(FLOW_ID.scope(
+                    session_id,
+                    run_flow(
+                        session_id,
+                        addr,
+                        stream_result,
+                        Some(config),
+                        flow_audit,
+                        trace_context.clone(),
+                        options.clone(),
+                    ),
+                ));
+            }
+        }
+        Err(error) => {
+            error!("Failed to set up connection batch: {:?}", error);
+        }
+    }
+}
+
+/// Waits for the flows the configuration depends on, if any, to complete.
+async fn wait_for_dependency(config: &FlowConfig, options: &ClientOptions) {
+    if let Some(depends_on) = &config.depends_on {
+        debug!("Waiting for flow {} to complete", depends_on);
+        options.flow_dependencies.wait_for(depends_on).await;
+    }
+}
+
+/// Returns the audit log of the flow, with the history of its fault profiles starting with
+/// `initial`, when `--audit-log` is set.
+fn flow_config_audit(
+    maps: &ClientMaps,
+    initial: flow_config_history::FlowProfiles,
+) -> Option<FlowConfigAudit> {
+    let audit_log = maps.socket_config.lock().unwrap().audit_log.clone()?;
+    Some((audit_log, FlowConfigHistory::shared(initial)))
+}
+
+/// Applies the parts of the flow configuration handled in userspace to the stream. The updates of
+/// the fault profiles are recorded in `history`, if any.
+fn condition_stream(
+    stream: ConditionedTcpStream,
+    config: &FlowConfig,
+    flow_config: &FlowConfigMap,
+    history: Option<SharedFlowConfigHistory>,
+) -> ConditionedTcpStream {
+    let start_delay = config
+        .failure_injection_start_delay_ms
+        .map(Duration::from_millis);
+    if start_delay.is_some() || config.path_flap.is_some() {
+        match (stream.stream.local_addr(), stream.stream.peer_addr()) {
+            (Ok(local), Ok(peer)) => {
+                let flow_config = flow_config.clone();
+                let (egress, ingress) = config.injected_profiles();
+                let path_flap = config.path_flap;
+                // The path flapping starts along with the fault injection.
+                tokio::spawn(async move {
+                    if let Some(delay) = start_delay {
+                        let started = path_flap::start_faults_after(
+                            &flow_config,
+                            local,
+                            peer,
+                            delay,
+                            egress,
+                            ingress,
+                            history.as_ref(),
+                        )
+                        .await;
+                        if !started {
+                            return;
+                        }
+                    }
+                    if let Some(path_flap) = path_flap {
+                        path_flap::flap_paths(flow_config, local, peer, path_flap, history).await;
+                    }
+                });
+            }
+            _ => warn!(
+                "Failed to get the flow addresses, path flapping and the fault injection start \
+                 delay are disabled"
+            ),
+        }
+    }
+
+    let stream = match &config.capture_path {
+        Some(path) => stream.with_capture(path),
+        None => stream,
+    };
+    let stream = match &config.targeted_corruption {
+        Some(specs) => stream.with_targeted_corruption(specs.clone()),
+        None => stream,
+    };
+    let stream = match config.bandwidth_kbps {
+        Some(bandwidth_kbps) => stream.with_bandwidth_limit(bandwidth_kbps),
+        None => stream,
+    };
+    match config.zero_window_after_bytes {
+        Some(after_bytes) => stream.with_zero_window(
+            after_bytes,
+            config.zero_window_duration_ms.map(Duration::from_millis),
+        ),
+        None => stream,
+    }
+}
+
+/// Number of messages exchanged with the server by `send_random_data`.
+const SEND_DATA_MESSAGES: Range<u32> = 50..150;
+
+/// Round-trip time assumed between the client and the server, through the veth pairs of the
+/// topology.
+const ESTIMATED_RTT: Duration = Duration::from_millis(1);
+
+/// Returns the average delay added to every packet by the fault profile.
+fn average_delay(profile: &FaultProfile) -> Duration {
+    match profile.conditioner {
+        // The jitter is uniformly distributed, see the traffic control program.
+        Conditioner::Delay(delay) => Duration::from_nanos(delay.offset + delay.jitter / 2),
+        _ => Duration::ZERO,
+    }
+}
+
+/// Returns the expected duration of a flow: the average number of exchanges, the pipelined
+/// messages counting as one, times the time between two messages, the round-trip time and the
+/// delays injected in both directions, bounded by the maximum flow duration of the configuration.
+/// Every packet is assumed to be delayed, whatever the selector, and the HTTP/2 requests are not
+/// accounted.
+///
+/// # Arguments
+/// * `config` - flow configuration, only present when traffic shaping is enabled.
+/// * `send_data` - whether random data is exchanged with the server.
+pub fn estimated_flow_duration(config: Option<&FlowConfig>, send_data: bool) -> Duration {
+    if !send_data {
+        return ESTIMATED_RTT;
+    }
+    let delay = config
+        .map(|config| {
+            let (egress, ingress) = config.injected_profiles();
+            average_delay(&egress) + average_delay(&ingress)
+        })
+        .unwrap_or_default();
+    let interval = Duration::from_millis(config.map_or(DEFAULT_INTER_PACKET_DELAY_MS, |config| {
+        config.inter_packet_delay_ms
+    }));
+    let messages = (SEND_DATA_MESSAGES.start + SEND_DATA_MESSAGES.end) / 2;
+    // The pipelined messages share the wait and the round trip.
+    let depth = config
+        .and_then(|config| config.pipeline_depth)
+        .map_or(1, u32::from)
+        .max(1);
+    let estimated = (interval + ESTIMATED_RTT + delay) * messages.div_ceil(depth);
+    match config.and_then(|config| config.max_flow_duration_ms) {
+        Some(max_flow_duration_ms) => estimated.min(Duration::from_millis(max_flow_duration_ms)),
+        None => estimated,
+    }
+}
+
+/// Warns when the connection rate and the estimated flow duration imply more concurrent flows
+/// than `max_concurrent` (Little's law).
+///
+/// # Arguments
+/// * `connections_per_sec` - connection rate of all the clients.
+/// * `max_concurrent` - maximum number of concurrent flows expected.
+/// * `traffic_shaping` - whether the flows use the flow configuration of the options.
+/// * `options` - settings applied to every connection.
+pub async fn check_max_concurrent(
+    connections_per_sec: f64,
+    max_concurrent: u32,
+    traffic_shaping: bool,
+    options: &ClientOptions,
 ) {
-    let client_namespace = NetNs::get(CLIENT_NAMESPACE).unwrap();
-    let stream_result: Result<ConditionedTcpStream, ClientSocketError> = if enable_traffic_shaping {
-        let mut bpf = setup_ebpf(cgroup_path);
-        let map = bpf.map_mut("SOCKET_CONFIG").unwrap();
-        let socket_config: HashMap<_, SocketKey, FlowConfig> = HashMap::try_from(map).unwrap();
-        let mut socket_builder = ClientSocketBuilder::new(client_namespace, socket_config);
-        let config = get_config_from_file(config_file_path);
-        socket_builder.connect(addr, config, config).await
+    let config = if traffic_shaping {
+        match resolve_flow_config(options).await {
+            Ok(config) => Some(config),
+            Err(error) => {
+                warn!(
+                    "Failed to load flow config to estimate the flow duration: {:?}",
+                    error
+                );
+                return;
+            }
+        }
     } else {
-        connect_sans_tc(client_namespace, addr).await
+        None
     };
+    let duration = estimated_flow_duration(config.as_ref(), options.send_data);
+    let concurrent = connections_per_sec * duration.as_secs_f64();
+    if concurrent > f64::from(max_concurrent) {
+        warn!(
+            "{:.0} connections per second lasting {:?} on average make {:.0} concurrent flows, \
+             more than --max-concurrent ({})",
+            connections_per_sec, duration, concurrent, max_concurrent
+        );
+    }
+}
 
+/// Returns the maximum duration of a flow: the smallest of the one of its configuration and the
+/// default one, when set.
+fn max_flow_duration(config: Option<&FlowConfig>, default: Option<Duration>) -> Option<Duration> {
+    let configured = config
+        .and_then(|config| config.max_flow_duration_ms)
+        .map(Duration::from_millis);
+    match (configured, default) {
+        (Some(configured), Some(default)) => Some(configured.min(default)),
+        (configured, default) => configured.or(default),
+    }
+}
+
+/// Exchanges data with the server, if requested, closes the connection and reports the
+/// `FlowResult`. The HTTP/2 requests, when configured, replace the raw data exchange.
+///
+/// # Arguments
+///
+/// * `session_id` - Logical session of the client the connection belongs to.
+/// * `addr` - Address and port of the server.
+/// * `stream_result` - connection to the server.
+/// * `config` - flow configuration, only present when traffic shaping is enabled.
+/// * `flow_audit` - audit log and history of the fault profiles of the flow, with `--audit-log`.
+/// * `trace_context` - OpenTelemetry context of the client, propagated to the server.
+/// * `options` - settings applied to the connection.
+async fn run_flow(
+    session_id: Uuid,
+    addr: SocketAddr,
+    stream_result: ConnectResult,
+    config: Option<FlowConfig>,
+    flow_audit: Option<FlowConfigAudit>,
+    trace_context: TraceContext,
+    options: ClientOptions,
+) {
+    let ClientOptions {
+        send_data,
+        flow_counters,
+        flow_dependencies,
+        flow_events,
+        max_flow_duration: default_max_flow_duration,
+        sock_timestamping,
+        flow_id_header,
+        rtt_backpressure,
+        ..
+    } = options;
+    let http2 = config.as_ref().and_then(|config| config.http2.as_ref());
+    let payload_distribution = config
+        .as_ref()
+        .map(|config| config.payload_distribution)
+        .unwrap_or_default();
+    let inter_packet_gap = config.as_ref().map_or(
+        InterPacketGap {
+            delay_ms: DEFAULT_INTER_PACKET_DELAY_MS,
+            jitter_ms: 0,
+        },
+        |config| InterPacketGap {
+            delay_ms: config.inter_packet_delay_ms,
+            jitter_ms: config.inter_packet_jitter_ms,
+        },
+    );
+    let pipeline_depth = config.as_ref().and_then(|config| config.pipeline_depth);
+    let half_close_write_after_ms = config
+        .as_ref()
+        .and_then(|config| config.half_close_write_after_ms);
+    let max_flow_duration = max_flow_duration(config.as_ref(), default_max_flow_duration);
+    let tcp_fast_open = config.as_ref().is_some_and(|config| config.tcp_fast_open);
+
+    // Sending fails when nobody is subscribed to the events, which is expected.
     match stream_result {
-        Ok(mut conditioned_tcp_stream) => {
+        Ok((conditioned_tcp_stream, connect_timing)) => {
             debug!("Connected to server");
+            let mut conditioned_tcp_stream = match sock_timestamping {
+                Some(mode) => conditioned_tcp_stream.with_tx_timestamping(mode),
+                None => conditioned_tcp_stream,
+            };
+            let _ = flow_events.send(FlowEvent::Started { server: addr });
+            let start = Instant::now();
+
+            // The exchange and the half-close, cut short when they last longer than the maximum
+            // flow duration.
+            let flow = async {
+                let exchange = async {
+                    if let Some(http2) = http2 {
+                        debug!("Sending HTTP/2 requests");
+                        let flow_id_header = http2
+                            .flow_id_header_name
+                            .as_deref()
+                            .or(flow_id_header.as_deref())
+                            .map(|name| (name, session_id));
+                        match http2::run_http2(
+                            &mut conditioned_tcp_stream,
+                            addr,
+                            http2,
+                            flow_id_header,
+                            session_id,
+                            &trace_context,
+                        )
+                        .await
+                        {
+                            Ok((streams, stats)) => return (streams, Some(stats), None),
+                            Err(error) => error!("HTTP/2 handshake failed: {:?}", error),
+                        }
+                    } else if send_data {
+                        debug!("Sending data");
+                        let pipeline_rtt_ms = send_random_data(
+                            &mut conditioned_tcp_stream,
+                            payload_distribution,
+                            inter_packet_gap,
+                            pipeline_depth,
+                        )
+                        .await;
+                        debug!("Data sent");
+                        return (Vec::new(), None, pipeline_rtt_ms);
+                    }
+                    (Vec::new(), None, None)
+                };
 
-            if send_data {
-                debug!("Sending data");
-                send_random_data(&mut conditioned_tcp_stream.stream).await;
-                debug!("Data sent");
+                match half_close_write_after_ms {
+                    Some(after_ms) => {
+                        // The exchange is cut short when it lasts longer.
+                        let deadline =
+                            tokio::time::Instant::from_std(start) + Duration::from_millis(after_ms);
+                        let exchanged = timeout_at(deadline, exchange).await.unwrap_or_default();
+                        sleep_until(deadline).await;
+                        let half_close_rtt_ms = half_close(&mut conditioned_tcp_stream).await;
+                        (exchanged, half_close_rtt_ms)
+                    }
+                    None => (exchange.await, None),
+                }
+            };
+            let ((http2_streams, http2_connection, pipeline_rtt_ms), half_close_rtt_ms) =
+                match max_flow_duration {
+                    Some(max_flow_duration) => {
+                        timeout(max_flow_duration, flow).await.unwrap_or_else(|_| {
+                            warn!(
+                                "Session {} exceeded the maximum flow duration of {:?}, closing it",
+                                session_id, max_flow_duration
+                            );
+                            Default::default()
+                        })
+                    }
+                    None => flow.await,
+                };
+
+            let tcp_fast_open_used = tcp_fast_open
+                .then(|| {
+                    conditioned_tcp_stream
+                        .used_fast_open()
+                        .inspect_err(|error| warn!("Failed to read TCP_INFO: {}", error))
+                        .ok()
+                })
+                .flatten();
+
+            if let Some(rtt_backpressure) = &rtt_backpressure {
+                match conditioned_tcp_stream.rtt() {
+                    Ok(rtt) if !rtt.is_zero() => rtt_backpressure.record_rtt(rtt),
+                    Ok(_) => {}
+                    Err(error) => warn!("Failed to read TCP_INFO: {}", error),
+                }
             }
 
             debug!("Closing connection");
-            conditioned_tcp_stream.stream.shutdown().await.unwrap();
+            if let Err(error) = conditioned_tcp_stream.shutdown().await {
+                // The HTTP/2 connection already shuts the stream down when closing.
+                debug!("Failed to shut down connection: {}", error);
+            }
+
+            let flow = FlowResult {
+                session_id,
+                server: addr,
+                duration_ms: start.elapsed().as_millis() as u64,
+                stats: conditioned_tcp_stream.stats(),
+                connect_timing,
+                half_close_rtt_ms,
+                pipeline_rtt_ms,
+                tcp_fast_open_used,
+                http2_streams,
+                http2_connection,
+            };
+            flow_counters.record_completed(&flow);
+            if let Some((audit_log, history)) = &flow_audit {
+                audit_log.log_flow_config_history(session_id, &history.lock().unwrap());
+            }
+            if let Some(name) = config.as_ref().and_then(|config| config.name.as_deref()) {
+                flow_dependencies.record_completed(name);
+            }
+            let _ = flow_events.send(FlowEvent::Completed(Box::new(flow.clone())));
+            debug!(flow:serde; "Flow completed");
         }
         Err(error) => {
-            error!("Failed to connect: {:?}", error);
+            flow_counters.record_failed();
+            let _ = flow_events.send(FlowEvent::Failed { server: addr });
+            error!("Session {} failed to connect: {:?}", session_id, error);
+        }
+    }
+}
+
+/// Shuts down the write side of the stream and reads until the server closes its own. Returns
+/// the milliseconds that took, None when the stream failed before.
+async fn half_close(stream: &mut ConditionedTcpStream) -> Option<u64> {
+    debug!("Half-closing connection");
+    let start = Instant::now();
+    if let Err(error) = stream.shutdown().await {
+        warn!("Failed to shut down the write side: {}", error);
+        return None;
+    }
+
+    let mut buffer = [0; 16384];
+    loop {
+        match stream.read(&mut buffer).await {
+            Ok(0) => return Some(start.elapsed().as_millis() as u64),
+            Ok(_) => {}
+            Err(error) => {
+                debug!("Failed to read after half-close: {}", error);
+                return None;
+            }
         }
     }
 }
 
-async fn send_random_data(stream: &mut TcpStream) {
-    stream.set_nodelay(true).unwrap();
-    let mut rng = rand::rng();
-    let packets = rng.random_range(50..150);
+/// Time waited by `send_random_data` after every response, see
+/// `FlowConfig::inter_packet_delay_ms` and `FlowConfig::inter_packet_jitter_ms`.
+#[derive(Clone, Copy, Debug)]
+struct InterPacketGap {
+    delay_ms: u64,
+    jitter_ms: u64,
+}
+
+impl InterPacketGap {
+    /// Draws a wait uniformly within `delay_ms` plus or minus `jitter_ms`, zero when below.
+    fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        let jitter_ms = self.jitter_ms.min(i64::MAX as u64) as i64;
+        let offset_ms = rng.random_range(-jitter_ms..=jitter_ms);
+        Duration::from_millis(self.delay_ms.saturating_add_signed(offset_ms))
+    }
+}
+
+/// Exchanges random messages with the server, `pipeline_depth` at a time when set, until a
+/// response can't be read. Returns the time between the first message being sent and the last
+/// response being read in milliseconds, when pipelined.
+async fn send_random_data(
+    stream: &mut ConditionedTcpStream,
+    payload_distribution: PayloadDistribution,
+    inter_packet_gap: InterPacketGap,
+    pipeline_depth: Option<u8>,
+) -> Option<u64> {
+    stream.stream.set_nodelay(true).unwrap();
+    // The thread-local generator can't be held across await points, the task may be moved to
+    // another thread.
+    let mut rng = StdRng::from_rng(&mut rand::rng());
+    let packets = rng.random_range(SEND_DATA_MESSAGES);
+    // Bounds the bytes written before reading, for the responses to fit in the socket buffers.
+    let depth = pipeline_depth
+        .map_or(1, u32::from)
+        .clamp(1, MAX_PIPELINE_DEPTH.into());
+    let first_send = Instant::now();
+    let mut last_response = None;
 
     let mut data = [0; 2048];
-    for _ in 0..packets {
-        let len = rng.random_range(200..2048);
-        rng.fill_bytes(&mut data[..len]);
+    let mut lens = Vec::with_capacity(depth as usize);
+    let mut sent = 0;
+    'exchange: while sent < packets {
+        lens.clear();
+        for _ in 0..depth.min(packets - sent) {
+            let len = rng.random_range(200..2048);
+            // The whole buffer is sent, not only the `len` bytes read back.
+            payload::fill_payload(&mut rng, payload_distribution, &mut data);
+            stream.write_all(&data).await.unwrap();
+            lens.push(len);
+        }
+        for len in &lens {
+            let mut response = vec![0; *len];
+            // The next responses can't be read from a broken stream.
+            if let Err(e) = stream.read_exact(&mut response).await {
+                debug!("Error reading response {}", e);
+                break 'exchange;
+            }
+            last_response = Some(Instant::now());
+        }
+        sent += lens.len() as u32;
+        sleep(inter_packet_gap.sample(&mut rng)).await;
+    }
+    pipeline_depth?;
+    last_response.map(|last_response| (last_response - first_send).as_millis() as u64)
+}
+
+/// Address of the server listening on `port`, the server loopback address of the topology.
+pub fn server_addr(port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)), port)
+}
+
+/// Time after which a health check connection is considered failed.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
-        stream.write_all(&data).await.unwrap();
-        let mut response = vec![0; len];
-        match stream.read_exact(&mut response).await {
-            Err(e) => debug!("Error reading response {}", e),
-            _ => {}
+/// Opens a test connection to the server, without fault injection, and returns the health of
+/// the path to it.
+///
+/// # Arguments
+/// * `addr` - Address and port of the server.
+/// * `options` - settings applied to the connection.
+pub async fn check_health(addr: SocketAddr, options: &ClientOptions) -> HealthStatus {
+    let connect = async {
+        let client_namespace = NetNs::get(&options.namespace)?;
+        connect_sans_tc(
+            client_namespace,
+            addr,
+            options.audit_sockets,
+            options.bind_addr,
+            options.vrf.as_deref(),
+            options.buffer_sizes,
+        )
+        .await
+    };
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, connect).await {
+        Ok(Ok(mut stream)) => {
+            let _ = stream.shutdown().await;
+            HealthStatus::Ok
+        }
+        Ok(Err(error)) => error.to_health_status(),
+        Err(_) => HealthStatus::Down(format!(
+            "Connection timed out after {:?}",
+            HEALTH_CHECK_TIMEOUT
+        )),
+    }
+}
+
+/// Runs a test flow to the server, without fault injection, and returns whether it completed:
+/// the same exchange as the clients, which must complete within twice the expected flow
+/// duration. The connection alone is given `HEALTH_CHECK_TIMEOUT`, as with `check_health`.
+///
+/// # Arguments
+/// * `addr` - Address and port of the server.
+/// * `options` - settings applied to the connection.
+pub async fn check_flow_completion(addr: SocketAddr, options: &ClientOptions) -> HealthStatus {
+    let deadline = (estimated_flow_duration(None, options.send_data) * 2).max(HEALTH_CHECK_TIMEOUT);
+    let (namespace, audit_sockets, bind_addr, vrf, buffer_sizes, send_data) = (
+        options.namespace.clone(),
+        options.audit_sockets,
+        options.bind_addr,
+        options.vrf.clone(),
+        options.buffer_sizes,
+        options.send_data,
+    );
+    // Spawned, for a panic of the exchange to fail the check rather than the caller.
+    let mut flow = tokio::spawn(async move {
+        let client_namespace = NetNs::get(&namespace)?;
+        let mut stream = connect_sans_tc(
+            client_namespace,
+            addr,
+            audit_sockets,
+            bind_addr,
+            vrf.as_deref(),
+            buffer_sizes,
+        )
+        .await?;
+        if send_data {
+            let gap = InterPacketGap {
+                delay_ms: DEFAULT_INTER_PACKET_DELAY_MS,
+                jitter_ms: 0,
+            };
+            send_random_data(&mut stream, PayloadDistribution::default(), gap, None).await;
+        }
+        let _ = stream.shutdown().await;
+        Ok::<_, ClientSocketError>(())
+    });
+    match tokio::time::timeout(deadline, &mut flow).await {
+        Ok(Ok(Ok(()))) => HealthStatus::Ok,
+        Ok(Ok(Err(error))) => error.to_health_status(),
+        Ok(Err(error)) => HealthStatus::Degraded(format!("Test flow failed: {}", error)),
+        Err(_) => {
+            flow.abort();
+            HealthStatus::Down(format!("Test flow did not complete within {:?}", deadline))
         }
-        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Spawns a flow, on the runtime of the next CPU with `--affinity-mode sticky`.
+fn spawn_flow<F>(options: &ClientOptions, flow: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    match &options.flow_runtimes {
+        Some(flow_runtimes) => flow_runtimes.spawn(flow),
+        None => drop(tokio::spawn(flow)),
     }
 }
 
@@ -176,39 +1143,638 @@ async fn send_random_data(stream: &mut TcpStream) {
 /// # Arguments
 /// * `rate` - TPS.
 /// * `port` - Server port.
-/// * `cgroup_path` - cgroup file path where the fault injection program is going to be attached.
-/// * `config_file_path` - path to the configuration file relative to tcp-tester crate root folder.
+/// * `maps` - eBPF maps, only present when traffic shaping is enabled.
+/// * `options` - settings applied to every connection.
 pub async fn start_client_at_rate(
     rate: u32,
     port: u16,
-    enable_traffic_shaping: bool,
-    send_data: bool,
-    cgroup_path: String,
-    config_file_path: String,
+    maps: Option<ClientMaps>,
+    options: ClientOptions,
 ) {
-    let micros_per_txn = (1_000_000 / rate) as u64;
-    let duration = Duration::from_micros(micros_per_txn);
+    let mut rate = options
+        .rate_file
+        .as_ref()
+        .map_or(rate, |rate_file| rate_file.rate());
+    if rate == 0 {
+        error!("The connection rate must be greater than 0");
+        return;
+    }
+
+    // Batches are only worth it when there are map entries to set up for every connection.
+    let batch_size = match maps {
+        Some(_) => options.batch_size.max(1),
+        None => 1,
+    };
+    let duration = request_interval(rate, batch_size);
     let mut interval = tokio::time::interval(duration);
     info!(
         "Generating requests at a rate of {} per sec ({:?} between requests)",
         rate, duration
     );
 
-    let mut num_spawned: u32 = 0;
+    let mut num_spawned: u64 = 0;
     loop {
-        let client_address = format!("2.2.2.2:{}", port).parse().unwrap();
-        let cgp = cgroup_path.clone();
-        let cfp = config_file_path.clone();
-        tokio::spawn(async move {
-            run_client(client_address, enable_traffic_shaping, send_data, cgp, cfp).await
-        });
+        let client_address = server_addr(port);
+        if let Some(rate_limiter) = &options.rate_limiter {
+            rate_limiter.acquire(client_address.ip(), batch_size).await;
+        }
+        let opts = options.clone();
+        match maps.clone() {
+            Some(maps) if batch_size > 1 => {
+                let addrs = vec![client_address; batch_size as usize];
+                spawn_flow(&options, run_client_batch(addrs, maps, opts));
+            }
+            maps => {
+                // The logs of the task carry the flow ID, also used as session ID of the flow.
+                let flow = FLOW_ID.scope(Uuid::new_v4(), run_client(client_address, maps, opts));
+                spawn_flow(&options, flow);
+            }
+        }
 
-        num_spawned += 1;
-        if num_spawned == rate {
+        num_spawned += u64::from(batch_size);
+        if num_spawned >= u64::from(rate) {
             info!("Initiated {num_spawned} transactions");
             num_spawned = 0;
         }
 
         interval.tick().await;
+        // Skipping every other tick halves the rate.
+        if let Some(rtt_backpressure) = &options.rtt_backpressure {
+            if rtt_backpressure.throttled() {
+                interval.tick().await;
+            }
+        }
+        if let Some(new_rate) = options
+            .rate_file
+            .as_ref()
+            .map(|rate_file| rate_file.rate())
+            .filter(|new_rate| *new_rate != rate)
+        {
+            rate = new_rate;
+            let duration = request_interval(rate, batch_size);
+            interval = tokio::time::interval(duration);
+            // The first tick completes immediately, the next request waits for the new period.
+            interval.tick().await;
+            debug!("Generating requests every {:?}", duration);
+        }
+    }
+}
+
+/// Returns the time between the batches of `batch_size` requests made at `rate` per second.
+fn request_interval(rate: u32, batch_size: u32) -> Duration {
+    let micros_per_txn = (1_000_000 / rate) as u64;
+    Duration::from_micros(micros_per_txn * batch_size as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcp_tester_common::{
+        CorruptionSpec, DelayConditioner, DelayDistribution, Http2Config,
+        FLOW_CONFIG_SCHEMA_VERSION,
+    };
+
+    fn client_options() -> ClientOptions {
+        ClientOptions {
+            namespace: "test".into(),
+            send_data: false,
+            config_file_path: String::new(),
+            config_format: None,
+            config_overlay: None,
+            audit_sockets: false,
+            batch_size: 1,
+            flow_counters: Arc::default(),
+            flow_dependencies: Arc::default(),
+            flow_runtimes: None,
+            bind_addr: None,
+            vrf: None,
+            buffer_sizes: BufferSizes::default(),
+            remote_config: Arc::default(),
+            flow_events: broadcast::channel(1).0,
+            max_flow_duration: None,
+            sock_timestamping: None,
+            flow_id_header: None,
+            rate_limiter: None,
+            rtt_backpressure: None,
+            rate_file: None,
+        }
+    }
+
+    #[test]
+    fn test_classifier_attach_point_sees_its_direction() {
+        // Keys the sockops program stores the profiles of a client flow under.
+        let egress_key = FlowKey::builder()
+            .src_ip(Ipv4Addr::new(1, 1, 1, 1))
+            .dst_ip(Ipv4Addr::new(2, 2, 2, 2))
+            .src_port(40000)
+            .dst_port(8080)
+            .build();
+        let profiles = [
+            (egress_key, Direction::EGRESS),
+            (egress_key.reverse(), Direction::INGRESS),
+        ];
+
+        for direction in [Direction::EGRESS, Direction::INGRESS] {
+            let (interface, attach_type) = classifier_attach_point(direction);
+            // The delays are only applied to the packets leaving an interface.
+            assert_eq!(attach_type, TcAttachType::Egress);
+            // The packets leaving towards the server are the ones sent by the client.
+            let packet_key = if interface == SERVER_SIDE_INTERFACE {
+                egress_key
+            } else {
+                egress_key.reverse()
+            };
+            let (_, profile_direction) = profiles
+                .iter()
+                .find(|(key, _)| (key.sip, key.sport) == (packet_key.sip, packet_key.sport))
+                .unwrap();
+            assert!(*profile_direction == direction);
+        }
+    }
+
+    #[test]
+    fn test_max_flow_duration_is_the_smallest() {
+        let mut config = parse_flow_config(
+            include_str!("../../config/no_faults.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        let secs = Duration::from_secs;
+        assert_eq!(max_flow_duration(Some(&config), None), None);
+        assert_eq!(max_flow_duration(None, Some(secs(5))), Some(secs(5)));
+
+        config.max_flow_duration_ms = Some(2000);
+        assert_eq!(max_flow_duration(Some(&config), None), Some(secs(2)));
+        assert_eq!(
+            max_flow_duration(Some(&config), Some(secs(5))),
+            Some(secs(2))
+        );
+        assert_eq!(
+            max_flow_duration(Some(&config), Some(secs(1))),
+            Some(secs(1))
+        );
+    }
+
+    #[test]
+    fn test_parse_flow_config_json_and_yaml() {
+        let json = include_str!("../../config/no_faults.json");
+        let yaml = r#"
+# Comments are allowed in YAML.
+ingress:
+  selector: {data_offset_min: 0, data_offset_max: 0, flags: 0}
+  conditioner:
+    DropPacket: {count: 0, range: 0}
+egress:
+  selector: {data_offset_min: 0, data_offset_max: 0, flags: 0}
+  conditioner:
+    DropPacket: {count: 0, range: 0}
+"#;
+        let from_json = parse_flow_config(json, ConfigFormat::Json).unwrap();
+        let from_yaml = parse_flow_config(yaml, ConfigFormat::Yaml).unwrap();
+        assert_eq!(format!("{:?}", from_json), format!("{:?}", from_yaml));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_parse_flow_config_toml() {
+        let toml = r#"
+# Comments are allowed in TOML.
+[ingress.selector]
+data_offset_min = 0
+data_offset_max = 0
+flags = 0
+
+[ingress.conditioner.DropPacket]
+count = 0
+range = 0
+
+[egress]
+selector = { data_offset_min = 0, data_offset_max = 0, flags = 0 }
+conditioner = { DropPacket = { count = 0, range = 0 } }
+"#;
+        let from_json = parse_flow_config(
+            include_str!("../../config/no_faults.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        let from_toml = parse_flow_config(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(format!("{:?}", from_json), format!("{:?}", from_toml));
+
+        // The nested structs are written as tables, and read back identically.
+        let mut config = from_json;
+        config.http2 = Some(Http2Config {
+            num_streams: 4,
+            request_interval_ms: 10,
+            path: "/index.html".into(),
+            method: "POST".into(),
+            flow_id_header_name: None,
+        });
+        config.opentelemetry_context = Some(std::collections::BTreeMap::from([(
+            "traceparent".to_string(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string(),
+        )]));
+        let written = toml::to_string(&config).unwrap();
+        assert!(written.contains("[http2]"), "{}", written);
+        let round_trip = parse_flow_config(&written, ConfigFormat::Toml).unwrap();
+        assert_eq!(format!("{:?}", round_trip), format!("{:?}", config));
+    }
+
+    #[test]
+    fn test_estimated_flow_duration() {
+        let mut config = parse_flow_config(
+            include_str!("../../config/no_faults.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(estimated_flow_duration(Some(&config), false), ESTIMATED_RTT);
+        // 100 messages, 10ms apart, with a 1ms round-trip time.
+        assert_eq!(
+            estimated_flow_duration(Some(&config), true),
+            Duration::from_millis(1100)
+        );
+
+        // 4ms more per round trip.
+        config.egress.conditioner = Conditioner::Delay(DelayConditioner {
+            count: 0,
+            offset: 2_000_000,
+            jitter: 0,
+        });
+        config.ingress = config.egress;
+        assert_eq!(
+            estimated_flow_duration(Some(&config), true),
+            Duration::from_millis(1500)
+        );
+        // 34 pipelines of up to 3 messages.
+        config.pipeline_depth = Some(3);
+        assert_eq!(
+            estimated_flow_duration(Some(&config), true),
+            Duration::from_millis(510)
+        );
+        config.max_flow_duration_ms = Some(500);
+        assert_eq!(
+            estimated_flow_duration(Some(&config), true),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_check_cross_fields_pipeline_depth() {
+        let mut config = parse_flow_config(
+            include_str!("../../config/no_faults.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        for (depth, valid) in [
+            (0, false),
+            (1, true),
+            (MAX_PIPELINE_DEPTH, true),
+            (255, false),
+        ] {
+            config.pipeline_depth = Some(depth);
+            assert_eq!(check_cross_fields(&config).is_ok(), valid, "{depth}");
+        }
+    }
+
+    #[test]
+    fn test_parse_flow_config_over_defaults() {
+        let mut defaults = parse_flow_config(
+            include_str!("../../config/packet_loss.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        defaults.max_flow_duration_ms = Some(2000);
+        defaults.bind_addr = Some(Ipv4Addr::new(1, 1, 1, 1).into());
+
+        let yaml = r#"
+bind_addr: 2.2.2.2
+egress:
+  conditioner:
+    DropPacket: {count: 0}
+"#;
+        let config = parse_flow_config_over(Some(defaults), yaml, ConfigFormat::Yaml).unwrap();
+        assert_eq!(config.max_flow_duration_ms, Some(2000));
+        assert_eq!(config.bind_addr, Some(Ipv4Addr::new(2, 2, 2, 2).into()));
+        assert!(matches!(
+            config.egress.conditioner,
+            Conditioner::DropPacket(drop) if drop.count == 0
+        ));
+        assert!(matches!(
+            config.ingress.conditioner,
+            Conditioner::DropPacket(drop) if drop.count == 1
+        ));
+    }
+
+    #[test]
+    fn test_inter_packet_gap_sample() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let gap = InterPacketGap {
+            delay_ms: 10,
+            jitter_ms: 0,
+        };
+        assert_eq!(gap.sample(&mut rng), Duration::from_millis(10));
+
+        let gap = InterPacketGap {
+            delay_ms: 5,
+            jitter_ms: 10,
+        };
+        let samples: Vec<_> = (0..1000).map(|_| gap.sample(&mut rng)).collect();
+        assert!(samples.iter().all(|gap| *gap <= Duration::from_millis(15)));
+        // Clamped to zero for about a third of the draws, below -5ms.
+        assert!(samples.iter().any(|gap| gap.is_zero()));
+    }
+
+    #[test]
+    fn test_flow_config_proto_round_trip() {
+        let mut config = parse_flow_config(
+            include_str!("../../config/packet_loss.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        config.bind_addr = Some(Ipv4Addr::new(1, 1, 1, 1).into());
+        config.payload_distribution = PayloadDistribution::Compressible(2.0);
+        config.capture_path = Some("/tmp/flow-{port}.pcap".into());
+        config.socks5_proxy = Some("10.0.0.3:1080".parse().unwrap());
+        config.tcp_fast_open = true;
+        config.name = Some("data".into());
+        config.depends_on = Some("auth".into());
+        config.failure_injection_start_delay_ms = Some(500);
+        config.tcp_user_timeout_ms = Some(3000);
+        config.bandwidth_kbps = Some(8000);
+        config.targeted_corruption = Some(vec![CorruptionSpec {
+            offset: 0,
+            length: 4,
+            pattern: vec![0xde, 0xad],
+        }]);
+        config.http2 = Some(Http2Config {
+            num_streams: 4,
+            request_interval_ms: 10,
+            path: "/index.html".into(),
+            method: "POST".into(),
+            flow_id_header_name: Some("X-NFM-Flow-ID".into()),
+        });
+        config.delay_distribution = DelayDistribution::Pareto {
+            scale_ms: 5.0,
+            shape: 1.5,
+        };
+        config.inter_packet_delay_ms = 20;
+        config.inter_packet_jitter_ms = 15;
+        config.pipeline_depth = Some(4);
+
+        let round_trip = FlowConfig::from_proto(config.to_proto()).unwrap();
+        assert_eq!(format!("{:?}", round_trip), format!("{:?}", config));
+
+        let mut proto = config.to_proto();
+        proto.egress = None;
+        assert!(FlowConfig::from_proto(proto).is_err());
+    }
+
+    /// Returns whether `value` is valid against `schema`, a subset of the JSON Schema keywords
+    /// good enough for the schema of `FlowConfig`, whose `$ref` point to `definitions`. Unlike
+    /// JSON Schema, the properties unknown to the schema are invalid, to catch the typos.
+    #[cfg(feature = "schema")]
+    fn validates(
+        schema: &serde_json::Value,
+        definitions: &serde_json::Value,
+        value: &serde_json::Value,
+    ) -> bool {
+        use serde_json::Value;
+
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/definitions/");
+            return validates(&definitions[name], definitions, value);
+        }
+        let branches = |keyword: &str| schema[keyword].as_array().cloned().unwrap_or_default();
+        let matching = |keyword: &str| {
+            branches(keyword)
+                .iter()
+                .filter(|branch| validates(branch, definitions, value))
+                .count()
+        };
+        if schema.get("anyOf").is_some() && matching("anyOf") == 0 {
+            return false;
+        }
+        if schema.get("oneOf").is_some() && matching("oneOf") != 1 {
+            return false;
+        }
+        if schema.get("allOf").is_some() && matching("allOf") != branches("allOf").len() {
+            return false;
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return false;
+            }
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(schema_type) => vec![schema_type.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let is_type = |schema_type: &&str| match *schema_type {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            _ => false,
+        };
+        if !types.is_empty() && !types.iter().any(is_type) {
+            return false;
+        }
+        // The schemas combining others leave the properties to them.
+        let Some(object) = value.as_object().filter(|_| schema.get("type").is_some()) else {
+            return true;
+        };
+        let required = branches("required");
+        if !required
+            .iter()
+            .all(|name| name.as_str().is_some_and(|name| object.contains_key(name)))
+        {
+            return false;
+        }
+        object.iter().all(|(name, property)| {
+            match (
+                schema["properties"].get(name),
+                &schema["additionalProperties"],
+            ) {
+                (Some(property_schema), _) => validates(property_schema, definitions, property),
+                (None, additional @ Value::Object(_)) => {
+                    validates(additional, definitions, property)
+                }
+                (None, _) => false,
+            }
+        })
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_flow_config_schema_validates_example_configs() {
+        let schema = serde_json::to_value(FlowConfig::json_schema()).unwrap();
+        let definitions = &schema["definitions"];
+        for contents in [
+            include_str!("../../config/no_faults.json"),
+            include_str!("../../config/packet_loss.json"),
+        ] {
+            let config: serde_json::Value = serde_json::from_str(contents).unwrap();
+            assert!(validates(&schema, definitions, &config), "{}", contents);
+        }
+
+        // All the fields set, as serialized.
+        let mut config = parse_flow_config(
+            include_str!("../../config/packet_loss.json"),
+            ConfigFormat::Json,
+        )
+        .unwrap();
+        config.path_flap = Some(tcp_tester_common::PathFlapConfig {
+            primary: config.egress,
+            secondary: config.ingress,
+            flap_interval_ms: 1000,
+        });
+        config.bind_addr = Some(Ipv4Addr::new(1, 1, 1, 1).into());
+        config.payload_distribution = PayloadDistribution::Compressible(2.0);
+        config.opentelemetry_context = Some([("traceparent".into(), "00-01-02-01".into())].into());
+        config.socks5_proxy = Some("10.0.0.3:1080".parse().unwrap());
+        let config = serde_json::to_value(&config).unwrap();
+        assert!(validates(&schema, definitions, &config), "{}", config);
+
+        let mut typo = config.clone();
+        typo["egress"]["conditioner"] = serde_json::json!({"Drop": {"count": 1, "range": 0}});
+        assert!(!validates(&schema, definitions, &typo));
+        let mut typo = config;
+        typo["tcp_fast_opn"] = true.into();
+        assert!(!validates(&schema, definitions, &typo));
+    }
+
+    #[test]
+    fn test_parse_flow_config_migrates_old_versions() {
+        // Version 1, in the legacy flat format.
+        let flat = r#"{
+            "selector": {"data_offset_min": 0, "data_offset_max": 0, "flags": 0},
+            "conditioner": {"DropPacket": {"count": 1, "range": 10}}
+        }"#;
+        let config = parse_flow_config(flat, ConfigFormat::Json).unwrap();
+        assert_eq!(config.schema_version, FLOW_CONFIG_SCHEMA_VERSION);
+        assert_eq!(
+            format!("{:?}", config.ingress),
+            format!("{:?}", config.egress)
+        );
+
+        let newer = format!(
+            r#"{{"schema_version": {}}}"#,
+            FLOW_CONFIG_SCHEMA_VERSION + 1
+        );
+        assert!(parse_flow_config(&newer, ConfigFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_parse_flow_config_invalid_yaml() {
+        assert!(parse_flow_config("ingress: [unclosed", ConfigFormat::Yaml).is_err());
+        // Valid YAML, but not a flow configuration.
+        assert!(parse_flow_config("ingress: 1", ConfigFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_config_format_from_path() {
+        assert_eq!(ConfigFormat::from_path("a/b.yaml"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("b.yml"), ConfigFormat::Yaml);
+        #[cfg(feature = "toml")]
+        assert_eq!(ConfigFormat::from_path("c.toml"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path("b.json"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path("b"), ConfigFormat::Json);
+    }
+
+    #[tokio::test]
+    async fn test_start_client_at_zero_rate_returns() {
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            start_client_at_rate(0, 8080, None, client_options()),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[cfg(feature = "requires-bpf")]
+    #[tokio::test]
+    async fn test_implausible_socket_config_applies_no_fault() {
+        use crate::cli::EbpfLogLevel;
+        use nix::sys::socket as sockopt;
+        use std::os::fd::AsRawFd;
+        use tcp_tester::os;
+        use tcp_tester_common::{Selector, SocketKey, MAX_DELAY_NS};
+        use tokio::net::{TcpListener, TcpSocket};
+
+        let capabilities = KernelCapabilities::detect();
+        let mut bpf = ebpf_loader::load_ebpf_program(ebpf_loader::LoadOptions {
+            capabilities: &capabilities,
+            log_level: EbpfLogLevel::Info,
+            object_path: None,
+            perf_event_sample_rate: None,
+            map_pin_path: None,
+        })
+        .unwrap();
+        // Attached to the cgroup of the test, for the sockops program to see its connections.
+        let program: &mut SockOps = bpf
+            .program_mut("tcp_tester_sockops")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        program.load().unwrap();
+        let cgroup = File::open(ebpf_loader::detect_cgroup_path().unwrap()).unwrap();
+        program
+            .attach(cgroup, get_attach_mode(&capabilities))
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = TcpSocket::new_v4().unwrap();
+        let cookie = sockopt::getsockopt(socket.as_raw_fd(), os::SoCookie).unwrap();
+        let inverted_selector = FaultProfile {
+            selector: Selector {
+                data_offset_min: 10,
+                data_offset_max: 0,
+                flags: 0,
+            },
+            ..FaultProfile::NONE
+        };
+        let excessive_delay = FaultProfile {
+            conditioner: Conditioner::Delay(DelayConditioner {
+                count: 0,
+                offset: MAX_DELAY_NS + 1,
+                jitter: 0,
+            }),
+            ..FaultProfile::NONE
+        };
+        let mut socket_config: HashMap<_, SocketKey, FaultProfile> =
+            HashMap::try_from(bpf.map_mut("SOCKET_CONFIG").unwrap()).unwrap();
+        socket_config
+            .insert(
+                SocketKey::new(cookie, Direction::EGRESS),
+                inverted_selector,
+                0,
+            )
+            .unwrap();
+        socket_config
+            .insert(
+                SocketKey::new(cookie, Direction::INGRESS),
+                excessive_delay,
+                0,
+            )
+            .unwrap();
+
+        let server_addr = listener.local_addr().unwrap();
+        let stream = socket.connect(server_addr).await.unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        let egress_key = FlowKey::builder()
+            .src_ip([127, 0, 0, 1].into())
+            .dst_ip([127, 0, 0, 1].into())
+            .src_port(local_addr.port())
+            .dst_port(server_addr.port())
+            .build();
+        // Read before the stream is closed, which removes the entries.
+        let flow_config: HashMap<_, FlowKey, FlowState> =
+            HashMap::try_from(bpf.map("FLOW_CONFIG").unwrap()).unwrap();
+        for key in [egress_key, egress_key.reverse()] {
+            assert_eq!(flow_config.get(&key, 0).unwrap().config, FaultProfile::NONE);
+        }
     }
 }
@@ -1,10 +1,16 @@
+use aya::maps::MapError;
 use nix::errno::Errno;
+use std::io::ErrorKind;
 
+// The variants are named after the errors they wrap.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ClientSocketError {
     SocketError(Errno),
     IoError(std::io::Error),
     NsError(netns_rs::Error),
+    /// The socket could not be registered in `SOCKET_CONFIG`, e.g. because the map is full.
+    MapError(MapError),
 }
 
 impl From<std::io::Error> for ClientSocketError {
@@ -18,3 +24,55 @@ impl From<netns_rs::Error> for ClientSocketError {
         ClientSocketError::NsError(e)
     }
 }
+
+impl From<MapError> for ClientSocketError {
+    fn from(e: MapError) -> Self {
+        ClientSocketError::MapError(e)
+    }
+}
+
+/// Health of the path to the servers, as reported by a test connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HealthStatus {
+    Ok,
+    /// The servers are reachable, but the connection failed for a local or transient reason.
+    Degraded(String),
+    /// The servers are unreachable.
+    Down(String),
+}
+
+impl HealthStatus {
+    /// Returns the HTTP status code of the health status.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            HealthStatus::Ok => 200,
+            HealthStatus::Degraded(_) => 207,
+            HealthStatus::Down(_) => 503,
+        }
+    }
+}
+
+impl ClientSocketError {
+    /// Returns the health status of a test connection that failed with this error.
+    pub fn to_health_status(&self) -> HealthStatus {
+        match self {
+            ClientSocketError::IoError(error) => match error.kind() {
+                ErrorKind::ConnectionRefused
+                | ErrorKind::HostUnreachable
+                | ErrorKind::NetworkUnreachable
+                | ErrorKind::TimedOut => HealthStatus::Down(error.to_string()),
+                _ => HealthStatus::Degraded(error.to_string()),
+            },
+            ClientSocketError::SocketError(errno) => {
+                HealthStatus::Degraded(format!("Socket option failed: {}", errno))
+            }
+            // The client namespace is gone, no connection can be made.
+            ClientSocketError::NsError(error) => {
+                HealthStatus::Down(format!("Namespace unavailable: {}", error))
+            }
+            ClientSocketError::MapError(error) => {
+                HealthStatus::Degraded(format!("Failed to register the socket: {}", error))
+            }
+        }
+    }
+}
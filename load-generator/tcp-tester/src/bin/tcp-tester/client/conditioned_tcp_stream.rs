@@ -1,5 +0,0 @@
-use tokio::net::TcpStream;
-
-pub struct ConditionedTcpStream {
-    pub stream: TcpStream,
-}
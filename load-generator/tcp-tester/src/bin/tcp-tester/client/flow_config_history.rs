@@ -0,0 +1,127 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tcp_tester_common::FaultProfile;
+
+/// History of a flow's `FLOW_CONFIG` entries, shared by the flow and the task that updates them.
+pub type SharedFlowConfigHistory = Arc<Mutex<FlowConfigHistory>>;
+
+/// Egress and ingress fault profiles of a flow, in this order.
+pub type FlowProfiles = [FaultProfile; 2];
+
+/// Fault profiles applied to an established flow over its lifetime: the ones it connected with and
+/// a copy of both directions on every update of its `FLOW_CONFIG` entries, the past entries never
+/// being modified.
+#[derive(Debug)]
+pub struct FlowConfigHistory {
+    start: Instant,
+    initial: FlowProfiles,
+    changes: Vec<(Instant, FlowProfiles)>,
+}
+
+/// Update of one direction of a flow in the timeline of `FlowConfigHistory::timeline`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FlowConfigChange {
+    /// Time between the flow connecting and the update.
+    pub after_ms: u64,
+    pub direction: &'static str,
+    pub old: FaultProfile,
+    pub new: FaultProfile,
+}
+
+impl FlowConfigHistory {
+    pub fn new(initial: FlowProfiles) -> FlowConfigHistory {
+        FlowConfigHistory {
+            start: Instant::now(),
+            initial,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn shared(initial: FlowProfiles) -> SharedFlowConfigHistory {
+        Arc::new(Mutex::new(FlowConfigHistory::new(initial)))
+    }
+
+    /// Records the profiles the flow has been updated with.
+    pub fn record(&mut self, profiles: FlowProfiles) {
+        self.record_at(Instant::now(), profiles);
+    }
+
+    fn record_at(&mut self, at: Instant, profiles: FlowProfiles) {
+        self.changes.push((at, profiles));
+    }
+
+    pub fn initial(&self) -> FlowProfiles {
+        self.initial
+    }
+
+    /// Returns the updates of the flow which changed a profile, one per direction changed, in
+    /// chronological order.
+    pub fn timeline(&self) -> Vec<FlowConfigChange> {
+        let mut timeline = Vec::new();
+        let mut current = self.initial;
+        for (at, profiles) in &self.changes {
+            let after_ms = at.saturating_duration_since(self.start).as_millis() as u64;
+            for (direction, (old, new)) in ["egress", "ingress"]
+                .into_iter()
+                .zip(current.iter().zip(profiles))
+            {
+                if old != new {
+                    timeline.push(FlowConfigChange {
+                        after_ms,
+                        direction,
+                        old: *old,
+                        new: *new,
+                    });
+                }
+            }
+            current = *profiles;
+        }
+        timeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tcp_tester_common::{Conditioner, DropPacketConditioner};
+
+    #[test]
+    fn test_flow_config_history_timeline() {
+        let dropping = FaultProfile {
+            conditioner: Conditioner::DropPacket(DropPacketConditioner { count: 1, range: 4 }),
+            ..FaultProfile::NONE
+        };
+        let mut history = FlowConfigHistory::new([FaultProfile::NONE, FaultProfile::NONE]);
+        let start = history.start;
+        history.record_at(
+            start + Duration::from_millis(100),
+            [dropping, FaultProfile::NONE],
+        );
+        // Unchanged profiles don't appear in the timeline.
+        history.record_at(
+            start + Duration::from_millis(200),
+            [dropping, FaultProfile::NONE],
+        );
+        history.record_at(start + Duration::from_millis(300), [dropping, dropping]);
+
+        assert_eq!(
+            history.timeline(),
+            vec![
+                FlowConfigChange {
+                    after_ms: 100,
+                    direction: "egress",
+                    old: FaultProfile::NONE,
+                    new: dropping,
+                },
+                FlowConfigChange {
+                    after_ms: 300,
+                    direction: "ingress",
+                    old: FaultProfile::NONE,
+                    new: dropping,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Flows sharing a name, see `FlowConfig::name`.
+#[derive(Debug, Default)]
+struct NamedFlows {
+    completed: AtomicBool,
+    notify: Notify,
+}
+
+/// Completions of the named flows, shared by all the clients, for the flows depending on them
+/// (see `FlowConfig::depends_on`) to start after.
+#[derive(Debug, Default)]
+pub struct FlowDependencies {
+    flows: Mutex<HashMap<String, Arc<NamedFlows>>>,
+}
+
+impl FlowDependencies {
+    fn flows(&self, name: &str) -> Arc<NamedFlows> {
+        let mut flows = self.flows.lock().unwrap();
+        flows.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Records the completion of a flow named `name`, starting the flows waiting for it.
+    pub fn record_completed(&self, name: &str) {
+        let flows = self.flows(name);
+        flows.completed.store(true, Ordering::Release);
+        flows.notify.notify_waiters();
+    }
+
+    /// Waits until a flow named `name` has completed, returning right away if one already did.
+    pub async fn wait_for(&self, name: &str) {
+        let flows = self.flows(name);
+        // Registered before checking, so that a completion in between is not missed.
+        let notified = flows.notify.notified();
+        if flows.completed.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn test_wait_for_completion() {
+        let dependencies = Arc::new(FlowDependencies::default());
+        let waiting = tokio::spawn({
+            let dependencies = dependencies.clone();
+            async move { dependencies.wait_for("auth").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        dependencies.record_completed("other");
+        dependencies.record_completed("auth");
+        timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The flows starting after the completion don't wait.
+        timeout(Duration::from_secs(1), dependencies.wait_for("auth"))
+            .await
+            .unwrap();
+    }
+}
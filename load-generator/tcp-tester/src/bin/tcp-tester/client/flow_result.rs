@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+use super::http2::{Http2StreamResult, HttpMultiplexStats};
+use super::socket_builder::ConnectionTimingBreakdown;
+use tcp_tester::conditioned_tcp_stream::Stats;
+
+/// Outcome of a client flow, reported once the connection is closed.
+#[derive(Clone, Debug, Serialize)]
+pub struct FlowResult {
+    /// Logical session of the client, shared by all its connection attempts.
+    pub session_id: Uuid,
+    /// Address and port of the server.
+    pub server: SocketAddr,
+    /// Time between the connection being established and closed.
+    pub duration_ms: u64,
+    /// I/O statistics of the connection.
+    pub stats: Stats,
+    /// Time spent in every step of the connection set up, when traffic shaping is enabled and
+    /// the connection is not part of a batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timing: Option<ConnectionTimingBreakdown>,
+    /// Time between the client shutting down its write side and the server closing its own,
+    /// when the flow is half-closed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub half_close_rtt_ms: Option<u64>,
+    /// Time between the first message of the flow being sent and its last response being read,
+    /// when `FlowConfig::pipeline_depth` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipeline_rtt_ms: Option<u64>,
+    /// Whether the connection was set up with TCP Fast Open, when enabled in the flow
+    /// configuration. The first connection to a server only gets the cookie.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_fast_open_used: Option<bool>,
+    /// Outcome of the HTTP/2 streams, when HTTP/2 is enabled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub http2_streams: Vec<Http2StreamResult>,
+    /// Outcome of the HTTP/2 connection the streams were multiplexed on, when HTTP/2 is enabled
+    /// and the handshake succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http2_connection: Option<HttpMultiplexStats>,
+}
+
+/// Lifecycle event of a client flow, published to the control plane.
+#[derive(Clone, Debug)]
+pub enum FlowEvent {
+    Started { server: SocketAddr },
+    Completed(Box<FlowResult>),
+    Failed { server: SocketAddr },
+}
+
+/// Totals of the flows run by all the clients, updated as the flows complete.
+#[derive(Debug, Default)]
+pub struct FlowCounters {
+    completed: AtomicU64,
+    failed: AtomicU64,
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+}
+
+impl FlowCounters {
+    pub fn record_completed(&self, flow: &FlowResult) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(flow.stats.bytes_written, Ordering::Relaxed);
+        self.bytes_read
+            .fetch_add(flow.stats.bytes_read, Ordering::Relaxed);
+    }
+
+    pub fn record_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> FlowTotals {
+        FlowTotals {
+            flows_completed: self.completed.load(Ordering::Relaxed),
+            flows_failed: self.failed.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the `FlowCounters`.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct FlowTotals {
+    pub flows_completed: u64,
+    /// Flows that failed to connect.
+    pub flows_failed: u64,
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+}
+
+impl FlowTotals {
+    /// Returns the totals accumulated since the `earlier` snapshot.
+    pub fn since(&self, earlier: &FlowTotals) -> FlowTotals {
+        FlowTotals {
+            flows_completed: self.flows_completed - earlier.flows_completed,
+            flows_failed: self.flows_failed - earlier.flows_failed,
+            bytes_written: self.bytes_written - earlier.bytes_written,
+            bytes_read: self.bytes_read - earlier.bytes_read,
+        }
+    }
+}
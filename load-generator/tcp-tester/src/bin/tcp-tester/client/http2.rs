@@ -0,0 +1,209 @@
+use bytes::Bytes;
+use futures::future::join_all;
+use h2::client::SendRequest;
+use http::{Method, Request};
+use log::warn;
+use opentelemetry::Context;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tcp_tester_common::Http2Config;
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::telemetry;
+use tcp_tester::conditioned_tcp_stream::ConditionedTcpStream;
+
+/// Outcome of an HTTP/2 stream.
+#[derive(Clone, Debug, Serialize)]
+pub struct Http2StreamResult {
+    /// Time between the request being sent and the response being fully received.
+    pub latency_us: Option<u64>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    /// Whether the stream failed because the server sent a GOAWAY, a connection-level event.
+    #[serde(skip)]
+    go_away: bool,
+}
+
+/// Outcome of the HTTP/2 connection multiplexing the streams of a flow, to tell the failures of
+/// the streams themselves from the connection-level events failing them, e.g. a GOAWAY.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HttpMultiplexStats {
+    /// ID of the flow the connection belongs to, `FlowResult::session_id`.
+    pub connection_id: Uuid,
+    pub streams_initiated: u32,
+    /// Streams which got a response.
+    pub streams_completed: u32,
+    pub streams_failed: u32,
+    /// Whether the server sent a GOAWAY, failing the streams it did not process.
+    pub goaway_received: bool,
+}
+
+impl HttpMultiplexStats {
+    fn new(
+        connection_id: Uuid,
+        streams: &[Http2StreamResult],
+        connection_go_away: bool,
+    ) -> HttpMultiplexStats {
+        let count = |filter: fn(&Http2StreamResult) -> bool| {
+            streams.iter().filter(|stream| filter(stream)).count() as u32
+        };
+        HttpMultiplexStats {
+            connection_id,
+            streams_initiated: streams.len() as u32,
+            streams_completed: count(|stream| stream.status.is_some()),
+            streams_failed: count(|stream| stream.error.is_some()),
+            goaway_received: connection_go_away || streams.iter().any(|stream| stream.go_away),
+        }
+    }
+}
+
+/// Sends a request on a new stream and reads the whole response.
+async fn send_request(
+    mut send_request: SendRequest<Bytes>,
+    request: Request<()>,
+) -> Result<u16, h2::Error> {
+    send_request = send_request.ready().await?;
+    let (response, _) = send_request.send_request(request, true)?;
+    let response = response.await?;
+    let status = response.status().as_u16();
+
+    let mut body = response.into_body();
+    while let Some(data) = body.data().await {
+        let data = data?;
+        let _ = body.flow_control().release_capacity(data.len());
+    }
+    Ok(status)
+}
+
+async fn run_stream(
+    send_request_handle: SendRequest<Bytes>,
+    request: Result<Request<()>, http::Error>,
+    delay: Duration,
+) -> Http2StreamResult {
+    sleep(delay).await;
+    let request = match request {
+        Ok(request) => request,
+        Err(error) => {
+            return Http2StreamResult {
+                latency_us: None,
+                status: None,
+                error: Some(error.to_string()),
+                go_away: false,
+            }
+        }
+    };
+
+    let start = Instant::now();
+    match send_request(send_request_handle, request).await {
+        Ok(status) => Http2StreamResult {
+            latency_us: Some(start.elapsed().as_micros() as u64),
+            status: Some(status),
+            error: None,
+            go_away: false,
+        },
+        Err(error) => Http2StreamResult {
+            latency_us: None,
+            status: None,
+            error: Some(error.to_string()),
+            go_away: error.is_go_away(),
+        },
+    }
+}
+
+/// Sends HTTP/2 requests to the server over the stream, multiplexed on concurrent streams.
+/// Returns the outcome of every stream and of the connection, once the HTTP/2 connection is
+/// closed. Only the handshake failing is an error, the streams failed by a connection error
+/// are reported along with it.
+///
+/// # Arguments
+/// * `stream` - connection to the server.
+/// * `addr` - address and port of the server, used as authority of the requests.
+/// * `config` - requests to send.
+/// * `flow_id_header` - name of the header carrying the flow ID in every request, and the ID.
+/// * `connection_id` - ID of the flow, see `HttpMultiplexStats::connection_id`.
+/// * `trace_context` - OpenTelemetry context injected in the headers of every request, so the
+///   server traces are linked to the client one.
+pub async fn run_http2(
+    stream: &mut ConditionedTcpStream,
+    addr: SocketAddr,
+    config: &Http2Config,
+    flow_id_header: Option<(&str, Uuid)>,
+    connection_id: Uuid,
+    trace_context: &Context,
+) -> Result<(Vec<Http2StreamResult>, HttpMultiplexStats), h2::Error> {
+    let (send_request_handle, connection) = h2::client::handshake(stream).await?;
+
+    let uri = format!("http://{}{}", addr, config.path);
+    let streams = (0..config.num_streams).map(|stream| {
+        let request = Method::from_bytes(config.method.as_bytes())
+            .map_err(http::Error::from)
+            .and_then(|method| {
+                let mut request = Request::builder().method(method).uri(&uri);
+                if let Some((name, flow_id)) = flow_id_header {
+                    request = request.header(name, flow_id.to_string());
+                }
+                request.body(())
+            })
+            .map(|mut request| {
+                telemetry::inject_context(trace_context, request.headers_mut());
+                request
+            });
+        let delay = Duration::from_millis(config.request_interval_ms * stream as u64);
+        run_stream(send_request_handle.clone(), request, delay)
+    });
+    // The streams hold the last request handles, the connection is closed once they are done.
+    let streams = join_all(streams.collect::<Vec<_>>());
+    drop(send_request_handle);
+
+    let (connection_result, results) = tokio::join!(connection, streams);
+    let connection_go_away = match connection_result {
+        Ok(()) => false,
+        Err(error) => {
+            warn!("HTTP/2 connection failed: {:?}", error);
+            error.is_go_away()
+        }
+    };
+    let stats = HttpMultiplexStats::new(connection_id, &results, connection_go_away);
+    Ok((results, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(status: Option<u16>, go_away: bool) -> Http2StreamResult {
+        Http2StreamResult {
+            latency_us: status.map(|_| 100),
+            status,
+            error: status.is_none().then(|| "stream error".into()),
+            go_away,
+        }
+    }
+
+    #[test]
+    fn test_multiplex_stats() {
+        let connection_id = Uuid::new_v4();
+        let streams = [
+            stream(Some(200), false),
+            stream(Some(503), false),
+            stream(None, false),
+        ];
+        assert_eq!(
+            HttpMultiplexStats::new(connection_id, &streams, false),
+            HttpMultiplexStats {
+                connection_id,
+                streams_initiated: 3,
+                streams_completed: 2,
+                streams_failed: 1,
+                goaway_received: false,
+            }
+        );
+
+        // Reported by the streams it failed, or by the connection alone.
+        let streams = [stream(Some(200), false), stream(None, true)];
+        assert!(HttpMultiplexStats::new(connection_id, &streams, false).goaway_received);
+        assert!(HttpMultiplexStats::new(connection_id, &streams[..1], true).goaway_received);
+    }
+}
@@ -0,0 +1,154 @@
+use aya::maps::MapError;
+use aya::sys::SyscallError;
+use log::{debug, warn};
+use std::net::{SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tcp_tester_common::{FaultProfile, FlowKey, PathFlapConfig};
+use tokio::time::Duration;
+
+use super::flow_config_history::{FlowProfiles, SharedFlowConfigHistory};
+use super::FlowConfigMap;
+
+/// Number of path flaps done by all the flows.
+static PATH_FLAPS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// `bpf_map_update_elem` flag only updating an existing entry, which fails with `ENOENT` otherwise.
+const BPF_EXIST: u64 = 2;
+
+/// Returns the `FLOW_CONFIG` key of the egress direction of a flow, the ingress one being its
+/// reverse.
+fn egress_flow_key(local: SocketAddrV4, peer: SocketAddrV4) -> FlowKey {
+    FlowKey::builder()
+        .src_ip(*local.ip())
+        .dst_ip(*peer.ip())
+        .src_port(local.port())
+        .dst_port(peer.port())
+        .build()
+}
+
+/// Sets the fault profiles of the directions of the flow, in the order of `keys`, and records them
+/// in the history of the flow, if any. Returns false when the flow is not in `FLOW_CONFIG`
+/// anymore, i.e. it has been closed.
+fn set_flow_profiles(
+    flow_config: &FlowConfigMap,
+    keys: &[FlowKey; 2],
+    profiles: FlowProfiles,
+    history: Option<&SharedFlowConfigHistory>,
+) -> bool {
+    let mut flow_config = flow_config.lock().unwrap();
+    for (key, profile) in keys.iter().zip(profiles) {
+        let mut state = match flow_config.get(key, 0) {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        state.config = profile;
+        // The sockops program removes the entries when the flow closes, which must not be undone
+        // by an update racing with it.
+        match flow_config.insert(key, state, BPF_EXIST) {
+            Ok(()) => {}
+            Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.raw_os_error() == Some(libc::ENOENT) =>
+            {
+                return false;
+            }
+            Err(error) => {
+                warn!("Failed to update flow config: {}", error);
+                return false;
+            }
+        }
+    }
+    if let Some(history) = history {
+        history.lock().unwrap().record(profiles);
+    }
+    true
+}
+
+/// Starts the fault injection of an established flow, free of faults until then, once `delay`
+/// elapsed. Returns false when the flow was closed before.
+///
+/// # Arguments
+/// * `flow_config` - `FLOW_CONFIG` map.
+/// * `local` - local address of the flow.
+/// * `peer` - server address of the flow.
+/// * `delay` - time the flow stays free of faults.
+/// * `egress` - fault profile applied to the packets sent by the client.
+/// * `ingress` - fault profile applied to the packets received by the client.
+/// * `history` - history of the fault profiles of the flow, with `--audit-log`.
+pub async fn start_faults_after(
+    flow_config: &FlowConfigMap,
+    local: SocketAddr,
+    peer: SocketAddr,
+    delay: Duration,
+    egress: FaultProfile,
+    ingress: FaultProfile,
+    history: Option<&SharedFlowConfigHistory>,
+) -> bool {
+    let (SocketAddr::V4(local), SocketAddr::V4(peer)) = (local, peer) else {
+        warn!("Delaying the fault injection is only supported for IPv4 flows");
+        return false;
+    };
+    tokio::time::sleep(delay).await;
+
+    let egress_key = egress_flow_key(local, peer);
+    let started = set_flow_profiles(
+        flow_config,
+        &[egress_key, egress_key.reverse()],
+        [egress, ingress],
+        history,
+    );
+    if started {
+        debug!("Flow {} -> {} started the fault injection", local, peer);
+    }
+    started
+}
+
+/// Alternates the fault profile of an established flow between the primary and secondary paths,
+/// until the flow is closed.
+///
+/// # Arguments
+/// * `flow_config` - `FLOW_CONFIG` map.
+/// * `local` - local address of the flow.
+/// * `peer` - server address of the flow.
+/// * `path_flap` - paths of the flow and flapping interval.
+/// * `history` - history of the fault profiles of the flow, with `--audit-log`.
+pub async fn flap_paths(
+    flow_config: FlowConfigMap,
+    local: SocketAddr,
+    peer: SocketAddr,
+    path_flap: PathFlapConfig,
+    history: Option<SharedFlowConfigHistory>,
+) {
+    let (SocketAddr::V4(local), SocketAddr::V4(peer)) = (local, peer) else {
+        warn!("Path flapping is only supported for IPv4 flows");
+        return;
+    };
+    if path_flap.flap_interval_ms == 0 {
+        warn!("Path flapping requires a non-zero flap interval");
+        return;
+    }
+
+    let egress_key = egress_flow_key(local, peer);
+    let keys = [egress_key, egress_key.reverse()];
+    let mut interval = tokio::time::interval(Duration::from_millis(path_flap.flap_interval_ms));
+    // The first tick completes immediately, the flow starts on the primary path.
+    interval.tick().await;
+
+    let mut on_primary = true;
+    loop {
+        interval.tick().await;
+
+        let profile = if on_primary {
+            path_flap.secondary
+        } else {
+            path_flap.primary
+        };
+        if !set_flow_profiles(&flow_config, &keys, [profile, profile], history.as_ref()) {
+            return;
+        }
+        on_primary = !on_primary;
+
+        let path_flaps_total = PATH_FLAPS_TOTAL.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = if on_primary { "primary" } else { "secondary" };
+        debug!(path_flaps_total, path; "Flow {} -> {} switched path", local, peer);
+    }
+}
@@ -0,0 +1,61 @@
+use rand::{Rng, RngExt};
+use tcp_tester_common::PayloadDistribution;
+
+/// Length of the pattern repeated by `PayloadDistribution::Compressible`. It holds up to 256
+/// distinct bytes, so that the entropy can go up to 8 bits per byte.
+const PATTERN_LEN: usize = 256;
+
+/// Fills `data` with bytes following the distribution.
+pub fn fill_payload<R: Rng>(rng: &mut R, distribution: PayloadDistribution, data: &mut [u8]) {
+    match distribution {
+        PayloadDistribution::Uniform => rng.fill_bytes(data),
+        PayloadDistribution::Ascii => data
+            .iter_mut()
+            .for_each(|byte| *byte = rng.random_range(b' '..=b'~')),
+        PayloadDistribution::Compressible(entropy) => {
+            // Bytes uniformly drawn from 2^entropy symbols have the target entropy, the pattern
+            // repetition makes them compressible on top of that.
+            let symbols = 2f64.powf(entropy.clamp(0.0, 8.0)).round() as u16;
+            let offset: u8 = rng.random();
+            let pattern: Vec<u8> = (0..PATTERN_LEN)
+                .map(|_| offset.wrapping_add(rng.random_range(0..symbols) as u8))
+                .collect();
+            for (byte, pattern_byte) in data.iter_mut().zip(pattern.iter().cycle()) {
+                *byte = *pattern_byte;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_fill_payload_ascii_is_printable() {
+        let mut data = [0; 2048];
+        fill_payload(
+            &mut StdRng::seed_from_u64(0),
+            PayloadDistribution::Ascii,
+            &mut data,
+        );
+        assert!(data
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || *byte == b' '));
+    }
+
+    #[test]
+    fn test_fill_payload_compressible_repeats_pattern() {
+        let mut data = [0; 2048];
+        fill_payload(
+            &mut StdRng::seed_from_u64(0),
+            PayloadDistribution::Compressible(2.0),
+            &mut data,
+        );
+        assert_eq!(data[..PATTERN_LEN], data[PATTERN_LEN..2 * PATTERN_LEN]);
+        assert!(data.iter().collect::<HashSet<_>>().len() <= 4);
+    }
+}
@@ -1,19 +1,204 @@
-use std::net::SocketAddr;
-use std::os::fd::AsFd;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{borrow::BorrowMut, os::fd::AsRawFd};
 
-use aya::maps::{HashMap, MapData};
+use aya::maps::{HashMap, MapData, MapError};
+use futures::future::join_all;
+use log::debug;
 use netns_rs::NetNs;
 use nix::sys::socket::{self as sockopt};
+use opentelemetry::Context;
+use serde::Serialize;
+use tcp_tester::conditioned_tcp_stream::ConditionedTcpStream;
 use tcp_tester::os;
-use tcp_tester_common::{Direction, FlowConfig, SocketKey};
-use tokio::net::TcpSocket;
+use tcp_tester::socket_audit::audit_socket;
+use tcp_tester_common::{Direction, FaultProfile, SocketKey};
+use tokio::net::{TcpSocket, TcpStream};
 
-use super::{client_socket_error::ClientSocketError, conditioned_tcp_stream::ConditionedTcpStream};
+use super::{client_socket_error::ClientSocketError, socks5};
+use crate::audit_log::{AuditLogger, AuditOperation};
+use crate::telemetry;
+
+/// Time spent in every step of `ClientSocketBuilder::connect`, in microseconds.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ConnectionTimingBreakdown {
+    /// Switches to the client namespace and back.
+    pub namespace_switch_us: u64,
+    /// Creation and set up of the socket, up to its options.
+    pub socket_create_us: u64,
+    /// Registration of the socket in `SOCKET_CONFIG`, lock included.
+    pub bpf_map_insert_us: u64,
+    /// TCP handshake, along with the SOCKS5 one when connecting through a proxy.
+    pub tcp_connect_us: u64,
+    pub total_us: u64,
+}
+
+/// Returns the microseconds elapsed since `start`.
+fn elapsed_us(start: Instant) -> u64 {
+    start.elapsed().as_micros() as u64
+}
+
+/// `SOCKET_CONFIG` map, along with `SOCKET_CONFIG_TIMESTAMPS`, the time its entries were
+/// inserted at. Both are updated under the same lock.
+pub struct SocketConfigMaps<T> {
+    pub config: HashMap<T, SocketKey, FaultProfile>,
+    pub timestamps: HashMap<T, SocketKey, u64>,
+    /// Records the modifications of `config`, with `--audit-log`.
+    pub audit_log: Option<Arc<AuditLogger>>,
+}
+
+impl<T> SocketConfigMaps<T>
+where
+    T: BorrowMut<MapData>,
+{
+    /// Inserts the configuration of `key` in `SOCKET_CONFIG`, replacing the existing one if any,
+    /// and its insertion time `now` in `SOCKET_CONFIG_TIMESTAMPS`. `caller` names the function
+    /// modifying the map, in the audit log.
+    pub fn insert(
+        &mut self,
+        key: SocketKey,
+        config: FaultProfile,
+        now: u64,
+        caller: &str,
+    ) -> Result<(), MapError> {
+        let old = match &self.audit_log {
+            Some(_) => self.config.get(&key, 0).ok(),
+            None => None,
+        };
+        self.config.insert(key, config, 0)?;
+        self.timestamps.insert(key, now, 0)?;
+        if let Some(audit_log) = &self.audit_log {
+            let operation = match old {
+                Some(_) => AuditOperation::Update,
+                None => AuditOperation::Insert,
+            };
+            audit_log.log(operation, &key, old, Some(config), caller);
+        }
+        Ok(())
+    }
+
+    /// Removes the entries of `key` from `SOCKET_CONFIG` and `SOCKET_CONFIG_TIMESTAMPS`, the
+    /// config entry being already gone if the socket connected in the meantime.
+    pub fn remove(&mut self, key: &SocketKey, caller: &str) {
+        let old = match &self.audit_log {
+            Some(_) => self.config.get(key, 0).ok(),
+            None => None,
+        };
+        let _ = self.config.remove(key);
+        let _ = self.timestamps.remove(key);
+        if let (Some(audit_log), Some(old)) = (&self.audit_log, old) {
+            audit_log.log(AuditOperation::Remove, key, Some(old), None, caller);
+        }
+    }
+}
 
 pub struct ClientSocketBuilder<T> {
     netns: NetNs,
-    socket_config: HashMap<T, SocketKey, FlowConfig>,
+    socket_config: Arc<Mutex<SocketConfigMaps<T>>>,
+    audit_sockets: bool,
+    bind_addr: Option<IpAddr>,
+    vrf: Option<String>,
+    trace_context: Context,
+    socks5_proxy: Option<SocketAddr>,
+    tcp_fast_open: bool,
+    tcp_user_timeout_ms: Option<u32>,
+    buffer_sizes: BufferSizes,
+}
+
+/// Send and receive buffer sizes of the client sockets, the kernel defaults when not set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BufferSizes {
+    pub send: Option<u32>,
+    pub recv: Option<u32>,
+}
+
+/// Sets the buffer sizes of the socket, logging the actual ones: the kernel doubles the
+/// requested sizes, for its bookkeeping overhead, and clamps them to `net.core.wmem_max` and
+/// `net.core.rmem_max`.
+fn set_buffer_sizes(socket: &TcpSocket, buffer_sizes: BufferSizes) -> std::io::Result<()> {
+    if buffer_sizes.send.is_none() && buffer_sizes.recv.is_none() {
+        return Ok(());
+    }
+    if let Some(size) = buffer_sizes.send {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = buffer_sizes.recv {
+        socket.set_recv_buffer_size(size)?;
+    }
+    let send_buffer = socket.send_buffer_size()?;
+    let recv_buffer = socket.recv_buffer_size()?;
+    debug!(send_buffer, recv_buffer; "Socket buffer sizes");
+    Ok(())
+}
+
+/// Sets `TCP_USER_TIMEOUT` on the socket, if any.
+fn set_tcp_user_timeout(
+    socket: &TcpSocket,
+    timeout_ms: Option<u32>,
+) -> Result<(), ClientSocketError> {
+    match timeout_ms {
+        Some(timeout_ms) => sockopt::setsockopt(
+            socket.as_raw_fd(),
+            sockopt::sockopt::TcpUserTimeout,
+            &timeout_ms,
+        )
+        .map_err(ClientSocketError::SocketError),
+        None => Ok(()),
+    }
+}
+
+/// Logs the options of the socket, to verify they are the expected ones before connecting.
+fn log_socket_audit(socket: &TcpSocket) {
+    let report = audit_socket(socket.as_raw_fd());
+    debug!(report:serde; "Socket options before connect");
+}
+
+/// Returns the IPv4 address of `addr` when it is an IPv4-mapped IPv6 address, e.g.
+/// `[::ffff:2.2.2.2]:80` from a dual-stack configuration, `addr` otherwise. The client sockets
+/// are IPv4, and the flows are keyed by their IPv4 addresses in the eBPF maps.
+fn normalize_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Connects the socket to `addr`, through the SOCKS5 proxy if any. The socket is then connected
+/// to the proxy, so the eBPF programs see the flow to the proxy, not the one to `addr`.
+async fn connect_socket(
+    socket: TcpSocket,
+    addr: SocketAddr,
+    socks5_proxy: Option<SocketAddr>,
+) -> std::io::Result<TcpStream> {
+    match socks5_proxy {
+        Some(proxy) => {
+            let mut stream = socket.connect(proxy).await?;
+            socks5::connect(&mut stream, addr).await?;
+            Ok(stream)
+        }
+        None => socket.connect(addr).await,
+    }
+}
+
+/// Binds the socket to the VRF master device, if any, then to the given source address, on a
+/// port picked by the kernel. The device goes first, for the address to be looked up in the
+/// routing table of the VRF.
+fn bind_socket(
+    socket: &TcpSocket,
+    bind_addr: Option<IpAddr>,
+    vrf: Option<&str>,
+) -> std::io::Result<()> {
+    if let Some(vrf) = vrf {
+        socket.bind_device(Some(vrf.as_bytes()))?;
+    }
+    match bind_addr {
+        Some(ip) => socket.bind(SocketAddr::new(ip, 0)),
+        None => Ok(()),
+    }
 }
 
 // Initiates a TCP connection without traffic control.  Thus, the socket's traffic is not tracked
@@ -21,55 +206,287 @@ pub struct ClientSocketBuilder<T> {
 pub async fn connect_sans_tc(
     netns: NetNs,
     addr: SocketAddr,
+    audit_sockets: bool,
+    bind_addr: Option<IpAddr>,
+    vrf: Option<&str>,
+    buffer_sizes: BufferSizes,
 ) -> Result<ConditionedTcpStream, ClientSocketError> {
     let socket = netns.run(|_| TcpSocket::new_v4().unwrap())?;
     socket.set_reuseaddr(true)?;
     socket.set_reuseport(true)?;
-    let stream = socket.connect(addr).await?;
-    Ok(ConditionedTcpStream { stream })
+    bind_socket(&socket, bind_addr, vrf)?;
+    set_buffer_sizes(&socket, buffer_sizes)?;
+    if audit_sockets {
+        log_socket_audit(&socket);
+    }
+    let stream = socket.connect(normalize_addr(addr)).await?;
+    Ok(ConditionedTcpStream::new(stream))
 }
 
 impl<T> ClientSocketBuilder<T>
 where
     T: BorrowMut<MapData>,
 {
-    pub fn new(netns: NetNs, socket_config: HashMap<T, SocketKey, FlowConfig>) -> Self {
+    pub fn new(netns: NetNs, socket_config: Arc<Mutex<SocketConfigMaps<T>>>) -> Self {
         ClientSocketBuilder {
             netns,
             socket_config,
+            audit_sockets: false,
+            bind_addr: None,
+            vrf: None,
+            trace_context: Context::new(),
+            socks5_proxy: None,
+            tcp_fast_open: false,
+            tcp_user_timeout_ms: None,
+            buffer_sizes: BufferSizes::default(),
         }
     }
 
+    /// Logs the options of every socket before connecting.
+    pub fn with_socket_audit(mut self, enabled: bool) -> Self {
+        self.audit_sockets = enabled;
+        self
+    }
+
+    /// Binds every socket to the given source address before connecting.
+    pub fn with_bind_addr(mut self, bind_addr: Option<IpAddr>) -> Self {
+        self.bind_addr = bind_addr;
+        self
+    }
+
+    /// Binds every socket to the VRF master device, for it to use the routing table of the VRF.
+    pub fn with_vrf(mut self, vrf: Option<String>) -> Self {
+        self.vrf = vrf;
+        self
+    }
+
+    /// Starts the span of every connection under the given OpenTelemetry context.
+    pub fn with_trace_context(mut self, trace_context: Context) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Connects every socket to the SOCKS5 proxy, which makes the connection to the server.
+    pub fn with_socks5_proxy(mut self, socks5_proxy: Option<SocketAddr>) -> Self {
+        self.socks5_proxy = socks5_proxy;
+        self
+    }
+
+    /// Sets up every connection with TCP Fast Open, the data of the first write going in the SYN.
+    pub fn with_tcp_fast_open(mut self, enabled: bool) -> Self {
+        self.tcp_fast_open = enabled;
+        self
+    }
+
+    /// Sets `TCP_USER_TIMEOUT` on every socket, the time the unacknowledged data is retransmitted
+    /// before the connection is aborted. The kernel default applies when not set.
+    pub fn with_tcp_user_timeout(mut self, timeout_ms: Option<u32>) -> Self {
+        self.tcp_user_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Sets the send and receive buffer sizes of every socket before connecting.
+    pub fn with_buffer_sizes(mut self, buffer_sizes: BufferSizes) -> Self {
+        self.buffer_sizes = buffer_sizes;
+        self
+    }
+
+    /// Stores the configurations of the ingress/egress of the socket in `SOCKET_CONFIG`, keyed
+    /// by the socket cookie, for the sockops program to pick them up on connect. The insertion
+    /// time goes to `SOCKET_CONFIG_TIMESTAMPS`, for the entries to expire if the socket never
+    /// connects.
+    fn register_socket(
+        socket_config: &mut SocketConfigMaps<T>,
+        socket: &TcpSocket,
+        egress_config: FaultProfile,
+        ingress_config: FaultProfile,
+    ) -> Result<(), ClientSocketError> {
+        let cookie = sockopt::getsockopt(socket.as_raw_fd(), os::SoCookie)
+            .map_err(ClientSocketError::SocketError)?;
+        println!("Socket cookie: {}", cookie);
+        let now = os::monotonic_time_ns().map_err(ClientSocketError::SocketError)?;
+        for (direction, config) in [
+            (Direction::INGRESS, ingress_config),
+            (Direction::EGRESS, egress_config),
+        ] {
+            let key = SocketKey::new(cookie, direction);
+            // Failing rather than panicking, which would poison the lock shared by all the clients.
+            socket_config.insert(key, config, now, "ClientSocketBuilder::register_socket")?;
+        }
+        Ok(())
+    }
+
+    /// Connects to `addr`, returning the stream along with the time spent in every step.
     pub async fn connect(
         &mut self,
         addr: SocketAddr,
-        egress_config: FlowConfig,
-        ingress_config: FlowConfig,
-    ) -> Result<ConditionedTcpStream, ClientSocketError> {
-        let socket = self.netns.run(|_| TcpSocket::new_v4().unwrap())?;
-        let fd = socket.as_fd();
-        let clone_fd = fd.try_clone_to_owned()?;
-
-        // Add the configurations of the ingress/egress
-        match sockopt::getsockopt(clone_fd.as_raw_fd(), os::SoCookie) {
-            Ok(cookie) => {
-                println!("Socket cookie: {}", cookie);
-                self.socket_config
-                    .insert(
-                        SocketKey::new(cookie, Direction::INGRESS),
-                        ingress_config,
-                        0,
-                    )
-                    .unwrap();
-                self.socket_config
-                    .insert(SocketKey::new(cookie, Direction::EGRESS), egress_config, 0)
-                    .unwrap();
-            }
-            Err(error) => return Err(ClientSocketError::SocketError(error)),
+        egress_config: FaultProfile,
+        ingress_config: FaultProfile,
+    ) -> Result<(ConditionedTcpStream, ConnectionTimingBreakdown), ClientSocketError> {
+        let start = Instant::now();
+        let mut timing = ConnectionTimingBreakdown::default();
+        let addr = normalize_addr(addr);
+        let (socket, new_socket_us) = self.netns.run(|_| {
+            let new_socket = Instant::now();
+            (TcpSocket::new_v4().unwrap(), elapsed_us(new_socket))
+        })?;
+        timing.namespace_switch_us = elapsed_us(start).saturating_sub(new_socket_us);
+
+        let socket_setup = Instant::now();
+        bind_socket(&socket, self.bind_addr, self.vrf.as_deref())?;
+        set_buffer_sizes(&socket, self.buffer_sizes)?;
+        if self.tcp_fast_open {
+            os::set_tcp_fastopen_connect(socket.as_raw_fd())
+                .map_err(ClientSocketError::SocketError)?;
         }
+        set_tcp_user_timeout(&socket, self.tcp_user_timeout_ms)?;
+        timing.socket_create_us = new_socket_us + elapsed_us(socket_setup);
+
+        let map_insert = Instant::now();
+        Self::register_socket(
+            &mut self.socket_config.lock().unwrap(),
+            &socket,
+            egress_config,
+            ingress_config,
+        )?;
+        timing.bpf_map_insert_us = elapsed_us(map_insert);
+
+        if self.audit_sockets {
+            log_socket_audit(&socket);
+        }
+        let tcp_connect = Instant::now();
+        let span = telemetry::start_connect_span(&self.trace_context, addr);
+        let stream = connect_socket(socket, addr, self.socks5_proxy).await;
+        telemetry::end_connect_span(span, &stream);
+        timing.tcp_connect_us = elapsed_us(tcp_connect);
+        timing.total_us = elapsed_us(start);
+        debug!(connect_timing:serde = timing; "Connected to {}", addr);
+
+        Ok((ConditionedTcpStream::new(stream?), timing))
+    }
+
+    /// Same as `connect` for several connections at once: all the sockets are created with a
+    /// single namespace switch and registered under a single `SOCKET_CONFIG` lock, then they
+    /// are connected concurrently.
+    ///
+    /// The results are in the same order as the requests. The whole batch only fails when the
+    /// namespace switch does.
+    ///
+    /// # Arguments
+    /// * `requests` - server address, egress and ingress configuration of every connection.
+    pub async fn connect_batch(
+        &mut self,
+        requests: Vec<(SocketAddr, FaultProfile, FaultProfile)>,
+    ) -> Result<Vec<Result<ConditionedTcpStream, ClientSocketError>>, ClientSocketError> {
+        let sockets = self.netns.run(|_| {
+            requests
+                .iter()
+                .map(|_| TcpSocket::new_v4())
+                .collect::<Vec<_>>()
+        })?;
+
+        let audit_sockets = self.audit_sockets;
+        let bind_addr = self.bind_addr;
+        let vrf = self.vrf.as_deref();
+        let tcp_fast_open = self.tcp_fast_open;
+        let tcp_user_timeout_ms = self.tcp_user_timeout_ms;
+        let buffer_sizes = self.buffer_sizes;
+        let pending: Vec<Result<(TcpSocket, SocketAddr), ClientSocketError>> = {
+            let mut socket_config = self.socket_config.lock().unwrap();
+            sockets
+                .into_iter()
+                .zip(requests)
+                .map(|(socket, (addr, egress_config, ingress_config))| {
+                    let socket = socket?;
+                    bind_socket(&socket, bind_addr, vrf)?;
+                    set_buffer_sizes(&socket, buffer_sizes)?;
+                    if tcp_fast_open {
+                        os::set_tcp_fastopen_connect(socket.as_raw_fd())
+                            .map_err(ClientSocketError::SocketError)?;
+                    }
+                    set_tcp_user_timeout(&socket, tcp_user_timeout_ms)?;
+                    Self::register_socket(
+                        &mut socket_config,
+                        &socket,
+                        egress_config,
+                        ingress_config,
+                    )?;
+                    if audit_sockets {
+                        log_socket_audit(&socket);
+                    }
+                    Ok((socket, normalize_addr(addr)))
+                })
+                .collect()
+        };
 
-        let stream = socket.connect(addr).await?;
+        let trace_context = &self.trace_context;
+        let socks5_proxy = self.socks5_proxy;
+        let connections = pending.into_iter().map(|pending| async move {
+            let (socket, addr) = pending?;
+            let span = telemetry::start_connect_span(trace_context, addr);
+            let stream = connect_socket(socket, addr, socks5_proxy).await;
+            telemetry::end_connect_span(span, &stream);
+            Ok(ConditionedTcpStream::new(stream?))
+        });
+        Ok(join_all(connections).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a `BPF_MAP_TYPE_HASH` map of `max_entries` entries, for the key and value types of
+    /// `SOCKET_CONFIG` and `SOCKET_CONFIG_TIMESTAMPS`.
+    #[cfg(feature = "requires-bpf")]
+    fn create_hash_map<V: aya::Pod>(max_entries: u32) -> HashMap<MapData, SocketKey, V> {
+        use aya::maps::Map;
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        // `BPF_MAP_CREATE` attributes: map type, key size, value size and maximum entries.
+        let attr: [u32; 4] = [
+            1,
+            std::mem::size_of::<SocketKey>() as u32,
+            std::mem::size_of::<V>() as u32,
+            max_entries,
+        ];
+        let fd = unsafe { libc::syscall(libc::SYS_bpf, 0, attr.as_ptr(), size_of_val(&attr)) };
+        assert!(fd >= 0, "{}", std::io::Error::last_os_error());
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        HashMap::try_from(Map::HashMap(MapData::from_fd(fd).unwrap())).unwrap()
+    }
+
+    #[cfg(feature = "requires-bpf")]
+    #[test]
+    fn test_register_socket_full_map() {
+        // Room for one direction of a single socket.
+        let socket_config = Mutex::new(SocketConfigMaps {
+            config: create_hash_map(1),
+            timestamps: create_hash_map(1),
+            audit_log: None,
+        });
+        let socket = TcpSocket::new_v4().unwrap();
+        let result = ClientSocketBuilder::register_socket(
+            &mut socket_config.lock().unwrap(),
+            &socket,
+            FaultProfile::NONE,
+            FaultProfile::NONE,
+        );
+        assert!(
+            matches!(result, Err(ClientSocketError::MapError(_))),
+            "{result:?}"
+        );
+        assert!(!socket_config.is_poisoned());
+    }
 
-        Ok(ConditionedTcpStream { stream })
+    #[test]
+    fn test_normalize_ipv4_mapped_addr() {
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:80".parse().unwrap();
+        assert_eq!(normalize_addr(mapped), "1.2.3.4:80".parse().unwrap());
+
+        for addr in ["1.2.3.4:80", "[::1]:80", "[2001:db8::1]:80"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            assert_eq!(normalize_addr(addr), addr);
+        }
     }
 }
@@ -0,0 +1,150 @@
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 5;
+const AUTH_NO_AUTH: u8 = 0;
+const AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const COMMAND_CONNECT: u8 = 1;
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+/// Error of a SOCKS5 reply, with the kind of the equivalent direct connection error.
+fn reply_error(reply: u8) -> io::Error {
+    let (kind, reason) = match reply {
+        1 => (ErrorKind::Other, "general failure"),
+        2 => (
+            ErrorKind::PermissionDenied,
+            "connection not allowed by ruleset",
+        ),
+        3 => (ErrorKind::NetworkUnreachable, "network unreachable"),
+        4 => (ErrorKind::HostUnreachable, "host unreachable"),
+        5 => (ErrorKind::ConnectionRefused, "connection refused"),
+        6 => (ErrorKind::TimedOut, "TTL expired"),
+        7 => (ErrorKind::Unsupported, "command not supported"),
+        8 => (ErrorKind::Unsupported, "address type not supported"),
+        _ => (ErrorKind::Other, "unknown reply"),
+    };
+    io::Error::new(kind, format!("SOCKS5 proxy failed to connect: {}", reason))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("SOCKS5 proxy: {}", message))
+}
+
+/// Asks the SOCKS5 proxy the stream is connected to for a connection to `dst`, without
+/// authentication (RFC 1928). Once it returns, the stream carries the data of the connection.
+pub async fn connect<S>(stream: &mut S, dst: SocketAddr) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&[VERSION, 1, AUTH_NO_AUTH]).await?;
+    let mut method = [0; 2];
+    stream.read_exact(&mut method).await?;
+    match method {
+        [VERSION, AUTH_NO_AUTH] => {}
+        [VERSION, AUTH_NO_ACCEPTABLE] => {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "SOCKS5 proxy requires authentication",
+            ))
+        }
+        _ => return Err(protocol_error("unexpected method selection")),
+    }
+
+    let mut request = vec![VERSION, COMMAND_CONNECT, 0];
+    match dst {
+        SocketAddr::V4(dst) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&dst.ip().octets());
+        }
+        SocketAddr::V6(dst) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&dst.ip().octets());
+        }
+    }
+    request.extend_from_slice(&dst.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != VERSION {
+        return Err(protocol_error("unexpected reply version"));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(reply_error(reply[1]));
+    }
+    // The address the proxy bound for the connection is not needed.
+    let bound_addr_len = match reply[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(protocol_error("unexpected bound address type")),
+    };
+    let mut bound_addr = vec![0; bound_addr_len + 2];
+    stream.read_exact(&mut bound_addr).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Accepts a single SOCKS5 connection request, answers it with `reply`, and returns the
+    /// requested destination.
+    async fn proxy(listener: TcpListener, reply: u8) -> (TcpStream, Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut greeting = [0; 3];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [VERSION, 1, AUTH_NO_AUTH]);
+        stream.write_all(&[VERSION, AUTH_NO_AUTH]).await.unwrap();
+
+        let mut request = [0; 10];
+        stream.read_exact(&mut request).await.unwrap();
+        stream
+            .write_all(&[VERSION, reply, 0, ADDRESS_IPV4, 127, 0, 0, 1, 0x1f, 0x90])
+            .await
+            .unwrap();
+        (stream, request.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_connect_through_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let proxy = tokio::spawn(proxy(listener, REPLY_SUCCEEDED));
+
+        connect(&mut client, "2.2.2.2:8080".parse().unwrap())
+            .await
+            .unwrap();
+        client.write_all(b"data").await.unwrap();
+
+        let (mut proxied, request) = proxy.await.unwrap();
+        assert_eq!(request[..4], [VERSION, COMMAND_CONNECT, 0, ADDRESS_IPV4]);
+        // 2.2.2.2, port 8080.
+        assert_eq!(request[4..], [2, 2, 2, 2, 0x1f, 0x90]);
+        let mut data = [0; 4];
+        proxied.read_exact(&mut data).await.unwrap();
+        assert_eq!(&data, b"data");
+    }
+
+    #[tokio::test]
+    async fn test_connect_refused_by_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let proxy = tokio::spawn(proxy(listener, 5));
+
+        let error = connect(&mut client, "2.2.2.2:8080".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        proxy.await.unwrap();
+    }
+}
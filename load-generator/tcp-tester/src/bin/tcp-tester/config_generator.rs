@@ -0,0 +1,157 @@
+use anyhow::{bail, Context};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tcp_tester_common::FlowConfig;
+
+use crate::cli::ConfigFormat;
+use crate::client;
+
+/// Values taken by a swept parameter, parsed from `start:step:end`. The end is included.
+#[derive(Debug)]
+pub struct SweepRange {
+    start: f64,
+    step: f64,
+    end: f64,
+    /// Decimals of the most precise bound, used to format the values.
+    decimals: usize,
+}
+
+impl FromStr for SweepRange {
+    type Err = anyhow::Error;
+
+    fn from_str(range: &str) -> anyhow::Result<SweepRange> {
+        let bounds: Vec<&str> = range.split(':').collect();
+        let [start, step, end] = bounds[..] else {
+            bail!("Invalid range {}, expected start:step:end", range);
+        };
+        let parse = |bound: &str| {
+            bound
+                .parse::<f64>()
+                .with_context(|| format!("Invalid range bound {} in {}", bound, range))
+        };
+        let decimals = [start, step, end]
+            .iter()
+            .map(|bound| {
+                bound
+                    .split_once('.')
+                    .map_or(0, |(_, decimals)| decimals.len())
+            })
+            .max()
+            .unwrap();
+
+        let range = SweepRange {
+            start: parse(start)?,
+            step: parse(step)?,
+            end: parse(end)?,
+            decimals,
+        };
+        if range.step <= 0.0 || range.end < range.start {
+            bail!(
+                "Invalid range {}, the step must be positive and start <= end",
+                range
+            );
+        }
+        Ok(range)
+    }
+}
+
+impl std::fmt::Display for SweepRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.start, self.step, self.end)
+    }
+}
+
+impl SweepRange {
+    fn values(&self) -> Vec<f64> {
+        // The values are computed from the start, not accumulated, so that rounding errors do
+        // not drift. The tolerance keeps the end despite them.
+        let count = ((self.end - self.start) / self.step + 1e-9).floor() as usize + 1;
+        (0..count)
+            .map(|i| self.start + self.step * i as f64)
+            .collect()
+    }
+
+    fn format(&self, value: f64) -> String {
+        format!("{:.*}", self.decimals, value)
+    }
+
+    /// Returns the JSON value of a parameter value: integers when the range has no decimals,
+    /// since most of the configuration fields are.
+    fn to_json(&self, value: f64) -> Value {
+        if self.decimals == 0 {
+            Value::from(value.round() as i64)
+        } else {
+            Value::from(value)
+        }
+    }
+}
+
+/// Sets the field at the dotted `path` of the configuration, creating the missing objects.
+fn set_field(config: &mut Value, path: &str, value: Value) -> anyhow::Result<()> {
+    let mut field = config;
+    for key in path.split('.') {
+        if field.is_null() {
+            *field = Value::Object(serde_json::Map::new());
+        }
+        field = match field {
+            Value::Object(object) => object.entry(key).or_insert(Value::Null),
+            _ => bail!("Invalid parameter {}, {} is not an object field", path, key),
+        };
+    }
+    *field = value;
+    Ok(())
+}
+
+/// Writes one flow configuration file per combination of the parameter values to `output_dir`,
+/// and returns their paths. The files are named after the parameters and their values, e.g.
+/// `egress.conditioner.Delay.offset_10.json` for a single parameter.
+///
+/// # Arguments
+/// * `base_config` - path of the configuration the parameters are set on.
+/// * `params` - dotted path of every parameter in the configuration, e.g.
+///   `egress.conditioner.DropPacket.count`, along with its values.
+/// * `output_dir` - directory where the files are written.
+pub fn generate_configs(
+    base_config: &str,
+    params: &[(String, SweepRange)],
+    output_dir: &Path,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(base_config)
+        .with_context(|| format!("Failed to read config file: {}", base_config))?;
+    // Normalizes the legacy flat format, so the parameters always address the current one.
+    let base = client::parse_flow_config(&contents, ConfigFormat::from_path(base_config))?;
+    let base = serde_json::to_value(base)?;
+
+    // Grid of the parameter values: every combination, named after its values.
+    let mut grid: Vec<(Vec<String>, Value)> = vec![(Vec::new(), base)];
+    for (path, range) in params {
+        let mut next = Vec::new();
+        for (names, config) in &grid {
+            for value in range.values() {
+                let mut config = config.clone();
+                set_field(&mut config, path, range.to_json(value))?;
+                let mut names = names.clone();
+                names.push(format!("{}_{}", path, range.format(value)));
+                next.push((names, config));
+            }
+        }
+        grid = next;
+    }
+
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    let mut files = Vec::new();
+    for (names, config) in grid {
+        let name = names.join("_");
+        serde_json::from_value::<FlowConfig>(config.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(|flow_config| client::check_cross_fields(&flow_config))
+            .with_context(|| format!("Invalid flow configuration for {}", name))?;
+        let path = output_dir.join(format!("{}.json", name));
+        std::fs::write(&path, serde_json::to_string_pretty(&config)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        files.push(path);
+    }
+    Ok(files)
+}
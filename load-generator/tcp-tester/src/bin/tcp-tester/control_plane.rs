@@ -0,0 +1,162 @@
+use log::{error, info, warn};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tcp_tester_common::FlowConfig;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::client::{self, ClientOptions, FlowEvent, HealthStatus};
+
+mod proto {
+    tonic::include_proto!("flow_controller");
+}
+
+use proto::flow_controller_server::{FlowController, FlowControllerServer};
+use proto::flow_event::Kind;
+use proto::health_response::Status as HealthStatusProto;
+use proto::{Empty, FlowConfigRequest, FlowConfigResponse, FlowStatsResponse, HealthResponse};
+
+impl From<FlowEvent> for proto::FlowEvent {
+    fn from(event: FlowEvent) -> Self {
+        match event {
+            FlowEvent::Started { server } => proto::FlowEvent {
+                kind: Kind::Started.into(),
+                server: server.to_string(),
+                ..Default::default()
+            },
+            FlowEvent::Completed(flow) => proto::FlowEvent {
+                kind: Kind::Completed.into(),
+                server: flow.server.to_string(),
+                duration_ms: flow.duration_ms,
+                bytes_written: flow.stats.bytes_written,
+                bytes_read: flow.stats.bytes_read,
+            },
+            FlowEvent::Failed { server } => proto::FlowEvent {
+                kind: Kind::Failed.into(),
+                server: server.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<HealthStatus> for HealthResponse {
+    fn from(health: HealthStatus) -> Self {
+        let http_status = health.http_status().into();
+        let (status, reason) = match health {
+            HealthStatus::Ok => (HealthStatusProto::Ok, String::new()),
+            HealthStatus::Degraded(reason) => (HealthStatusProto::Degraded, reason),
+            HealthStatus::Down(reason) => (HealthStatusProto::Down, reason),
+        };
+        HealthResponse {
+            status: status.into(),
+            reason,
+            http_status,
+        }
+    }
+}
+
+/// `FlowController` gRPC service, giving remote access to the state shared by the clients.
+pub struct FlowControllerService {
+    /// Options of the clients, holding the state they share.
+    options: ClientOptions,
+    /// Server the health check connections are made to.
+    health_check_addr: SocketAddr,
+}
+
+impl FlowControllerService {
+    pub fn new(options: ClientOptions, health_check_addr: SocketAddr) -> Self {
+        FlowControllerService {
+            options,
+            health_check_addr,
+        }
+    }
+}
+
+type FlowEventStream = Pin<Box<dyn Stream<Item = Result<proto::FlowEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl FlowController for FlowControllerService {
+    async fn update_flow_config(
+        &self,
+        request: Request<FlowConfigRequest>,
+    ) -> Result<Response<FlowConfigResponse>, Status> {
+        let request = request.into_inner();
+        let config = match request.config {
+            Some(config) => FlowConfig::from_proto(config),
+            None => FlowConfig::from_json(&request.config_json),
+        }
+        .map_err(|error| Status::invalid_argument(format!("{:?}", error)))?;
+        client::check_cross_fields(&config)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let config_json =
+            serde_json::to_string(&config).map_err(|error| Status::internal(error.to_string()))?;
+        info!(config_json; "Flow configuration updated through the control plane");
+        let response = FlowConfigResponse {
+            config_json,
+            config: Some(config.to_proto()),
+        };
+        *self.options.remote_config.write().unwrap() = Some(config);
+        Ok(Response::new(response))
+    }
+
+    async fn get_flow_stats(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<FlowStatsResponse>, Status> {
+        let totals = self.options.flow_counters.totals();
+        Ok(Response::new(FlowStatsResponse {
+            flows_completed: totals.flows_completed,
+            flows_failed: totals.flows_failed,
+            bytes_written: totals.bytes_written,
+            bytes_read: totals.bytes_read,
+        }))
+    }
+
+    type ListActiveFlowsStream = FlowEventStream;
+
+    async fn list_active_flows(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActiveFlowsStream>, Status> {
+        let events =
+            BroadcastStream::new(self.options.flow_events.subscribe()).filter_map(|event| {
+                match event {
+                    Ok(event) => Some(Ok(event.into())),
+                    Err(error) => {
+                        // The subscriber is too slow, the skipped events are lost.
+                        warn!("Flow events dropped: {}", error);
+                        None
+                    }
+                }
+            });
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn check_health(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        let health = client::check_health(self.health_check_addr, &self.options).await;
+        info!(health:?; "Health check completed");
+        Ok(Response::new(health.into()))
+    }
+}
+
+/// Serves the `FlowController` gRPC service until the process exits.
+///
+/// # Arguments
+/// * `addr` - address and port the service listens on.
+/// * `service` - state shared with the clients.
+pub async fn serve(addr: SocketAddr, service: FlowControllerService) {
+    info!("Control plane listening on {}", addr);
+    if let Err(error) = Server::builder()
+        .add_service(FlowControllerServer::new(service))
+        .serve(addr)
+        .await
+    {
+        error!("Control plane failed: {:?}", error);
+    }
+}
@@ -0,0 +1,111 @@
+use log::warn;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tcp_tester::os;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::cli::AffinityMode;
+
+/// Pins the calling thread to `cpus`, a failure only being logged.
+pub fn pin_current_thread(cpus: &[usize]) {
+    if let Err(error) = os::set_thread_affinity(cpus) {
+        warn!(
+            "Failed to pin the thread {} to the CPUs {:?}: {}",
+            std::thread::current().name().unwrap_or("unnamed"),
+            cpus,
+            error
+        );
+    }
+}
+
+/// Builds the runtime of tcp-tester, whose threads are pinned to `cpus`: with
+/// `AffinityMode::RoundRobin`, one worker thread per CPU, each one pinned to the next CPU of the
+/// list, otherwise every thread to all of them.
+pub fn build_runtime(cpus: &[usize], mode: Option<AffinityMode>) -> std::io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if cpus.is_empty() {
+        return builder.build();
+    }
+
+    let cpus = cpus.to_vec();
+    if mode == Some(AffinityMode::RoundRobin) {
+        let next = AtomicUsize::new(0);
+        builder.worker_threads(cpus.len()).on_thread_start(move || {
+            let cpu = cpus[next.fetch_add(1, Ordering::Relaxed) % cpus.len()];
+            pin_current_thread(&[cpu]);
+        });
+    } else {
+        builder.on_thread_start(move || pin_current_thread(&cpus));
+    }
+    builder.build()
+}
+
+/// Single-threaded runtimes, one per CPU and running on a thread pinned to it, for the flows to
+/// stay on the same CPU for their entire lifetime (`AffinityMode::Sticky`).
+#[derive(Debug)]
+pub struct FlowRuntimes {
+    handles: Vec<Handle>,
+    next: AtomicUsize,
+}
+
+impl FlowRuntimes {
+    /// Starts a runtime on each one of `cpus`.
+    pub fn start(cpus: &[usize]) -> std::io::Result<FlowRuntimes> {
+        let handles = cpus
+            .iter()
+            .map(|&cpu| {
+                let runtime = Builder::new_current_thread().enable_all().build()?;
+                let handle = runtime.handle().clone();
+                std::thread::Builder::new()
+                    .name(format!("flows-cpu{cpu}"))
+                    .spawn(move || {
+                        pin_current_thread(&[cpu]);
+                        runtime.block_on(std::future::pending::<()>());
+                    })?;
+                Ok(handle)
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(FlowRuntimes {
+            handles,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Spawns a flow on the runtime of the next CPU, the tasks it spawns staying on it too.
+    pub fn spawn<F>(&self, flow: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.handles.len();
+        self.handles[index].spawn(flow);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::{self, ThreadId};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_flow_runtimes_spawn() {
+        let runtimes = FlowRuntimes::start(&[0, 0]).unwrap();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        for flow in 0..3 {
+            let sender = sender.clone();
+            runtimes.spawn(async move {
+                let _ = sender.send((flow, thread::current().id()));
+            });
+        }
+        let mut threads: [Option<ThreadId>; 3] = [None; 3];
+        for _ in 0..3 {
+            let (flow, thread) = receiver.recv().await.unwrap();
+            threads[flow] = Some(thread);
+        }
+        // The third flow is spawned on the runtime of the first one.
+        assert_ne!(threads[0], threads[1]);
+        assert_eq!(threads[0], threads[2]);
+        assert_ne!(threads[0], Some(thread::current().id()));
+    }
+}
@@ -1,11 +1,145 @@
-use aya::{include_bytes_aligned, Ebpf};
+use anyhow::{bail, Context};
+use aya::maps::{Map, MapData};
+use aya::programs::{loaded_programs, Program};
+use aya::Pod;
+use aya::{include_bytes_aligned, Btf, Ebpf, EbpfLoader};
 use aya_log::EbpfLogger;
+use log::{debug, warn};
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use object::{Object, ObjectSection, ObjectSymbol};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::mem::size_of;
+use std::os::fd::{AsFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use tcp_tester_common::{FaultProfile, FlowKey, FlowState, FlowStats, SocketKey, PROGRAM_VERSION};
 
-pub fn load_ebpf_program() -> Result<Ebpf, String> {
-    #[cfg(debug_assertions)]
-    let bpf = Ebpf::load(include_bytes_aligned!(concat!(env!("BPF_OBJECT_PATH"))));
-    #[cfg(not(debug_assertions))]
-    let mut bpf = Ebpf::load(include_bytes_aligned!(concat!(env!("BPF_OBJECT_PATH"))));
+use crate::cli::EbpfLogLevel;
+use crate::kernel_capabilities::KernelCapabilities;
+use crate::perf_samples;
+
+/// eBPF object embedded in the binary.
+static BPF_OBJECT: &[u8] = include_bytes_aligned!(concat!(env!("BPF_OBJECT_PATH")));
+
+/// Reads the `TCP_TESTER_PROGRAM_VERSION` global of the eBPF object.
+fn read_program_version(bpf_object: &[u8]) -> anyhow::Result<u32> {
+    let file = object::File::parse(bpf_object).context("Failed to parse the eBPF object")?;
+    let symbol = file
+        .symbol_by_name("TCP_TESTER_PROGRAM_VERSION")
+        .context("The eBPF object has no TCP_TESTER_PROGRAM_VERSION, it predates versioning")?;
+    let section = symbol
+        .section_index()
+        .and_then(|index| file.section_by_index(index).ok())
+        .context("TCP_TESTER_PROGRAM_VERSION is not defined in a section")?;
+    let data = section.data()?;
+    let offset = (symbol.address() - section.address()) as usize;
+    let bytes = data
+        .get(offset..offset + 4)
+        .context("TCP_TESTER_PROGRAM_VERSION is out of its section")?;
+    // The eBPF target is little-endian.
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Fails when the eBPF object was built with another `PROGRAM_VERSION` than the binary, e.g.
+/// when the eBPF program was not rebuilt after a change of the map types.
+fn verify_program_version(bpf_object: &[u8]) -> anyhow::Result<()> {
+    let version = read_program_version(bpf_object)?;
+    if version != PROGRAM_VERSION {
+        bail!(
+            "Stale eBPF program: it was built with version {}, the binary expects version {}. \
+             Rebuild the eBPF program.",
+            version,
+            PROGRAM_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// How the eBPF programs are loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct LoadOptions<'a> {
+    /// eBPF features supported by the running kernel.
+    pub capabilities: &'a KernelCapabilities,
+    /// Most verbose level of the messages logged by the programs.
+    pub log_level: EbpfLogLevel,
+    /// eBPF object file the programs are loaded from, instead of the one embedded in the binary,
+    /// to try out a change of the programs without rebuilding tcp-tester.
+    pub object_path: Option<&'a Path>,
+    /// Rate in Hz at which every CPU samples the delayed packets to `PERF_OUTPUT`, disabled when
+    /// not set.
+    pub perf_event_sample_rate: Option<u32>,
+    /// Directory of the bpffs the maps holding state are pinned under, the ones pinned there by a
+    /// previous run being reused with their entries. When not set, the maps only live as long as
+    /// the programs.
+    pub map_pin_path: Option<&'a Path>,
+}
+
+/// Loads the eBPF programs, from `options.object_path` when set, the embedded object otherwise.
+pub fn load_ebpf_program(options: LoadOptions) -> Result<Ebpf, String> {
+    let LoadOptions {
+        capabilities,
+        log_level,
+        object_path,
+        perf_event_sample_rate,
+        map_pin_path,
+    } = options;
+    let object_file;
+    let bpf_object = match object_path {
+        Some(path) => {
+            debug!("Loading the eBPF programs from {}", path.display());
+            object_file = std::fs::read(path).map_err(|error| {
+                format!(
+                    "Failed to read the eBPF object {}: {}",
+                    path.display(),
+                    error
+                )
+            })?;
+            &object_file
+        }
+        None => BPF_OBJECT,
+    };
+    verify_program_version(bpf_object).map_err(|error| format!("{:#}", error))?;
+
+    // The kernel BTF is only needed to relocate the programs, skip it when unsupported.
+    let btf = capabilities
+        .has_co_re
+        .then(|| {
+            Btf::from_sys_fs()
+                .inspect_err(|error| warn!("Failed to load the kernel BTF: {}", error))
+                .ok()
+        })
+        .flatten();
+    // The programs declare the maps pinned by name, they are pinned under a directory of this
+    // process when they must not outlive it, the pins being removed once the maps are created.
+    let scratch_pin_path = Path::new(BPFFS_PATH).join(format!("tcp-tester-{}", std::process::id()));
+    let pin_path = map_pin_path.unwrap_or(&scratch_pin_path);
+    ensure_bpffs_mounted()
+        .and_then(|()| {
+            std::fs::create_dir_all(pin_path)
+                .with_context(|| format!("Failed to create {}", pin_path.display()))
+        })
+        .map_err(|error| format!("{:#}", error))?;
+    let bpf = EbpfLoader::new()
+        .btf(btf.as_ref())
+        .map_pin_path(pin_path)
+        .set_global("LOG_LEVEL", &log_level.value(), true)
+        .set_global(
+            "PERF_SAMPLE_INTERVAL_NS",
+            &perf_event_sample_rate.map_or(0, perf_samples::perf_sample_interval_ns),
+            true,
+        )
+        .load(bpf_object);
+    if map_pin_path.is_none() {
+        if let Err(error) = std::fs::remove_dir_all(&scratch_pin_path) {
+            warn!(
+                "Failed to remove the pins of {}: {}",
+                scratch_pin_path.display(),
+                error
+            );
+        }
+    }
     match bpf {
         Ok(mut ebpf_program) => {
             if let Err(error) = EbpfLogger::init(&mut ebpf_program) {
@@ -16,3 +150,569 @@ pub fn load_ebpf_program() -> Result<Ebpf, String> {
         Err(error) => Err(error.to_string()),
     }
 }
+
+/// Mount point of the bpffs.
+const BPFFS_PATH: &str = "/sys/fs/bpf";
+
+/// Directory of the bpffs where the maps are pinned with `--pin-maps`.
+pub const PINNED_MAPS_PATH: &str = "/sys/fs/bpf/nfm";
+
+/// Returns whether a bpffs is mounted at `BPFFS_PATH`, from the contents of `/proc/mounts`.
+fn is_bpffs_mounted(mounts: &str) -> bool {
+    mounts.lines().any(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        fields.next() == Some(BPFFS_PATH) && fields.next() == Some("bpf")
+    })
+}
+
+/// Cgroup the programs are attached to when it can't be detected.
+pub const DEFAULT_CGROUP_PATH: &str = "/mnt/cgroup2";
+
+/// Returns the mount point of the cgroup v2 hierarchy, from the contents of `/proc/mounts`.
+fn cgroup2_mount_point(mounts: &str) -> Option<&str> {
+    mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        match (fields.next(), fields.next()) {
+            (Some(mount_point), Some("cgroup2")) => Some(mount_point),
+            _ => None,
+        }
+    })
+}
+
+/// Returns the cgroup v2 of the process, relative to the hierarchy root, from the contents of
+/// `/proc/self/cgroup`.
+fn own_cgroup(cgroups: &str) -> Option<&str> {
+    cgroups.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Returns the path of the cgroup v2 of the process, for the programs to be attached to it
+/// when `--cgroup-path` is not set. Warns when it is the root cgroup, whose programs see the
+/// sockets of all the processes of the host.
+pub fn detect_cgroup_path() -> anyhow::Result<String> {
+    let mounts = std::fs::read_to_string("/proc/mounts").context("Failed to read /proc/mounts")?;
+    let mount_point = cgroup2_mount_point(&mounts).context("No cgroup v2 hierarchy is mounted")?;
+    let cgroups =
+        std::fs::read_to_string("/proc/self/cgroup").context("Failed to read /proc/self/cgroup")?;
+    let cgroup = own_cgroup(&cgroups).context("The process is in no cgroup v2")?;
+    if cgroup == "/" {
+        warn!(
+            "The detected cgroup is the root cgroup {}, the fault injection applies to the \
+             sockets of every process",
+            mount_point
+        );
+    }
+    Ok(format!("{}{}", mount_point.trim_end_matches('/'), cgroup))
+}
+
+/// Mounts the bpffs at `BPFFS_PATH` unless it already is, the maps can't be pinned otherwise.
+pub fn ensure_bpffs_mounted() -> anyhow::Result<()> {
+    let mounts = std::fs::read_to_string("/proc/mounts").context("Failed to read /proc/mounts")?;
+    if is_bpffs_mounted(&mounts) {
+        return Ok(());
+    }
+    debug!("Mounting the bpffs at {}", BPFFS_PATH);
+    match mount(
+        Some("none"),
+        BPFFS_PATH,
+        Some("bpf"),
+        MsFlags::empty(),
+        Some(""),
+    ) {
+        Ok(()) => Ok(()),
+        Err(Errno::EPERM) => bail!(
+            "Failed to mount the bpffs at {}, which requires CAP_SYS_ADMIN: run as root, or mount \
+             it beforehand with `mount -t bpf none {}`",
+            BPFFS_PATH,
+            BPFFS_PATH
+        ),
+        Err(error) => {
+            Err(error).with_context(|| format!("Failed to mount the bpffs at {}", BPFFS_PATH))
+        }
+    }
+}
+
+/// Returns the names of the maps pinned under `base_path`, none when it doesn't exist.
+pub fn pinned_map_names(base_path: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(base_path) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// `BPF_OBJ_GET` command of the `bpf` syscall.
+const BPF_OBJ_GET: libc::c_long = 7;
+
+/// `BPF_OBJ_GET` flag opening the object read-only.
+const BPF_F_RDONLY: u32 = 1 << 3;
+
+/// Attributes of the `BPF_OBJ_GET` command, the first fields of `union bpf_attr`.
+#[repr(C)]
+struct BpfObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+/// Opens the map `name` pinned under `base_path` with `--pin-maps` read-only, which only requires
+/// read access to the pin, unlike `MapData::from_pin`.
+pub fn open_pinned_map_read_only(base_path: &Path, name: &str) -> anyhow::Result<MapData> {
+    let path = base_path.join(name);
+    let pathname = CString::new(path.as_os_str().as_bytes())?;
+    let attr = BpfObjGetAttr {
+        pathname: pathname.as_ptr() as u64,
+        bpf_fd: 0,
+        file_flags: BPF_F_RDONLY,
+    };
+    // The syscall only reads `attr` and `pathname`, which outlive it.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_OBJ_GET,
+            &attr as *const BpfObjGetAttr,
+            size_of::<BpfObjGetAttr>(),
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to open pinned map {}", path.display()));
+    }
+    // The file descriptor was just returned by the syscall, nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    MapData::from_fd(fd)
+        .with_context(|| format!("Failed to read the info of pinned map {}", path.display()))
+}
+
+/// Opens another handle of the map `name` of the programs, for it to be read by several tasks.
+pub fn clone_map(bpf: &Ebpf, name: &str) -> anyhow::Result<MapData> {
+    let map = bpf
+        .map(name)
+        .with_context(|| format!("Map {} not found", name))?;
+    let fd = map_data(map)
+        .with_context(|| format!("Unexpected type of map {}", name))?
+        .fd()
+        .as_fd()
+        .try_clone_to_owned()?;
+    Ok(MapData::from_fd(fd)?)
+}
+
+/// Hash maps of the programs, whose insertions fail once they reach their maximum number of
+/// entries.
+const HASH_MAPS: [&str; 5] = [
+    "FLOW_CONFIG",
+    "SOCKET_CONFIG",
+    "SOCKET_CONFIG_TIMESTAMPS",
+    "FLOW_STATS",
+    "FLOW_START_TIME",
+];
+
+/// Utilization above which a warning is logged by `dump_map_stats`.
+const MAP_UTILIZATION_WARN_PCT: f32 = 80.0;
+
+/// Utilization of a hash map of the programs.
+#[derive(Debug, PartialEq)]
+pub struct MapStats {
+    pub name: String,
+    pub entries: u32,
+    pub max_entries: u32,
+    pub utilization_pct: f32,
+}
+
+impl MapStats {
+    fn new(name: &str, entries: u32, max_entries: u32) -> MapStats {
+        MapStats {
+            name: name.to_string(),
+            entries,
+            max_entries,
+            utilization_pct: entries as f32 * 100.0 / max_entries.max(1) as f32,
+        }
+    }
+}
+
+/// Opens other handles of the hash maps of the programs, for their utilization to be read by
+/// `dump_map_stats` once the maps are taken by the tasks using them.
+pub fn clone_hash_maps(bpf: &Ebpf) -> anyhow::Result<Vec<(String, Map)>> {
+    HASH_MAPS
+        .iter()
+        .map(|&name| {
+            let data = clone_map(bpf, name)?;
+            let map = match bpf.map(name) {
+                Some(Map::LruHashMap(_)) => Map::LruHashMap(data),
+                _ => Map::HashMap(data),
+            };
+            Ok((name.to_string(), map))
+        })
+        .collect()
+}
+
+fn count_keys<K: Pod, V: Pod>(map: &Map) -> anyhow::Result<u32> {
+    let map: aya::maps::HashMap<_, K, V> = aya::maps::HashMap::try_from(map)?;
+    Ok(map.keys().filter(Result::is_ok).count() as u32)
+}
+
+/// Returns the number of entries of the hash map `name`, by iterating over its keys.
+fn count_entries(name: &str, map: &Map) -> anyhow::Result<u32> {
+    match name {
+        "FLOW_CONFIG" => count_keys::<FlowKey, FlowState>(map),
+        "SOCKET_CONFIG" => count_keys::<SocketKey, FaultProfile>(map),
+        "SOCKET_CONFIG_TIMESTAMPS" => count_keys::<SocketKey, u64>(map),
+        "FLOW_STATS" => count_keys::<FlowKey, FlowStats>(map),
+        "FLOW_START_TIME" => count_keys::<u64, u64>(map),
+        _ => bail!("Map {} is not a hash map of the programs", name),
+    }
+}
+
+/// Returns the utilization of the hash maps, opened by `clone_hash_maps`, and warns about the
+/// ones above `MAP_UTILIZATION_WARN_PCT`, which are close to failing insertions.
+pub fn dump_map_stats(maps: &[(String, Map)]) -> Vec<MapStats> {
+    maps.iter()
+        .filter_map(|(name, map)| {
+            let max_entries = map_data(map)?
+                .info()
+                .inspect_err(|error| warn!("Failed to read the info of map {}: {}", name, error))
+                .ok()?
+                .max_entries();
+            let entries = count_entries(name, map)
+                .inspect_err(|error| {
+                    warn!("Failed to count the entries of map {}: {}", name, error)
+                })
+                .ok()?;
+            Some(MapStats::new(name, entries, max_entries))
+        })
+        .inspect(|stats| {
+            if stats.utilization_pct > MAP_UTILIZATION_WARN_PCT {
+                warn!(
+                    "Map {} is {:.1}% full ({}/{} entries), insertions fail once it is full",
+                    stats.name, stats.utilization_pct, stats.entries, stats.max_entries
+                );
+            }
+        })
+        .collect()
+}
+
+/// eBPF program loaded in the kernel, as listed by `list_programs`.
+#[derive(Debug)]
+pub struct ProgramInfo {
+    pub name: String,
+    pub program_type: String,
+    pub id: u32,
+    /// Whether the program is one of the programs attached by this tcp-tester.
+    pub attached: bool,
+}
+
+/// Lists the eBPF programs loaded in the kernel, whether they belong to tcp-tester or not, e.g.
+/// the ones of the agent. The programs of `bpf` are only loaded to be attached, so they are the
+/// attached ones.
+pub fn list_programs(bpf: &Ebpf) -> Vec<ProgramInfo> {
+    let attached: HashSet<u32> = bpf
+        .programs()
+        .filter_map(|(_, program)| program.info().ok())
+        .map(|info| info.id())
+        .collect();
+
+    loaded_programs()
+        .filter_map(|info| {
+            info.inspect_err(|error| warn!("Failed to read program info: {}", error))
+                .ok()
+        })
+        .map(|info| ProgramInfo {
+            name: info.name_as_str().unwrap_or_default().to_string(),
+            program_type: info.program_type().map_or_else(
+                |_| "unknown".to_string(),
+                |program_type| format!("{:?}", program_type),
+            ),
+            id: info.id(),
+            attached: attached.contains(&info.id()),
+        })
+        .collect()
+}
+
+/// Time spent in a program of tcp-tester and number of times it ran, accumulated by the kernel
+/// while the eBPF statistics are enabled (Linux 5.1 or later), see `aya::sys::enable_stats`.
+#[derive(Debug)]
+pub struct ProgramStats {
+    pub name: String,
+    pub run_time_ns: u64,
+    pub run_count: u64,
+}
+
+/// Returns the name and ID of the programs of `bpf`, for `get_program_stats` to find them once
+/// `bpf` is out of reach.
+pub fn program_ids(bpf: &Ebpf) -> Vec<(String, u32)> {
+    bpf.programs()
+        .filter_map(|(name, program)| {
+            let info = program
+                .info()
+                .inspect_err(|error| {
+                    warn!("Failed to read the info of program {}: {}", name, error)
+                })
+                .ok()?;
+            Some((name.to_string(), info.id()))
+        })
+        .collect()
+}
+
+/// Returns the run-time statistics of the programs listed by `program_ids`, as read with
+/// `BPF_OBJ_GET_INFO_BY_FD`.
+pub fn get_program_stats(programs: &[(String, u32)]) -> Vec<ProgramStats> {
+    loaded_programs()
+        .filter_map(|info| {
+            info.inspect_err(|error| warn!("Failed to read program info: {}", error))
+                .ok()
+        })
+        .filter_map(|info| {
+            let (name, _) = programs.iter().find(|(_, id)| *id == info.id())?;
+            Some(ProgramStats {
+                name: name.clone(),
+                run_time_ns: info.run_time().as_nanos() as u64,
+                run_count: info.run_count(),
+            })
+        })
+        .collect()
+}
+
+/// Size and complexity of a program of tcp-tester, as accepted by the verifier.
+#[derive(Debug)]
+pub struct ProgramComplexity {
+    pub name: String,
+    /// Size of the JIT-compiled program in bytes, 0 when the JIT is disabled.
+    pub jited_prog_len: u32,
+    /// Size of the program in bytes, once translated by the verifier.
+    pub xlated_prog_len: Option<u32>,
+    /// Number of instructions processed by the verifier (Linux 5.16 or later).
+    pub verified_insns: Option<u32>,
+}
+
+/// Loads every program of `bpf` in the kernel, without attaching them, and returns their size and
+/// complexity, as read with `BPF_OBJ_GET_INFO_BY_FD`.
+pub fn load_program_complexities(bpf: &mut Ebpf) -> anyhow::Result<Vec<ProgramComplexity>> {
+    let mut complexities = Vec::new();
+    for (name, program) in bpf.programs_mut() {
+        match program {
+            Program::SchedClassifier(program) => program.load(),
+            Program::SockOps(program) => program.load(),
+            _ => bail!("Program {} has an unexpected type", name),
+        }
+        .with_context(|| format!("Failed to load {}", name))?;
+        let info = program
+            .info()
+            .with_context(|| format!("Failed to read the info of program {}", name))?;
+        complexities.push(ProgramComplexity {
+            name: name.to_string(),
+            jited_prog_len: info.size_jitted(),
+            xlated_prog_len: info.size_translated(),
+            verified_insns: info.verified_instruction_count(),
+        });
+    }
+    complexities.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(complexities)
+}
+
+/// Key and value sizes the userspace types expect of every map of the program.
+fn expected_map_layouts() -> [(&'static str, usize, usize); 9] {
+    [
+        ("FLOW_CONFIG", size_of::<FlowKey>(), size_of::<FlowState>()),
+        (
+            "SOCKET_CONFIG",
+            size_of::<SocketKey>(),
+            size_of::<FaultProfile>(),
+        ),
+        (
+            "SOCKET_CONFIG_TIMESTAMPS",
+            size_of::<SocketKey>(),
+            size_of::<u64>(),
+        ),
+        ("FLOW_STATS", size_of::<FlowKey>(), size_of::<FlowStats>()),
+        ("FLOW_START_TIME", size_of::<u64>(), size_of::<u64>()),
+        ("LATENCY_HISTOGRAM", size_of::<u32>(), size_of::<u64>()),
+        ("EXCEPTION_CNT", size_of::<u32>(), size_of::<u64>()),
+        ("FLOW_ERRORS", size_of::<u32>(), size_of::<u64>()),
+        ("ACTIVE_FLOWS_GAUGE", size_of::<u32>(), size_of::<i64>()),
+    ]
+}
+
+fn map_data(map: &Map) -> Option<&MapData> {
+    match map {
+        Map::HashMap(data)
+        | Map::LruHashMap(data)
+        | Map::PerCpuArray(data)
+        | Map::PerCpuHashMap(data) => Some(data),
+        _ => None,
+    }
+}
+
+/// Checks that the key and value sizes of the maps, as seen by the kernel, match the sizes of
+/// the userspace types. A mismatch, e.g. a type changed in `tcp-tester-common` without
+/// rebuilding the eBPF program, would otherwise silently corrupt the map entries.
+pub fn verify_map_layout(bpf: &Ebpf) -> anyhow::Result<()> {
+    for (name, key_size, value_size) in expected_map_layouts() {
+        let map = bpf
+            .map(name)
+            .with_context(|| format!("Map {} not found", name))?;
+        let info = map_data(map)
+            .with_context(|| format!("Unexpected type of map {}", name))?
+            .info()
+            .with_context(|| format!("Failed to read the info of map {}", name))?;
+        if (info.key_size() as usize, info.value_size() as usize) != (key_size, value_size) {
+            bail!(
+                "Layout mismatch of map {}: the eBPF program has {}-byte keys and {}-byte values, \
+                 userspace expects {}-byte keys and {}-byte values. Rebuild the eBPF program.",
+                name,
+                info.key_size(),
+                info.value_size(),
+                key_size,
+                value_size
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcp_tester_common::Direction;
+
+    #[test]
+    fn test_socket_key_compares_all_bytes() {
+        let key = SocketKey::new(42, Direction::INGRESS);
+        assert!(key == key.reverse().reverse());
+        assert!(key != key.reverse());
+
+        // The padding is zeroed, the keys built from the same values are the same entry of the
+        // BPF maps.
+        assert_eq!(key.as_bytes()[9..], [0; 7]);
+        assert_eq!(key.reverse().as_bytes()[9..], [0; 7]);
+        assert_eq!(
+            key.as_bytes(),
+            SocketKey::new(42, Direction::INGRESS).as_bytes()
+        );
+        let keys = HashSet::from([
+            key,
+            key.reverse().reverse(),
+            SocketKey::new(42, Direction::INGRESS),
+        ]);
+        assert_eq!(keys.len(), 1);
+    }
+
+    #[test]
+    fn test_map_stats_utilization() {
+        assert_eq!(MapStats::new("FLOW_STATS", 896, 1024).utilization_pct, 87.5);
+        assert_eq!(MapStats::new("FLOW_STATS", 0, 1024).utilization_pct, 0.0);
+        assert_eq!(MapStats::new("FLOW_STATS", 0, 0).utilization_pct, 0.0);
+    }
+
+    #[test]
+    fn test_load_ebpf_program_from_object_path() {
+        let capabilities = KernelCapabilities::detect();
+        let options = |object_path| LoadOptions {
+            capabilities: &capabilities,
+            log_level: EbpfLogLevel::Info,
+            object_path: Some(object_path),
+            perf_event_sample_rate: None,
+            map_pin_path: None,
+        };
+        let error = load_ebpf_program(options(Path::new("/nonexistent/tcp-tester-bpf.o")))
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("Failed to read the eBPF object"),
+            "{error}"
+        );
+
+        // Checked like the embedded object before loading.
+        let path = std::env::temp_dir().join(format!("tcp-tester-bpf-{}.o", std::process::id()));
+        std::fs::write(&path, b"not an ELF file").unwrap();
+        let error = load_ebpf_program(options(&path)).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            error.starts_with("Failed to parse the eBPF object"),
+            "{error}"
+        );
+    }
+
+    #[cfg(feature = "requires-bpf")]
+    #[test]
+    fn test_pinned_maps_survive_a_reload() {
+        use aya::maps::HashMap;
+
+        let capabilities = KernelCapabilities::detect();
+        let pin_path =
+            Path::new(BPFFS_PATH).join(format!("tcp-tester-test-{}", std::process::id()));
+        let options = LoadOptions {
+            capabilities: &capabilities,
+            log_level: EbpfLogLevel::Info,
+            object_path: None,
+            perf_event_sample_rate: None,
+            map_pin_path: Some(&pin_path),
+        };
+        let key = FlowKey::builder()
+            .src_ip([10, 0, 0, 1].into())
+            .dst_ip([20, 0, 0, 2].into())
+            .src_port(40000)
+            .dst_port(5001)
+            .build();
+
+        let mut bpf = load_ebpf_program(options).unwrap();
+        let mut flow_stats: HashMap<_, FlowKey, FlowStats> =
+            HashMap::try_from(bpf.map_mut("FLOW_STATS").unwrap()).unwrap();
+        let stats = FlowStats {
+            rx_packets: 3,
+            ..FlowStats::default()
+        };
+        flow_stats.insert(key, stats, 0).unwrap();
+        drop(bpf);
+
+        let bpf = load_ebpf_program(options).unwrap();
+        let flow_stats: HashMap<_, FlowKey, FlowStats> =
+            HashMap::try_from(bpf.map("FLOW_STATS").unwrap()).unwrap();
+        let reloaded = flow_stats.get(&key, 0);
+        std::fs::remove_dir_all(&pin_path).unwrap();
+        assert_eq!(reloaded.unwrap().rx_packets, 3);
+    }
+
+    #[test]
+    fn test_open_pinned_map_read_only_missing_pin() {
+        let error = open_pinned_map_read_only(Path::new("/nonexistent"), "FLOW_STATS")
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{error}"),
+            "Failed to open pinned map /nonexistent/FLOW_STATS"
+        );
+        assert_eq!(
+            error.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_detect_cgroup_path() {
+        let mounts = "proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nodev,noexec,relatime,nsdelegate 0 0
+";
+        assert_eq!(cgroup2_mount_point(mounts), Some("/sys/fs/cgroup"));
+        assert_eq!(
+            cgroup2_mount_point("cgroup /sys/fs/cgroup/cpu cgroup rw 0 0\n"),
+            None
+        );
+
+        // Hybrid hierarchies list the cgroup v1 controllers first.
+        let cgroups = "12:cpu,cpuacct:/user.slice\n0::/user.slice/session-1.scope\n";
+        assert_eq!(own_cgroup(cgroups), Some("/user.slice/session-1.scope"));
+        assert_eq!(own_cgroup("12:cpu,cpuacct:/\n"), None);
+    }
+
+    #[test]
+    fn test_is_bpffs_mounted() {
+        let mounts = "sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+bpf /sys/fs/bpf bpf rw,nosuid,nodev,noexec,relatime,mode=700 0 0
+";
+        assert!(is_bpffs_mounted(mounts));
+        assert!(!is_bpffs_mounted("sysfs /sys sysfs rw 0 0\n"));
+        assert!(!is_bpffs_mounted("tmpfs /sys/fs/bpf tmpfs rw 0 0\n"));
+    }
+}
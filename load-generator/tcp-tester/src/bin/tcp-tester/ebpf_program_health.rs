@@ -0,0 +1,100 @@
+use aya::maps::{MapData, PerCpuArray};
+use aya::sys::{enable_stats, Stats};
+use log::{debug, info, warn};
+use tcp_tester_common::EXCEPTION_CNT_PROGRAMS;
+use tokio::time::Duration;
+
+use crate::ebpf_loader;
+
+/// Time between two consecutive reads of the `EXCEPTION_CNT` map.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Monitors the error paths hit by the eBPF programs, which are otherwise silently swallowed:
+/// unparseable packets in the traffic control programs and failed map updates in the sockops
+/// program.
+pub struct EbpfProgramHealth {
+    exception_cnt: PerCpuArray<MapData, u64>,
+    reported: [u64; EXCEPTION_CNT_PROGRAMS.len()],
+}
+
+impl EbpfProgramHealth {
+    /// # Arguments
+    /// * `exception_cnt` - `EXCEPTION_CNT` map.
+    pub fn new(exception_cnt: PerCpuArray<MapData, u64>) -> Self {
+        EbpfProgramHealth {
+            exception_cnt,
+            reported: [0; EXCEPTION_CNT_PROGRAMS.len()],
+        }
+    }
+
+    /// Reads the number of exceptions of the program in `slot`, merging the values of all the
+    /// CPUs.
+    fn read_exceptions(&self, slot: u32) -> Option<u64> {
+        match self.exception_cnt.get(&slot, 0) {
+            Ok(values) => Some(values.iter().sum()),
+            Err(error) => {
+                debug!("Failed to read exception count {}: {}", slot, error);
+                None
+            }
+        }
+    }
+
+    /// Periodically logs a warning for every program that hit new exceptions since the last
+    /// check.
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            for (slot, program) in EXCEPTION_CNT_PROGRAMS.iter().enumerate() {
+                let Some(ebpf_exceptions_total) = self.read_exceptions(slot as u32) else {
+                    continue;
+                };
+                if ebpf_exceptions_total > self.reported[slot] {
+                    warn!(
+                        program, ebpf_exceptions_total;
+                        "eBPF program {} hit {} exceptions",
+                        program,
+                        ebpf_exceptions_total - self.reported[slot]
+                    );
+                    self.reported[slot] = ebpf_exceptions_total;
+                }
+            }
+        }
+    }
+}
+
+/// Periodically logs the time spent in every program and the number of times it ran, as the
+/// `ebpf_program_run_time_ns_total` and `ebpf_program_run_count_total` counters labeled by
+/// program, i.e. the CPU the programs cost per packet or per socket event.
+///
+/// # Arguments
+/// * `programs` - name and ID of the programs, see `ebpf_loader::program_ids`.
+/// * `interval` - time between two consecutive reports.
+pub async fn log_program_stats_periodically(programs: Vec<(String, u32)>, interval: Duration) {
+    // The kernel accounts the run time of the programs as long as the file descriptor is open,
+    // or when `kernel.bpf_stats_enabled` is set.
+    let _stats = enable_stats(Stats::RunTime)
+        .inspect_err(|error| {
+            warn!(
+                "Failed to enable the eBPF program statistics, they stay at 0 unless \
+                 kernel.bpf_stats_enabled is set: {}",
+                error
+            )
+        })
+        .ok();
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        for stats in ebpf_loader::get_program_stats(&programs) {
+            let (ebpf_program_run_time_ns_total, ebpf_program_run_count_total) =
+                (stats.run_time_ns, stats.run_count);
+            info!(
+                program = stats.name.as_str(), ebpf_program_run_time_ns_total,
+                ebpf_program_run_count_total;
+                "eBPF program run time"
+            );
+        }
+    }
+}
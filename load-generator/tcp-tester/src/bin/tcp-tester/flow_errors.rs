@@ -0,0 +1,94 @@
+use aya::maps::{MapData, PerCpuHashMap};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashSet};
+use tcp_tester_common::FLOW_ERROR_NAMES;
+use tokio::time::Duration;
+
+/// Returns the name of the error `code`, see `FLOW_ERROR_NAMES`.
+fn error_name(code: u32) -> &'static str {
+    FLOW_ERROR_NAMES
+        .get(code as usize)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+/// Reads the `FLOW_ERRORS` map, merging the values of all the CPUs.
+fn read_flow_errors(flow_errors: &PerCpuHashMap<MapData, u32, u64>) -> BTreeMap<u32, u64> {
+    flow_errors
+        .iter()
+        .filter_map(|entry| {
+            entry
+                .inspect_err(|error| debug!("Failed to read flow error count: {}", error))
+                .ok()
+        })
+        .map(|(code, values)| (code, values.iter().sum()))
+        .collect()
+}
+
+/// Returns the error counts by error name, warning about the codes not in `seen`, which are
+/// added to it.
+fn report_flow_errors(
+    counts: &BTreeMap<u32, u64>,
+    seen: &mut HashSet<u32>,
+) -> BTreeMap<&'static str, u64> {
+    counts
+        .iter()
+        .map(|(&code, &count)| {
+            let name = error_name(code);
+            if seen.insert(code) {
+                warn!(
+                    code = name;
+                    "The traffic control programs hit error {} ({} times)", name, count
+                );
+            }
+            (name, count)
+        })
+        .collect()
+}
+
+/// Periodically logs the errors hit by the traffic control programs, per error code, as
+/// `ebpf_tc_errors_total`. The first occurrence of every error code is logged as a warning.
+///
+/// # Arguments
+/// * `flow_errors` - `FLOW_ERRORS` map.
+/// * `interval` - time between two consecutive reports.
+pub async fn log_flow_errors_periodically(
+    flow_errors: PerCpuHashMap<MapData, u32, u64>,
+    interval: Duration,
+) {
+    let mut seen = HashSet::new();
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let counts = read_flow_errors(&flow_errors);
+        if counts.is_empty() {
+            continue;
+        }
+        let ebpf_tc_errors_total = report_flow_errors(&counts, &mut seen);
+        info!(ebpf_tc_errors_total:serde; "Traffic control program errors");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcp_tester_common::{FLOW_ERROR_FLOW_STATS_INSERT, FLOW_ERROR_TCP_HEADER};
+
+    #[test]
+    fn test_report_flow_errors() {
+        let mut seen = HashSet::new();
+        let counts = BTreeMap::from([(FLOW_ERROR_TCP_HEADER, 3), (42, 1)]);
+        let report = report_flow_errors(&counts, &mut seen);
+        assert_eq!(report, BTreeMap::from([("tcp_header", 3), ("unknown", 1)]));
+        assert_eq!(seen, HashSet::from([FLOW_ERROR_TCP_HEADER, 42]));
+
+        let counts = BTreeMap::from([
+            (FLOW_ERROR_TCP_HEADER, 5),
+            (FLOW_ERROR_FLOW_STATS_INSERT, 1),
+        ]);
+        let report = report_flow_errors(&counts, &mut seen);
+        assert_eq!(report["flow_stats_insert"], 1);
+        assert_eq!(seen.len(), 3);
+    }
+}
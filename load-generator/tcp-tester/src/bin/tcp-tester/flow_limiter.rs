@@ -0,0 +1,317 @@
+use log::{info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Burst of connections allowed on top of the rate, as the time the rate takes to make them.
+const BURST: Duration = Duration::from_secs(1);
+
+/// Token bucket refilled at `rate` tokens per second, holding up to `BURST` worth of them. It
+/// is kept as the time at which the bucket is full again, each token taking `1 / rate` to be
+/// refilled. Tokens are reserved ahead of time: the caller waits for the refill to cover its
+/// reservation, so the waiters are served in order.
+#[derive(Debug)]
+struct TokenBucket {
+    interval: Duration,
+    full_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        TokenBucket {
+            interval: Duration::from_secs(1) / rate.max(1),
+            full_at: now,
+        }
+    }
+
+    /// Reserves `tokens`, returning how long to wait until they are available.
+    fn reserve(&mut self, tokens: u32, now: Instant) -> Duration {
+        self.full_at = self.full_at.max(now) + self.interval * tokens;
+        self.full_at
+            .saturating_duration_since(now)
+            .saturating_sub(BURST)
+    }
+}
+
+/// Limits the connection rate to every destination IP, whatever the number of clients
+/// connecting to it, with `--per-ip-rate`.
+#[derive(Debug)]
+pub struct PerDestinationRateLimiter {
+    rate: u32,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl PerDestinationRateLimiter {
+    /// Creates a limiter of `rate` connections per second to every destination IP.
+    pub fn new(rate: u32) -> Self {
+        PerDestinationRateLimiter {
+            rate: rate.max(1),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Waits until `connections` more connections can be made to `ip`.
+    pub async fn acquire(&self, ip: IpAddr, connections: u32) {
+        let wait = {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().unwrap();
+            buckets
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(self.rate, now))
+                .reserve(connections, now)
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Time over which the p99 RTT of `--rtt-backpressure-threshold-ms` is computed, long enough for
+/// the spawn rate not to oscillate.
+const RTT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Time between two evaluations of the p99 RTT.
+const RTT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fraction of the threshold the p99 RTT must drop below for the spawn rate to be restored.
+const RTT_RESUME_RATIO: f64 = 0.7;
+
+/// RTTs of the flows completed within the last `RTT_WINDOW`, oldest first.
+#[derive(Debug, Default)]
+struct RttWindow {
+    samples: VecDeque<(Instant, Duration)>,
+    checked_at: Option<Instant>,
+    throttled: bool,
+}
+
+impl RttWindow {
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, _)) = self.samples.front() {
+            if now.saturating_duration_since(at) <= RTT_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn p99(&self) -> Option<Duration> {
+        let mut rtts: Vec<Duration> = self.samples.iter().map(|&(_, rtt)| rtt).collect();
+        rtts.sort_unstable();
+        let rank = (rtts.len() as f64 * 0.99).ceil() as usize;
+        rtts.get(rank.checked_sub(1)?).copied()
+    }
+}
+
+/// Returns whether the spawn rate is halved, given whether it already is and the p99 RTT. The
+/// rate is halved above `threshold` and restored below `RTT_RESUME_RATIO` of it, and stays as is
+/// in between or without RTTs.
+fn throttle(throttled: bool, p99: Option<Duration>, threshold: Duration) -> bool {
+    match p99 {
+        Some(p99) if p99 > threshold => true,
+        Some(p99) if p99 < threshold.mul_f64(RTT_RESUME_RATIO) => false,
+        _ => throttled,
+    }
+}
+
+/// Halves the connection rate of the clients while the server is overloaded, with
+/// `--rtt-backpressure-threshold-ms`: while the p99 of the RTTs of the flows completed within
+/// the last `RTT_WINDOW` is above the threshold.
+#[derive(Debug)]
+pub struct RttBackpressure {
+    threshold: Duration,
+    window: Mutex<RttWindow>,
+}
+
+impl RttBackpressure {
+    pub fn new(threshold: Duration) -> Self {
+        RttBackpressure {
+            threshold,
+            window: Mutex::default(),
+        }
+    }
+
+    /// Records the RTT of a completed flow, as smoothed by the kernel.
+    pub fn record_rtt(&self, rtt: Duration) {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        window.samples.push_back((now, rtt));
+        window.expire(now);
+    }
+
+    /// Returns whether the connection rate is to be halved, the p99 RTT being evaluated at most
+    /// every `RTT_CHECK_INTERVAL`.
+    pub fn throttled(&self) -> bool {
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        if window
+            .checked_at
+            .is_some_and(|at| now.saturating_duration_since(at) < RTT_CHECK_INTERVAL)
+        {
+            return window.throttled;
+        }
+        window.checked_at = Some(now);
+        window.expire(now);
+
+        let p99 = window.p99();
+        let throttled = throttle(window.throttled, p99, self.threshold);
+        if throttled != window.throttled {
+            let p99_rtt_ms = p99.unwrap_or_default().as_millis() as u64;
+            if throttled {
+                warn!(
+                    p99_rtt_ms;
+                    "p99 RTT of {}ms above {:?}, halving the connection rate",
+                    p99_rtt_ms,
+                    self.threshold
+                );
+            } else {
+                info!(p99_rtt_ms; "p99 RTT back to {}ms, restoring the connection rate", p99_rtt_ms);
+            }
+            window.throttled = throttled;
+        }
+        throttled
+    }
+}
+
+/// Period at which `--rate-file` is read again.
+const RATE_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Highest connection rate, the connections being paced with a microsecond resolution.
+pub const MAX_CONNECTION_RATE: u32 = 1_000_000;
+
+/// Parses the content of `--rate-file`, a positive integer on one line, at most
+/// `MAX_CONNECTION_RATE`.
+fn parse_rate(content: &str) -> Result<u32, String> {
+    let content = content.trim();
+    match content.parse::<u32>() {
+        Ok(0) => Err("the connection rate must be greater than 0".to_string()),
+        Ok(rate) if rate > MAX_CONNECTION_RATE => Err(format!(
+            "the connection rate must be at most {}",
+            MAX_CONNECTION_RATE
+        )),
+        Ok(rate) => Ok(rate),
+        Err(error) => Err(format!("invalid connection rate {:?}: {}", content, error)),
+    }
+}
+
+/// Connection rate read from `--rate-file`, replacing `--connection-rate` so that it can be
+/// changed at runtime by updating the file.
+#[derive(Debug)]
+pub struct RateFile {
+    path: String,
+    rate: AtomicU32,
+}
+
+impl RateFile {
+    /// Reads the rate from `path`, starting at `default_rate` when the file can't be read.
+    pub fn open(path: String, default_rate: u32) -> Self {
+        let rate = match Self::read(&path) {
+            Ok(rate) => rate,
+            Err(error) => {
+                warn!(
+                    "Failed to read the connection rate from {}, starting at {} per sec: {}",
+                    path, default_rate, error
+                );
+                default_rate
+            }
+        };
+        RateFile {
+            path,
+            rate: AtomicU32::new(rate),
+        }
+    }
+
+    fn read(path: &str) -> Result<u32, String> {
+        let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        parse_rate(&content)
+    }
+
+    /// Returns the connection rate last read from the file.
+    pub fn rate(&self) -> u32 {
+        self.rate.load(Ordering::Relaxed)
+    }
+
+    /// Reads the file every `RATE_FILE_POLL_INTERVAL`, the previous rate being kept when the
+    /// file can't be read or has an invalid content.
+    pub async fn poll_periodically(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(RATE_FILE_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let rate = match Self::read(&self.path) {
+                Ok(rate) => rate,
+                Err(error) => {
+                    warn!(
+                        "Failed to read the connection rate from {}, keeping {} per sec: {}",
+                        self.path,
+                        self.rate(),
+                        error
+                    );
+                    continue;
+                }
+            };
+            let old_rate = self.rate.swap(rate, Ordering::Relaxed);
+            if old_rate != rate {
+                info!(old_rate, rate; "Connection rate changed from {} to {} per sec", old_rate, rate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_reservations() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, start);
+
+        // A second worth of burst, then the reservations wait for the refill.
+        assert_eq!(bucket.reserve(10, start), Duration::ZERO);
+        assert_eq!(bucket.reserve(1, start), Duration::from_millis(100));
+        assert_eq!(bucket.reserve(2, start), Duration::from_millis(300));
+
+        // The refill covers the reservations first.
+        let later = start + Duration::from_millis(400);
+        assert_eq!(bucket.reserve(1, later), Duration::ZERO);
+
+        // Unused tokens don't accumulate over the burst.
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(10, much_later), Duration::ZERO);
+        assert_eq!(bucket.reserve(1, much_later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("250\n"), Ok(250));
+        assert_eq!(parse_rate("1000000"), Ok(MAX_CONNECTION_RATE));
+        assert!(parse_rate("1000001").is_err());
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-5").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("").is_err());
+    }
+
+    #[test]
+    fn test_rtt_backpressure_hysteresis() {
+        let ms = Duration::from_millis;
+        let threshold = ms(100);
+        assert!(throttle(false, Some(ms(101)), threshold));
+        // Throttled until the p99 drops below 70ms.
+        assert!(throttle(true, Some(ms(80)), threshold));
+        assert!(throttle(true, None, threshold));
+        assert!(!throttle(true, Some(ms(69)), threshold));
+        assert!(!throttle(false, Some(ms(100)), threshold));
+
+        let start = Instant::now();
+        let mut window = RttWindow::default();
+        assert_eq!(window.p99(), None);
+        window.samples = (1..=100).map(|rtt| (start, ms(rtt))).collect();
+        assert_eq!(window.p99(), Some(ms(99)));
+        window.samples.push_back((start + ms(6000), ms(1)));
+        window.expire(start + ms(6000));
+        assert_eq!(window.p99(), Some(ms(1)));
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::Context;
+use aya::maps::{HashMap, MapData};
+use log::{debug, error};
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use tcp_tester_common::{FlowKey, FlowStats};
+use tokio::time::Duration;
+
+/// A `FLOW_STATS` map entry, as written to the stats file.
+#[derive(Debug, Serialize)]
+pub struct FlowStatsEntry {
+    src: String,
+    dst: String,
+    protocol: &'static str,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_packets: u64,
+    tx_packets: u64,
+}
+
+impl FlowStatsEntry {
+    fn new(key: &FlowKey, stats: &FlowStats) -> Self {
+        FlowStatsEntry {
+            src: SocketAddrV4::new(Ipv4Addr::from(key.sip), key.sport as u16).to_string(),
+            dst: SocketAddrV4::new(Ipv4Addr::from(key.dip), key.dport as u16).to_string(),
+            // The traffic control programs only track TCP flows.
+            protocol: "tcp",
+            rx_bytes: stats.rx_bytes,
+            tx_bytes: stats.tx_bytes,
+            rx_packets: stats.rx_packets,
+            tx_packets: stats.tx_packets,
+        }
+    }
+}
+
+pub fn read_flow_stats(flow_stats: &HashMap<MapData, FlowKey, FlowStats>) -> Vec<FlowStatsEntry> {
+    flow_stats
+        .iter()
+        .filter_map(|entry| match entry {
+            Ok((key, stats)) => Some(FlowStatsEntry::new(&key, &stats)),
+            Err(error) => {
+                debug!("Failed to read flow stats entry: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Writes the stats file atomically, so readers never observe a partially written file.
+async fn write_flow_stats(path: &str, entries: &[FlowStatsEntry]) -> anyhow::Result<()> {
+    let json = serde_json::to_string(entries)?;
+    let tmp_path = format!("{}.tmp", path);
+    tokio::fs::write(&tmp_path, json)
+        .await
+        .with_context(|| format!("Failed to write stats file: {}", tmp_path))?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Failed to rename {} to {}", tmp_path, path))?;
+    Ok(())
+}
+
+/// Periodically dumps the contents of the `FLOW_STATS` map to a JSON file.
+///
+/// # Arguments
+/// * `flow_stats` - `FLOW_STATS` map populated by the traffic control programs.
+/// * `path` - path of the file where the stats are written.
+/// * `interval` - time between two consecutive writes.
+pub async fn write_flow_stats_periodically(
+    flow_stats: HashMap<MapData, FlowKey, FlowStats>,
+    path: String,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let entries = read_flow_stats(&flow_stats);
+        debug!("Writing stats of {} flows to {}", entries.len(), path);
+        if let Err(error) = write_flow_stats(&path, &entries).await {
+            error!("Failed to write flow stats: {:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_stats_entry_addresses() {
+        let key = FlowKey::builder()
+            .src_ip(Ipv4Addr::new(1, 1, 1, 1))
+            .dst_ip(Ipv4Addr::new(2, 2, 2, 2))
+            .src_port(40000)
+            .dst_port(5001)
+            .build();
+        let entry = FlowStatsEntry::new(&key, &FlowStats::default());
+        assert_eq!(entry.src, "1.1.1.1:40000");
+        assert_eq!(entry.dst, "2.2.2.2:5001");
+        assert_eq!(
+            FlowStatsEntry::new(&key.reverse(), &FlowStats::default()).src,
+            "2.2.2.2:5001"
+        );
+    }
+}
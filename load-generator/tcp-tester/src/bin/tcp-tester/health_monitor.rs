@@ -0,0 +1,160 @@
+use anyhow::{bail, Context};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::client::{self, ClientOptions, HealthStatus};
+
+/// Time after which a webhook that didn't respond is considered failed.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body of the alerts posted to `--health-check-alert-webhook`.
+#[derive(Debug, Serialize)]
+struct HealthAlert<'a> {
+    status: &'a str,
+    reason: &'a str,
+    server: SocketAddr,
+    timestamp_ms: u64,
+}
+
+/// Parses a webhook URL, `http://host[:port]/path`. HTTPS is not supported.
+pub fn parse_webhook_url(url: &str) -> Result<String, String> {
+    let uri: http::Uri = url
+        .parse()
+        .map_err(|error| format!("Invalid webhook URL {url}: {error}"))?;
+    if uri.scheme_str() != Some("http") || uri.host().is_none() {
+        return Err(format!("Webhook URL {url} must be http://host[:port]/path"));
+    }
+    Ok(url.to_string())
+}
+
+/// Posts `body` as JSON to the webhook `url`, over HTTP/1.1, failing unless it answers with a
+/// 2xx status.
+async fn post_webhook(url: &str, body: &impl Serialize) -> anyhow::Result<()> {
+    let uri: http::Uri = url.parse()?;
+    let host = uri.host().context("Webhook URL without host")?;
+    let port = uri.port_u16().unwrap_or(80);
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let body = serde_json::to_vec(body)?;
+
+    let exchange = async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        let head = format!(
+            "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = timeout(WEBHOOK_TIMEOUT, exchange)
+        .await
+        .with_context(|| format!("Webhook {} timed out after {:?}", url, WEBHOOK_TIMEOUT))?
+        .with_context(|| format!("Failed to post to webhook {}", url))?;
+
+    // Status line, e.g. `HTTP/1.1 204 No Content`.
+    let status = String::from_utf8_lossy(&response)
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(status) => bail!("Webhook {} answered with status {}", url, status),
+        None => bail!("Webhook {} sent an invalid response", url),
+    }
+}
+
+/// Every `interval`, runs a test flow to the server with `client::check_flow_completion`, which
+/// is not conditioned nor accounted by the eBPF programs. The failures are logged and, when
+/// `webhook` is set, posted to it as a JSON `HealthAlert`.
+///
+/// # Arguments
+/// * `addr` - Address and port of the server.
+/// * `options` - settings applied to the test connections.
+/// * `interval` - time between two consecutive test flows.
+/// * `webhook` - URL the failures are posted to, if any.
+pub async fn check_flow_completion_periodically(
+    addr: SocketAddr,
+    options: ClientOptions,
+    interval: Duration,
+    webhook: Option<String>,
+) {
+    // The first test flow waits for an interval, for the servers to be listening.
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        interval.tick().await;
+
+        let (status, reason) = match client::check_flow_completion(addr, &options).await {
+            HealthStatus::Ok => {
+                info!("Health check flow to {} completed", addr);
+                continue;
+            }
+            HealthStatus::Degraded(reason) => ("degraded", reason),
+            HealthStatus::Down(reason) => ("down", reason),
+        };
+        error!(status; "Health check flow to {} failed: {}", addr, reason);
+        let Some(webhook) = &webhook else {
+            continue;
+        };
+        let alert = HealthAlert {
+            status,
+            reason: &reason,
+            server: addr,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        };
+        if let Err(error) = post_webhook(webhook, &alert).await {
+            warn!("Failed to send the health check alert: {:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_post_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request[..n]).to_string()
+        });
+
+        post_webhook(&url, &serde_json::json!({"status": "down"}))
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("POST /alerts HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(
+            request.ends_with("\r\n\r\n{\"status\":\"down\"}"),
+            "{}",
+            request
+        );
+
+        assert!(parse_webhook_url("https://example.com/alerts").is_err());
+        assert!(parse_webhook_url("http://10.0.0.1:8080/alerts").is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+use aya::util::KernelVersion;
+use log::warn;
+use serde::Serialize;
+use std::path::Path;
+
+/// Kernel BTF, required to relocate CO-RE programs.
+const KERNEL_BTF_PATH: &str = "/sys/kernel/btf/vmlinux";
+
+/// eBPF features supported by the running kernel. They are detected once at startup, so the
+/// code paths depending on them are selected in a single place.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct KernelCapabilities {
+    /// Traffic control programs can be attached with tcx links (Linux >= 6.6), otherwise they
+    /// are attached through netlink.
+    pub has_tcx: bool,
+    /// Sockops programs are attached with `BPF_LINK_CREATE` (Linux >= 5.7).
+    pub has_sockops_kern_5_7: bool,
+    /// CO-RE relocations can be applied when loading the programs: the kernel exposes its BTF in
+    /// `/sys/kernel/btf/vmlinux` and Linux >= 5.2.
+    pub has_co_re: bool,
+}
+
+impl KernelCapabilities {
+    /// Detects the capabilities of the running kernel. When its version cannot be determined,
+    /// the features depending on it are assumed to be unsupported.
+    pub fn detect() -> KernelCapabilities {
+        let version = KernelVersion::current().unwrap_or_else(|error| {
+            warn!(
+                "Failed to get the kernel version, assuming an old kernel: {}",
+                error
+            );
+            KernelVersion::new(0, 0, 0)
+        });
+
+        KernelCapabilities {
+            has_tcx: version >= KernelVersion::new(6, 6, 0),
+            has_sockops_kern_5_7: version >= KernelVersion::new(5, 7, 0),
+            has_co_re: Path::new(KERNEL_BTF_PATH).exists()
+                && version >= KernelVersion::new(5, 2, 0),
+        }
+    }
+}
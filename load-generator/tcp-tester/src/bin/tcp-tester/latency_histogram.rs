@@ -0,0 +1,114 @@
+use anyhow::Context;
+use aya::maps::{MapData, PerCpuArray};
+use hdrhistogram::serialization::{Serializer, V2Serializer};
+use hdrhistogram::Histogram;
+use log::{debug, info};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use tcp_tester_common::LATENCY_HISTOGRAM_BUCKETS;
+use tokio::time::Duration;
+
+/// Number of flows that completed within `upper_bound_us`, and after the previous bucket bound.
+#[derive(Debug, Serialize)]
+pub struct LatencyBucket {
+    upper_bound_us: u64,
+    flows: u64,
+}
+
+/// Reads the `LATENCY_HISTOGRAM` map, merging the values of all the CPUs.
+pub fn read_latency_histogram(histogram: &PerCpuArray<MapData, u64>) -> Vec<LatencyBucket> {
+    (0..LATENCY_HISTOGRAM_BUCKETS)
+        .map(|bucket| {
+            let flows = match histogram.get(&bucket, 0) {
+                Ok(values) => values.iter().sum(),
+                Err(error) => {
+                    debug!(
+                        "Failed to read latency histogram bucket {}: {}",
+                        bucket, error
+                    );
+                    0
+                }
+            };
+            LatencyBucket {
+                upper_bound_us: 1 << (bucket + 1),
+                flows,
+            }
+        })
+        .collect()
+}
+
+/// Converts the buckets to an HdrHistogram of the flow durations in microseconds, the flows of
+/// a bucket being recorded at its upper bound. The last bucket also holds the longer flows.
+pub fn to_hdr_histogram(buckets: &[LatencyBucket]) -> anyhow::Result<Histogram<u64>> {
+    let mut histogram = Histogram::new(3)?;
+    for bucket in buckets.iter().filter(|bucket| bucket.flows > 0) {
+        histogram.record_n(bucket.upper_bound_us, bucket.flows)?;
+    }
+    Ok(histogram)
+}
+
+/// Writes the buckets to `path` in the HdrHistogram V2 encoding, readable by the HdrHistogram
+/// tooling, e.g. `hdrhist-plot` or the HistogramLogAnalyzer.
+pub fn write_hdr_histogram(buckets: &[LatencyBucket], path: &Path) -> anyhow::Result<()> {
+    let histogram = to_hdr_histogram(buckets)?;
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    V2Serializer::new()
+        .serialize(&histogram, &mut BufWriter::new(file))
+        .map_err(|error| anyhow::anyhow!("Failed to encode the histogram: {:?}", error))?;
+    Ok(())
+}
+
+/// Periodically logs the flow duration histogram populated by the sockops program.
+///
+/// # Arguments
+/// * `histogram` - `LATENCY_HISTOGRAM` map.
+/// * `interval` - time between two consecutive reports.
+pub async fn log_latency_histogram_periodically(
+    histogram: PerCpuArray<MapData, u64>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let latency_histogram = read_latency_histogram(&histogram);
+        info!(latency_histogram:serde; "Flow duration histogram");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hdrhistogram::serialization::Deserializer;
+
+    #[test]
+    fn test_hdr_histogram_encoding_round_trip() {
+        let buckets: Vec<LatencyBucket> = (0..LATENCY_HISTOGRAM_BUCKETS)
+            .map(|bucket| LatencyBucket {
+                upper_bound_us: 1 << (bucket + 1),
+                flows: (bucket as u64 % 5) * 10,
+            })
+            .collect();
+        let histogram = to_hdr_histogram(&buckets).unwrap();
+        assert_eq!(
+            histogram.len(),
+            buckets.iter().map(|bucket| bucket.flows).sum::<u64>()
+        );
+
+        let mut encoded = Vec::new();
+        V2Serializer::new()
+            .serialize(&histogram, &mut encoded)
+            .unwrap();
+        let decoded: Histogram<u64> = Deserializer::new()
+            .deserialize(&mut encoded.as_slice())
+            .unwrap();
+        assert_eq!(
+            decoded.value_at_quantile(0.99),
+            histogram.value_at_quantile(0.99)
+        );
+        assert_eq!(decoded.len(), histogram.len());
+    }
+}
@@ -0,0 +1,91 @@
+use flexi_logger::{
+    Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, Logger, LoggerHandle, Naming,
+};
+use log::Record;
+use std::io::Write;
+use uuid::Uuid;
+
+tokio::task_local! {
+    /// Flow of the task, set for the task of every client connection, so that its logs are
+    /// correlated without passing the ID around.
+    pub static FLOW_ID: Uuid;
+}
+
+/// Returns the flow of the running task, None outside of the tasks of the flows.
+pub fn current_flow_id() -> Option<Uuid> {
+    FLOW_ID.try_with(|flow_id| *flow_id).ok()
+}
+
+/// Same as `flexi_logger::opt_format`, followed by the flow of the task if any.
+fn flow_format(w: &mut dyn Write, now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+    flexi_logger::opt_format(w, now, record)?;
+    write_flow_id(w)
+}
+
+fn write_flow_id(w: &mut dyn Write) -> std::io::Result<()> {
+    match current_flow_id() {
+        Some(flow_id) => write!(w, " flow_id={}", flow_id),
+        None => Ok(()),
+    }
+}
+
+/// Sets up the logger, filtered by `RUST_LOG`. The logs go to stderr, and to a rotated file as
+/// well when `log_file` is set. The logs of the flow tasks end with their `flow_id`.
+///
+/// The returned handle flushes the log file when dropped, it must be kept alive as long as the
+/// process logs.
+///
+/// # Arguments
+/// * `log_file` - path of the log file.
+/// * `max_size_mb` - size at which the log file is rotated.
+/// * `keep` - number of rotated log files kept.
+pub fn init_logging(
+    log_file: Option<&str>,
+    max_size_mb: u64,
+    keep: usize,
+) -> anyhow::Result<Option<LoggerHandle>> {
+    let Some(log_file) = log_file else {
+        env_logger::Builder::from_default_env()
+            .format(|buf, record| {
+                // Same as the default format of env_logger.
+                write!(
+                    buf,
+                    "[{} {:<5} {}] {}",
+                    buf.timestamp(),
+                    buf.default_styled_level(record.level()),
+                    record.module_path().unwrap_or_default(),
+                    record.args()
+                )?;
+                write_flow_id(buf)?;
+                writeln!(buf)
+            })
+            .init();
+        return Ok(None);
+    };
+
+    // Same default level as env_logger.
+    let handle = Logger::try_with_env_or_str("error")?
+        .format(flow_format)
+        .log_to_file(FileSpec::try_from(log_file)?)
+        .duplicate_to_stderr(Duplicate::All)
+        .rotate(
+            Criterion::Size(max_size_mb * 1024 * 1024),
+            Naming::Numbers,
+            Cleanup::KeepLogFiles(keep),
+        )
+        .start()?;
+    Ok(Some(handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_current_flow_id() {
+        assert_eq!(current_flow_id(), None);
+        let flow_id = Uuid::new_v4();
+        let scoped = FLOW_ID.scope(flow_id, async { current_flow_id() }).await;
+        assert_eq!(scoped, Some(flow_id));
+    }
+}
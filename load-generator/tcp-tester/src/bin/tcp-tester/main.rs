@@ -1,34 +1,672 @@
+mod active_flows;
+mod audit_log;
 mod cli;
 mod client;
+mod config_generator;
+mod control_plane;
+mod cpu_affinity;
 mod ebpf_loader;
+mod ebpf_program_health;
+mod flow_errors;
+mod flow_limiter;
+mod flow_stats;
+mod health_monitor;
+mod kernel_capabilities;
+mod latency_histogram;
+mod logging;
+mod map_ttl;
+mod namespace_manager;
+mod namespaces;
+mod perf_samples;
+mod qdisc_stats;
+mod queue_monitor;
+mod raw_capture;
+mod reconcile;
+mod report;
+mod scenario;
+mod self_test;
 mod server;
+mod socket_config_snapshot;
+mod stats_window;
+mod telemetry;
 
+use aya::maps::{HashMap, Map, PerCpuArray, PerCpuHashMap};
 use clap::Parser;
-use log::info;
+use log::{error, info, warn};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use tokio::task::JoinSet;
+use tokio::time::Duration;
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
+/// Flow events buffered for the slowest control plane subscriber.
+const FLOW_EVENTS_CAPACITY: usize = 1024;
 
+fn run_config_generator(
+    base_config: &str,
+    params: &[String],
+    ranges: &[String],
+    output_dir: &str,
+) -> anyhow::Result<()> {
+    if params.len() != ranges.len() {
+        anyhow::bail!("Every --param requires a --range");
+    }
+    let params = params
+        .iter()
+        .cloned()
+        .zip(ranges.iter().map(|range| range.parse()))
+        .map(|(param, range)| range.map(|range| (param, range)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let files =
+        config_generator::generate_configs(base_config, &params, std::path::Path::new(output_dir))?;
+    // The paths are the output of the subcommand, for the configurations to be run by a script.
+    for file in &files {
+        println!("{}", file.display());
+    }
+    info!(
+        "Generated {} configuration files in {}",
+        files.len(),
+        output_dir
+    );
+    Ok(())
+}
+
+/// Writes the JSON Schema of the flow configurations to `output`, stdout when not set.
+#[cfg(feature = "schema")]
+fn write_flow_config_schema(output: Option<&str>) -> anyhow::Result<()> {
+    let schema = serde_json::to_string_pretty(&tcp_tester_common::FlowConfig::json_schema())?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, schema + "\n")?;
+            println!("{}", path);
+        }
+        None => println!("{}", schema),
+    }
+    Ok(())
+}
+
+fn print_programs(programs: &[ebpf_loader::ProgramInfo]) {
+    println!("{:>8}  {:<20}  {:<16}  ATTACHED", "ID", "NAME", "TYPE");
+    for program in programs {
+        println!(
+            "{:>8}  {:<20}  {:<16}  {}",
+            program.id,
+            program.name,
+            program.program_type,
+            if program.attached { "yes" } else { "no" }
+        );
+    }
+}
+
+fn print_program_complexities(complexities: &[ebpf_loader::ProgramComplexity]) {
+    println!(
+        "{:<32}  {:>14}  {:>15}  VERIFIED_INSNS",
+        "NAME", "JITED_PROG_LEN", "XLATED_PROG_LEN"
+    );
+    let or_unknown = |value: Option<u32>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+    for complexity in complexities {
+        println!(
+            "{:<32}  {:>14}  {:>15}  {}",
+            complexity.name,
+            complexity.jited_prog_len,
+            or_unknown(complexity.xlated_prog_len),
+            or_unknown(complexity.verified_insns)
+        );
+    }
+}
+
+fn main() -> ExitCode {
     let params = cli::Params::parse();
+    let _logger = match logging::init_logging(
+        params.log_file.as_deref(),
+        params.log_file_max_size,
+        params.log_file_keep,
+    ) {
+        Ok(logger) => logger,
+        Err(error) => {
+            eprintln!("Failed to set up logging: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // The main thread runs the top-level future, it shares all the CPUs.
+    let cpus = params.affinity.clone().unwrap_or_default();
+    if !cpus.is_empty() {
+        cpu_affinity::pin_current_thread(&cpus);
+    }
+    let flow_runtimes = match params.affinity_mode {
+        Some(cli::AffinityMode::Sticky) => match cpu_affinity::FlowRuntimes::start(&cpus) {
+            Ok(flow_runtimes) => Some(Arc::new(flow_runtimes)),
+            Err(error) => {
+                error!("Failed to start the runtimes of the flows: {:?}", error);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => None,
+    };
+    let runtime = match cpu_affinity::build_runtime(&cpus, params.affinity_mode) {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            error!("Failed to start the runtime: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+    runtime.block_on(run(params, flow_runtimes))
+}
+
+async fn run(
+    params: cli::Params,
+    flow_runtimes: Option<Arc<cpu_affinity::FlowRuntimes>>,
+) -> ExitCode {
+    if let Some(cli::Command::ConfigGenerator {
+        base_config,
+        params,
+        ranges,
+        output_dir,
+    }) = &params.command
+    {
+        if let Err(error) = run_config_generator(base_config, params, ranges, output_dir) {
+            error!("Failed to generate configurations: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    #[cfg(feature = "schema")]
+    if let Some(cli::Command::Schema { output }) = &params.command {
+        if let Err(error) = write_flow_config_schema(output.as_deref()) {
+            error!("Failed to write the flow configuration schema: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+    let tracer_provider = match telemetry::init_telemetry(params.otlp_endpoint.as_deref()) {
+        Ok(tracer_provider) => tracer_provider,
+        Err(error) => {
+            error!("Failed to set up telemetry: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
     let clients_per_server = 1u8;
     info!(params:serde, clients_per_server; "Starting tcp-tester");
 
+    let scenario = match &params.scenario {
+        Some(path) => match scenario::TestScenario::from_file(path).await {
+            Ok(scenario) => Some(scenario),
+            Err(error) => {
+                error!("Failed to load scenario: {:?}", error);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let topology = match &params.topology {
+        Some(path) => {
+            let topology = match namespace_manager::Topology::from_file(path).await {
+                Ok(topology) => topology,
+                Err(error) => {
+                    error!("Failed to load topology: {:?}", error);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if let Err(error) = topology.apply(&params.namespace_prefix).await {
+                error!("Failed to set up topology: {:?}", error);
+                return ExitCode::FAILURE;
+            }
+            info!("Topology {} set up", path);
+            Some(topology)
+        }
+        None => None,
+    };
+
+    let namespaces = namespaces::Namespaces::new(&params.namespace_prefix);
+    let middle_box_namespace = match params.container_pid {
+        Some(pid) => namespaces::MiddleBoxNamespace::Container(pid),
+        None => namespaces::MiddleBoxNamespace::Named(namespaces.tcp_tester.clone()),
+    };
+    let capabilities = kernel_capabilities::KernelCapabilities::detect();
+    info!(capabilities:serde; "Detected kernel capabilities");
+    let cgroup_path = match &params.cgroup_path {
+        Some(cgroup_path) => cgroup_path.clone(),
+        None => match ebpf_loader::detect_cgroup_path() {
+            Ok(cgroup_path) => {
+                info!("Detected cgroup {}", cgroup_path);
+                cgroup_path
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to detect the cgroup, using {}: {:?}",
+                    ebpf_loader::DEFAULT_CGROUP_PATH,
+                    error
+                );
+                ebpf_loader::DEFAULT_CGROUP_PATH.into()
+            }
+        },
+    };
+    let load_options = ebpf_loader::LoadOptions {
+        capabilities: &capabilities,
+        log_level: params.ebpf_log_level,
+        object_path: params.ebpf_object.as_deref().map(std::path::Path::new),
+        perf_event_sample_rate: params.perf_event_sample_rate,
+        map_pin_path: params
+            .pin_maps
+            .then(|| std::path::Path::new(ebpf_loader::PINNED_MAPS_PATH)),
+    };
+
+    if let Some(cli::Command::Report {
+        map_pin_path,
+        histogram_output,
+    }) = &params.command
+    {
+        let setup_ebpf = || {
+            client::setup_ebpf(
+                cgroup_path.clone(),
+                params.passive,
+                load_options,
+                &middle_box_namespace,
+                params.tc_handle,
+                params.self_test.then_some(namespaces.client.as_str()),
+            )
+        };
+        if let Err(error) = report::run_report(
+            std::path::Path::new(map_pin_path),
+            setup_ebpf,
+            Duration::from_secs(params.stats_interval),
+            histogram_output.as_deref().map(std::path::Path::new),
+        )
+        .await
+        {
+            error!("Failed to report: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if let Some(cli::Command::Reconcile { map_pin_path }) = &params.command {
+        if let Err(error) =
+            reconcile::run_reconcile(std::path::Path::new(map_pin_path), &namespaces.client)
+        {
+            error!("Failed to reconcile flows: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if params.dry_attach {
+        let complexities = ebpf_loader::load_ebpf_program(load_options)
+            .map_err(anyhow::Error::msg)
+            .and_then(|mut bpf| {
+                ebpf_loader::verify_map_layout(&bpf)?;
+                ebpf_loader::load_program_complexities(&mut bpf)
+            });
+        return match complexities {
+            Ok(complexities) => {
+                print_program_complexities(&complexities);
+                ExitCode::SUCCESS
+            }
+            Err(error) => {
+                error!("Failed to load the eBPF programs: {:?}", error);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if let Some(cli::Command::ListPrograms) = &params.command {
+        match client::setup_ebpf(
+            cgroup_path.clone(),
+            params.passive,
+            load_options,
+            &middle_box_namespace,
+            params.tc_handle,
+            params.self_test.then_some(namespaces.client.as_str()),
+        ) {
+            Ok(bpf) => print_programs(&ebpf_loader::list_programs(&bpf)),
+            Err(error) => {
+                error!("Failed to set up eBPF: {:?}", error);
+                return ExitCode::FAILURE;
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let traffic_shaping = params.traffic_shaping == cli::OnOff::On;
+    if params.passive && traffic_shaping {
+        warn!("Passive mode enabled, traffic shaping is ignored");
+    }
+
+    if params.pin_maps {
+        let previous_maps =
+            ebpf_loader::pinned_map_names(std::path::Path::new(ebpf_loader::PINNED_MAPS_PATH));
+        if !previous_maps.is_empty() {
+            info!(previous_maps:serde; "Reusing the maps pinned by a previous run");
+        }
+    }
+    // The eBPF programs are attached once and shared by all the clients, they get detached when
+    // the handle is dropped.
+    let ebpf_setup = (params.passive || traffic_shaping).then(|| {
+        client::setup_ebpf(
+            cgroup_path.clone(),
+            params.passive,
+            load_options,
+            &middle_box_namespace,
+            params.tc_handle,
+            params.self_test.then_some(namespaces.client.as_str()),
+        )
+    });
+    let mut bpf = match ebpf_setup {
+        Some(Ok(bpf)) => Some(bpf),
+        // The clients connect without traffic control when there are no maps.
+        Some(Err(error)) if params.fallback_to_plain => {
+            warn!(
+                "Failed to set up eBPF, falling back to plain connections: {:?}",
+                error
+            );
+            None
+        }
+        Some(Err(error)) => {
+            error!("Failed to set up eBPF: {:?}", error);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    // Opened before the maps are taken by the tasks using them.
+    let hash_maps = match bpf.as_ref().map(ebpf_loader::clone_hash_maps) {
+        Some(Ok(hash_maps)) => hash_maps,
+        Some(Err(error)) => {
+            warn!(
+                "Failed to open the hash maps for their utilization: {:?}",
+                error
+            );
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    let audit_log = match params
+        .audit_log
+        .as_deref()
+        .map(audit_log::AuditLogger::open)
+    {
+        Some(Ok(audit_log)) => Some(Arc::new(audit_log)),
+        Some(Err(error)) => {
+            error!("{:?}", error);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let client_maps: Option<client::ClientMaps> =
+        bpf.as_mut()
+            .filter(|_| !params.passive)
+            .map(|bpf| client::ClientMaps {
+                socket_config: Arc::new(Mutex::new(client::SocketConfigMaps {
+                    config: HashMap::try_from(bpf.take_map("SOCKET_CONFIG").unwrap()).unwrap(),
+                    timestamps: HashMap::try_from(
+                        bpf.take_map("SOCKET_CONFIG_TIMESTAMPS").unwrap(),
+                    )
+                    .unwrap(),
+                    audit_log: audit_log.clone(),
+                })),
+                flow_config: Arc::new(Mutex::new(
+                    HashMap::try_from(bpf.take_map("FLOW_CONFIG").unwrap()).unwrap(),
+                )),
+            });
+
+    if audit_log.is_some() && client_maps.is_none() {
+        warn!("The audit log requires traffic shaping");
+    }
     let mut tasks = JoinSet::new();
-    for i in 0..params.servers {
-        let port = params.starting_port.wrapping_add(i.into());
-        tasks.spawn(server::server(port, params.response_delay_ms));
+    if let (Some(path), Some(topology)) = (params.topology.clone(), topology) {
+        tasks.spawn(namespace_manager::reload_topology_on_sighup(
+            path,
+            params.namespace_prefix.clone(),
+            topology,
+        ));
+    }
+    if let Some(path) = params.snapshot_on_sigterm.clone() {
+        match &client_maps {
+            Some(client_maps) => {
+                tasks.spawn(socket_config_snapshot::write_snapshot_on_sigterm(
+                    client_maps.socket_config.clone(),
+                    path,
+                ));
+            }
+            None => warn!("Writing a snapshot requires traffic shaping"),
+        }
+    }
+    if let (Some(ttl), Some(client_maps)) = (params.map_entry_ttl_secs, &client_maps) {
+        tasks.spawn(map_ttl::expire_socket_config_periodically(
+            client_maps.socket_config.clone(),
+            Duration::from_secs(ttl),
+            Duration::from_secs(params.map_ttl_check_interval),
+        ));
+    }
+    // The map is also read by the stats file writer.
+    if let Some(interval) = params.reconcile_interval {
+        match bpf
+            .as_ref()
+            .map(|bpf| ebpf_loader::clone_map(bpf, "FLOW_STATS"))
+        {
+            Some(Ok(map)) => {
+                tasks.spawn(reconcile::reconcile_periodically(
+                    HashMap::try_from(Map::LruHashMap(map)).unwrap(),
+                    namespaces.client.clone(),
+                    Duration::from_secs(interval),
+                ));
+            }
+            Some(Err(error)) => error!("Failed to open FLOW_STATS: {:?}", error),
+            None => warn!("Flow reconciliation requires traffic shaping or passive mode"),
+        }
+    }
+    if let Some(path) = params.interface_stats_file.clone() {
+        match bpf.as_mut() {
+            Some(bpf) => {
+                let map = bpf.take_map("FLOW_STATS").unwrap();
+                tasks.spawn(flow_stats::write_flow_stats_periodically(
+                    HashMap::try_from(map).unwrap(),
+                    path,
+                    Duration::from_secs(params.stats_interval),
+                ));
+            }
+            None => warn!("Interface stats file requires traffic shaping or passive mode"),
+        }
+    }
+
+    // The flow durations are only recorded by the sockops program, which is not attached in
+    // passive mode.
+    if let Some(bpf) = bpf.as_mut().filter(|_| !params.passive) {
+        let map = bpf.take_map("LATENCY_HISTOGRAM").unwrap();
+        tasks.spawn(latency_histogram::log_latency_histogram_periodically(
+            PerCpuArray::try_from(map).unwrap(),
+            Duration::from_secs(params.stats_interval),
+        ));
+    }
+
+    if let Some(bpf) = bpf.as_mut() {
+        let map = bpf.take_map("FLOW_ERRORS").unwrap();
+        tasks.spawn(flow_errors::log_flow_errors_periodically(
+            PerCpuHashMap::try_from(map).unwrap(),
+            Duration::from_secs(params.stats_interval),
+        ));
+    }
+
+    if let (Some(bpf), Some(rate_hz)) = (bpf.as_mut(), params.perf_event_sample_rate) {
+        let map = bpf.take_map("PERF_OUTPUT").unwrap();
+        tasks.spawn(perf_samples::write_perf_samples(
+            map,
+            params.perf_samples_file.clone(),
+            rate_hz,
+        ));
+    }
+
+    if let Some(bpf) = bpf.as_mut() {
+        let map = bpf.take_map("EXCEPTION_CNT").unwrap();
+        let health =
+            ebpf_program_health::EbpfProgramHealth::new(PerCpuArray::try_from(map).unwrap());
+        tasks.spawn(health.run());
+        tasks.spawn(ebpf_program_health::log_program_stats_periodically(
+            ebpf_loader::program_ids(bpf),
+            Duration::from_secs(params.stats_interval),
+        ));
+    }
+
+    if let Some(interface) = &params.raw_capture {
+        match raw_capture::start_raw_capture(
+            &middle_box_namespace,
+            interface,
+            raw_capture::RAW_CAPTURE_CAPACITY,
+        ) {
+            Ok(capture) => {
+                tasks.spawn(raw_capture::log_raw_capture_periodically(
+                    capture,
+                    Duration::from_secs(params.stats_interval),
+                ));
+            }
+            Err(error) => warn!("Failed to start the raw capture: {:?}", error),
+        }
+    }
+
+    if let Some(qdisc_stats_interval) = params.qdisc_stats {
+        tasks.spawn(qdisc_stats::log_qdisc_stats_periodically(
+            namespaces.tcp_tester.clone(),
+            Duration::from_secs(qdisc_stats_interval),
+            params.qdisc_drop_warn,
+        ));
+    }
 
+    let client_options = client::ClientOptions {
+        namespace: namespaces.client.clone(),
+        send_data: params.send_data == cli::OnOff::On,
+        config_file_path: params.config_file_path.clone(),
+        config_format: params.config_format,
+        config_overlay: params.config_overlay.clone(),
+        audit_sockets: params.audit_sockets,
+        batch_size: params.batch_size,
+        flow_counters: Arc::default(),
+        flow_dependencies: Arc::default(),
+        flow_runtimes,
+        bind_addr: params.bind_addr,
+        vrf: params.vrf.clone(),
+        buffer_sizes: client::BufferSizes {
+            send: params.tcp_send_buffer.or(params.tcp_buffer_size),
+            recv: params.tcp_recv_buffer.or(params.tcp_buffer_size),
+        },
+        remote_config: Arc::default(),
+        max_flow_duration: params.max_flow_duration.map(Duration::from_secs),
+        sock_timestamping: params.enable_sock_timestamping,
+        flow_id_header: params.flow_id_header.clone(),
+        rate_limiter: params
+            .per_ip_rate
+            .map(|rate| Arc::new(flow_limiter::PerDestinationRateLimiter::new(rate))),
+        rtt_backpressure: params.rtt_backpressure_threshold_ms.map(|threshold_ms| {
+            Arc::new(flow_limiter::RttBackpressure::new(Duration::from_millis(
+                threshold_ms,
+            )))
+        }),
+        rate_file: params
+            .rate_file
+            .clone()
+            .map(|path| Arc::new(flow_limiter::RateFile::open(path, params.connection_rate))),
+        flow_events: broadcast::channel(FLOW_EVENTS_CAPACITY).0,
+    };
+
+    if let Some(rate_file) = &client_options.rate_file {
+        tasks.spawn(rate_file.clone().poll_periodically());
+    }
+
+    if let Some(window) = params.stats_window {
+        tasks.spawn(stats_window::log_stats_window_periodically(
+            client_options.flow_events.subscribe(),
+            Duration::from_secs(window),
+            Duration::from_secs(params.stats_interval),
+            hash_maps,
+        ));
+    }
+
+    if let Some(max_concurrent) = params.max_concurrent.filter(|_| scenario.is_none()) {
+        let connections_per_sec = f64::from(params.connection_rate)
+            * f64::from(params.servers)
+            * f64::from(clients_per_server);
+        client::check_max_concurrent(
+            connections_per_sec,
+            max_concurrent,
+            client_maps.is_some(),
+            &client_options,
+        )
+        .await;
+    }
+
+    // The connections are only counted by the sockops program, which is not attached in passive
+    // mode.
+    if let Some(bpf) = bpf.as_mut().filter(|_| !params.passive) {
+        let map = bpf.take_map("ACTIVE_FLOWS_GAUGE").unwrap();
+        // Every client connects at the connection rate, the scenarios change it.
+        let connections_per_sec = scenario.is_none().then(|| {
+            f64::from(params.connection_rate)
+                * f64::from(params.servers)
+                * f64::from(clients_per_server)
+        });
+        tasks.spawn(active_flows::log_active_flows_periodically(
+            PerCpuArray::try_from(map).unwrap(),
+            client_options.flow_events.subscribe(),
+            connections_per_sec,
+            Duration::from_secs(params.stats_interval),
+        ));
+    }
+
+    if let Some(grpc_addr) = params.grpc_addr {
+        let service = control_plane::FlowControllerService::new(
+            client_options.clone(),
+            client::server_addr(params.starting_port),
+        );
+        tasks.spawn(control_plane::serve(grpc_addr, service));
+    }
+
+    if let Some(health_check_interval) = params.health_check_interval {
+        tasks.spawn(health_monitor::check_flow_completion_periodically(
+            client::server_addr(params.starting_port),
+            client_options.clone(),
+            Duration::from_secs(health_check_interval),
+            params.health_check_alert_webhook.clone(),
+        ));
+    }
+
+    let ports: Vec<u16> = (0..params.servers)
+        .map(|i| params.starting_port.wrapping_add(i.into()))
+        .collect();
+    for port in &ports {
+        tasks.spawn(server::server(
+            *port,
+            params.response_delay_ms,
+            namespaces.server.clone(),
+        ));
+    }
+
+    if let Some(queue_monitor_interval) = params.queue_monitor {
+        tasks.spawn(queue_monitor::log_queue_depths_periodically(
+            namespaces.server.clone(),
+            ports.clone(),
+            Duration::from_secs(queue_monitor_interval),
+            params.queue_warn_threshold,
+        ));
+    }
+
+    if let Some(scenario) = scenario {
+        scenario
+            .run(params.connection_rate, &ports, client_maps, client_options)
+            .await;
+        shutdown_telemetry(tracer_provider);
+        // The servers and the background tasks are dropped along with the runtime.
+        return ExitCode::SUCCESS;
+    }
+
+    for port in ports {
         for _ in 0..clients_per_server {
             info!("Spawning client");
             tasks.spawn(client::start_client_at_rate(
                 params.connection_rate,
                 port,
-                params.traffic_shaping == cli::OnOff::On,
-                params.send_data == cli::OnOff::On,
-                params.cgroup_path.clone(),
-                params.config_file_path.clone(),
+                client_maps.clone(),
+                client_options.clone(),
             ));
         }
     }
@@ -36,4 +674,15 @@ async fn main() {
     while let Some(res) = tasks.join_next().await {
         info!("Completed task: {}", res.is_ok())
     }
+    shutdown_telemetry(tracer_provider);
+    ExitCode::SUCCESS
+}
+
+/// Exports the pending spans before exiting.
+fn shutdown_telemetry(tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>) {
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(error) = tracer_provider.shutdown() {
+            warn!("Failed to export the pending spans: {:?}", error);
+        }
+    }
 }
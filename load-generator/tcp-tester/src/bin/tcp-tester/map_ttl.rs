@@ -0,0 +1,61 @@
+use log::{debug, warn};
+use tcp_tester::os;
+use tcp_tester_common::SocketKey;
+use tokio::time::Duration;
+
+use crate::client::SocketConfigMap;
+
+/// Removes the `SOCKET_CONFIG` entries inserted more than `ttl` ago, along with their
+/// `SOCKET_CONFIG_TIMESTAMPS` entries. Returns the number of entries removed.
+fn expire_socket_config(socket_config: &SocketConfigMap, ttl: Duration) -> usize {
+    let now = match os::monotonic_time_ns() {
+        Ok(now) => now,
+        Err(error) => {
+            warn!("Failed to read the monotonic clock: {}", error);
+            return 0;
+        }
+    };
+
+    let mut maps = socket_config.lock().unwrap();
+    let expired: Vec<(SocketKey, u64)> = maps
+        .timestamps
+        .iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|(key, inserted)| {
+            let age = Duration::from_nanos(now.saturating_sub(inserted));
+            (age > ttl).then_some((key, age.as_secs()))
+        })
+        .collect();
+
+    for (key, age_secs) in &expired {
+        maps.remove(key, "expire_socket_config");
+        debug!(
+            cookie = key.cookie, age_secs = *age_secs;
+            "Removed expired SOCKET_CONFIG entry"
+        );
+    }
+    expired.len()
+}
+
+/// Periodically removes the `SOCKET_CONFIG` entries of the sockets that never connected, e.g.
+/// because the client was killed in between, so they don't fill up the map.
+///
+/// # Arguments
+/// * `socket_config` - `SOCKET_CONFIG` and `SOCKET_CONFIG_TIMESTAMPS` maps.
+/// * `ttl` - age after which an entry is removed.
+/// * `interval` - time between two consecutive checks.
+pub async fn expire_socket_config_periodically(
+    socket_config: SocketConfigMap,
+    ttl: Duration,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let expired = expire_socket_config(&socket_config, ttl);
+        if expired > 0 {
+            debug!("Removed {} expired SOCKET_CONFIG entries", expired);
+        }
+    }
+}
@@ -0,0 +1,555 @@
+use anyhow::{bail, Context};
+use log::{error, info, warn};
+use netns_rs::NetNs;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Network namespace of a `Topology`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Node {
+    /// Name of the node, the namespace being named `<prefix>-<name>`.
+    pub name: String,
+    /// Addresses assigned to the loopback interface, e.g. `1.1.1.1` or `1.1.1.0/24`.
+    #[serde(default)]
+    pub loopback_addresses: Vec<String>,
+    /// Sysctls set in the namespace, e.g. `net.ipv4.ip_forward: "1"`.
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+}
+
+/// Route of a `Node`, set up once all the links are.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Route {
+    /// Destination prefix, e.g. `2.2.2.2` or `20.0.0.0/24`.
+    pub destination: String,
+    /// Next hop.
+    pub via: IpAddr,
+    /// Source address of the packets sent to the destination.
+    #[serde(default)]
+    pub src: Option<IpAddr>,
+}
+
+/// End of a `Link`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct LinkEndpoint {
+    /// Name of the node the interface is in.
+    pub node: String,
+    /// Name of the interface.
+    pub interface: String,
+    /// Address of the interface, e.g. `10.0.0.1/24`.
+    #[serde(default)]
+    pub address: Option<String>,
+    #[serde(default)]
+    pub mtu: Option<u32>,
+    /// Offloads turned off with `ethtool -K`, e.g. `tx-tcp-segmentation` so that the segments
+    /// correspond to packets. Failures are only logged, not every driver supports them.
+    #[serde(default)]
+    pub disabled_offloads: Vec<String>,
+}
+
+/// veth pair between two nodes of a `Topology`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Link {
+    pub endpoints: [LinkEndpoint; 2],
+}
+
+/// Network namespaces, veth pairs between them, and their addresses and routes, as described in
+/// a topology file. This is the declarative equivalent of `bin/network-setup`, see
+/// `src/config/topology.yaml` for the topology it sets up.
+#[derive(Debug, Deserialize)]
+pub struct Topology {
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub links: Vec<Link>,
+}
+
+/// Changes turning a topology into another, see `Topology::diff`.
+#[derive(Debug, Default)]
+pub struct TopologyDiff {
+    /// Links only in the desired topology.
+    pub to_add: Vec<Link>,
+    /// Links only in the existing topology.
+    pub to_remove: Vec<Link>,
+    /// Links between the same interfaces in both topologies but with other settings, as
+    /// desired. They are recreated.
+    pub to_modify: Vec<Link>,
+    /// Nodes only in the desired topology, or with other settings, as desired.
+    pub nodes_to_set_up: Vec<Node>,
+    /// Nodes only in the existing topology, whose namespace is deleted.
+    pub nodes_to_remove: Vec<String>,
+    /// Loopback addresses of the nodes of both topologies that the desired one no longer has,
+    /// as node and address.
+    pub stale_loopback_addresses: Vec<(String, String)>,
+    /// Routes of the nodes of both topologies that the desired one no longer has, as node and
+    /// destination.
+    pub stale_routes: Vec<(String, String)>,
+    /// Routes of the desired topology, replaced once the links are changed: removing an
+    /// interface removes the routes through it.
+    pub routes: Vec<(String, Route)>,
+}
+
+impl TopologyDiff {
+    /// Whether the topologies are the same.
+    pub fn is_empty(&self) -> bool {
+        self.to_add.is_empty()
+            && self.to_remove.is_empty()
+            && self.to_modify.is_empty()
+            && self.nodes_to_set_up.is_empty()
+            && self.nodes_to_remove.is_empty()
+            && self.stale_loopback_addresses.is_empty()
+            && self.stale_routes.is_empty()
+    }
+}
+
+/// Runs `ip` with the given arguments, failing with its error output when it fails.
+async fn ip(args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new("ip")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run ip")?;
+    if !output.status.success() {
+        bail!(
+            "ip {} failed with {}: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Whether the interface exists in the namespace.
+async fn interface_exists(namespace: &str, interface: &str) -> anyhow::Result<bool> {
+    let status = Command::new("ip")
+        .args(["-n", namespace, "link", "show", "dev", interface])
+        .output()
+        .await
+        .context("Failed to run ip")?
+        .status;
+    Ok(status.success())
+}
+
+impl Topology {
+    /// Reads a topology from a YAML file, JSON being accepted as well.
+    pub async fn from_file(path: &str) -> anyhow::Result<Topology> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read topology file: {}", path))?;
+        let topology: Topology = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse topology file: {}", path))?;
+        topology
+            .validate()
+            .with_context(|| format!("Invalid topology file: {}", path))?;
+        Ok(topology)
+    }
+
+    /// Checks that the node names are unique and that the links only refer to them.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut names = HashSet::new();
+        for node in &self.nodes {
+            if !names.insert(node.name.as_str()) {
+                bail!("Node {} is defined more than once", node.name);
+            }
+        }
+        for endpoint in self.links.iter().flat_map(|link| &link.endpoints) {
+            if !names.contains(endpoint.node.as_str()) {
+                bail!(
+                    "Interface {} is in the undefined node {}",
+                    endpoint.interface,
+                    endpoint.node
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the topology, with the namespaces named `<prefix>-<node name>`. Only creates what
+    /// is missing, the addresses and routes are replaced, so it can be applied on top of an
+    /// existing topology, e.g. one created by a previous run.
+    pub async fn apply(&self, prefix: &str) -> anyhow::Result<()> {
+        for node in &self.nodes {
+            node.set_up(prefix).await?;
+        }
+        for link in &self.links {
+            link.set_up(prefix).await?;
+        }
+        for node in &self.nodes {
+            for route in &node.routes {
+                route.replace(&namespace(prefix, &node.name)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the changes turning the `existing` topology into the `desired` one. The links
+    /// are identified by the interfaces at their ends, and the nodes by their name.
+    pub fn diff(existing: &Topology, desired: &Topology) -> TopologyDiff {
+        let mut diff = TopologyDiff::default();
+        for link in &desired.links {
+            match existing
+                .links
+                .iter()
+                .find(|other| other.ends() == link.ends())
+            {
+                None => diff.to_add.push(link.clone()),
+                Some(other) if !other.same_settings(link) => diff.to_modify.push(link.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.to_remove = existing
+            .links
+            .iter()
+            .filter(|link| {
+                !desired
+                    .links
+                    .iter()
+                    .any(|other| other.ends() == link.ends())
+            })
+            .cloned()
+            .collect();
+
+        for node in &desired.nodes {
+            let Some(other) = existing.nodes.iter().find(|other| other.name == node.name) else {
+                diff.nodes_to_set_up.push(node.clone());
+                continue;
+            };
+            if other == node {
+                continue;
+            }
+            diff.nodes_to_set_up.push(node.clone());
+            for address in &other.loopback_addresses {
+                if !node.loopback_addresses.contains(address) {
+                    diff.stale_loopback_addresses
+                        .push((node.name.clone(), address.clone()));
+                }
+            }
+            for route in &other.routes {
+                if !node
+                    .routes
+                    .iter()
+                    .any(|other| other.destination == route.destination)
+                {
+                    diff.stale_routes
+                        .push((node.name.clone(), route.destination.clone()));
+                }
+            }
+        }
+        diff.nodes_to_remove = existing
+            .nodes
+            .iter()
+            .filter(|node| !desired.nodes.iter().any(|other| other.name == node.name))
+            .map(|node| node.name.clone())
+            .collect();
+        diff.routes = desired
+            .nodes
+            .iter()
+            .flat_map(|node| {
+                node.routes
+                    .iter()
+                    .map(|route| (node.name.clone(), route.clone()))
+            })
+            .collect();
+        diff
+    }
+
+    /// Applies the changes of a `Topology::diff`, leaving the unchanged nodes and links, and the
+    /// flows going through them, untouched. The sysctls no longer set by the desired topology
+    /// keep their value.
+    pub async fn apply_diff(diff: &TopologyDiff, prefix: &str) -> anyhow::Result<()> {
+        for link in diff.to_remove.iter().chain(&diff.to_modify) {
+            link.remove(prefix).await?;
+        }
+        for node in &diff.nodes_to_remove {
+            let name = namespace(prefix, node);
+            if NetNs::get(&name).is_ok() {
+                info!("Deleting namespace {}", name);
+                ip(&["netns", "del", &name]).await?;
+            }
+        }
+        for node in &diff.nodes_to_set_up {
+            node.set_up(prefix).await?;
+        }
+        for (node, address) in &diff.stale_loopback_addresses {
+            let name = namespace(prefix, node);
+            ip(&["-n", &name, "addr", "del", address, "dev", "lo"]).await?;
+        }
+        for (node, destination) in &diff.stale_routes {
+            ip(&["-n", &namespace(prefix, node), "route", "del", destination]).await?;
+        }
+        for link in diff.to_add.iter().chain(&diff.to_modify) {
+            link.set_up(prefix).await?;
+        }
+        for (node, route) in &diff.routes {
+            route.replace(&namespace(prefix, node)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the namespace of the node `node`.
+fn namespace(prefix: &str, node: &str) -> String {
+    format!("{}-{}", prefix, node)
+}
+
+impl Node {
+    /// Creates the namespace of the node when missing, and sets its sysctls and loopback
+    /// addresses.
+    async fn set_up(&self, prefix: &str) -> anyhow::Result<()> {
+        let name = namespace(prefix, &self.name);
+        if NetNs::get(&name).is_err() {
+            info!("Creating namespace {}", name);
+            ip(&["netns", "add", &name]).await?;
+        }
+        ip(&["-n", &name, "link", "set", "lo", "up"]).await?;
+        for (key, value) in &self.sysctls {
+            let setting = format!("{}={}", key, value);
+            ip(&["netns", "exec", &name, "sysctl", "-w", &setting]).await?;
+        }
+        for address in &self.loopback_addresses {
+            ip(&["-n", &name, "addr", "replace", address, "dev", "lo"]).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Route {
+    /// Sets the route up in the namespace, replacing the one to the same destination if any.
+    async fn replace(&self, namespace: &str) -> anyhow::Result<()> {
+        let via = self.via.to_string();
+        let src = self.src.map(|src| src.to_string());
+        let mut args = vec!["-n", namespace, "route", "replace", &self.destination];
+        args.extend(["via", &via]);
+        if let Some(src) = &src {
+            args.extend(["src", src]);
+        }
+        ip(&args).await
+    }
+}
+
+impl Link {
+    /// Returns the node and interface of both ends, in order, identifying the link whichever
+    /// way it is written.
+    fn ends(&self) -> [(&str, &str); 2] {
+        let mut ends = self
+            .endpoints
+            .each_ref()
+            .map(|endpoint| (endpoint.node.as_str(), endpoint.interface.as_str()));
+        ends.sort();
+        ends
+    }
+
+    /// Whether both ends of the links, which have the same ends, have the same settings.
+    fn same_settings(&self, other: &Link) -> bool {
+        let mut endpoints = self.endpoints.each_ref();
+        let mut other_endpoints = other.endpoints.each_ref();
+        endpoints.sort_by_key(|endpoint| (&endpoint.node, &endpoint.interface));
+        other_endpoints.sort_by_key(|endpoint| (&endpoint.node, &endpoint.interface));
+        endpoints == other_endpoints
+    }
+
+    /// Creates the veth pair when missing, and sets the MTU, addresses and offloads of its
+    /// interfaces.
+    async fn set_up(&self, prefix: &str) -> anyhow::Result<()> {
+        let [first, second] = &self.endpoints;
+        let (first_namespace, second_namespace) = (
+            namespace(prefix, &first.node),
+            namespace(prefix, &second.node),
+        );
+        // Interfaces are only moved along with their peer, so one of them is enough.
+        if !interface_exists(&first_namespace, &first.interface).await? {
+            info!(
+                "Creating veth pair {}/{} - {}/{}",
+                first_namespace, first.interface, second_namespace, second.interface
+            );
+            ip(&[
+                "link",
+                "add",
+                &first.interface,
+                "netns",
+                &first_namespace,
+                "type",
+                "veth",
+                "peer",
+                &second.interface,
+                "netns",
+                &second_namespace,
+            ])
+            .await?;
+        }
+
+        for (endpoint, name) in [(first, &first_namespace), (second, &second_namespace)] {
+            if let Some(mtu) = endpoint.mtu {
+                let mtu = mtu.to_string();
+                ip(&["-n", name, "link", "set", &endpoint.interface, "mtu", &mtu]).await?;
+            }
+            ip(&["-n", name, "link", "set", &endpoint.interface, "up"]).await?;
+            if let Some(address) = &endpoint.address {
+                ip(&[
+                    "-n",
+                    name,
+                    "addr",
+                    "replace",
+                    address,
+                    "dev",
+                    &endpoint.interface,
+                ])
+                .await?;
+            }
+            for offload in &endpoint.disabled_offloads {
+                let interface = &endpoint.interface;
+                let args = [
+                    "netns", "exec", name, "ethtool", "-K", interface, offload, "off",
+                ];
+                if let Err(error) = ip(&args).await {
+                    warn!("Failed to turn {} off: {:?}", offload, error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the veth pair, if it still exists: deleting one of its interfaces deletes both.
+    async fn remove(&self, prefix: &str) -> anyhow::Result<()> {
+        let [first, second] = &self.endpoints;
+        let first_namespace = namespace(prefix, &first.node);
+        if !interface_exists(&first_namespace, &first.interface).await? {
+            return Ok(());
+        }
+        info!(
+            "Deleting veth pair {}/{} - {}/{}",
+            first_namespace,
+            first.interface,
+            namespace(prefix, &second.node),
+            second.interface
+        );
+        ip(&["-n", &first_namespace, "link", "del", &first.interface]).await
+    }
+}
+
+/// Reapplies the topology file at `path` on every SIGHUP, only changing what differs from the
+/// topology applied last, e.g. removing a link to simulate its failure without interrupting the
+/// flows going through the other ones.
+///
+/// # Arguments
+/// * `path` - topology file.
+/// * `prefix` - prefix of the namespaces.
+/// * `applied` - topology set up from the file.
+pub async fn reload_topology_on_sighup(path: String, prefix: String, mut applied: Topology) {
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(sighup) => sighup,
+        Err(error) => {
+            warn!(
+                "Failed to handle SIGHUP, the topology won't be reloaded: {}",
+                error
+            );
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        let desired = match Topology::from_file(&path).await {
+            Ok(desired) => desired,
+            Err(error) => {
+                warn!("Failed to reload topology: {:?}", error);
+                continue;
+            }
+        };
+        let diff = Topology::diff(&applied, &desired);
+        if diff.is_empty() {
+            info!("Topology {} unchanged", path);
+            continue;
+        }
+        info!(
+            links_added = diff.to_add.len(),
+            links_removed = diff.to_remove.len(),
+            links_modified = diff.to_modify.len(),
+            nodes_set_up = diff.nodes_to_set_up.len(),
+            nodes_removed = diff.nodes_to_remove.len();
+            "Updating topology {}", path
+        );
+        match Topology::apply_diff(&diff, &prefix).await {
+            Ok(()) => applied = desired,
+            Err(error) => error!("Failed to update topology: {:?}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topology(yaml: &str) -> Topology {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_topology_diff() {
+        let existing = topology(
+            "
+nodes:
+  - name: client
+    loopback_addresses: [1.1.1.1]
+    routes:
+      - {destination: 2.2.2.2, via: 10.0.0.2}
+  - name: server
+  - name: spare
+links:
+  - endpoints:
+      - {node: client, interface: i1, address: 10.0.0.1/24}
+      - {node: server, interface: i2, address: 10.0.0.2/24}
+  - endpoints:
+      - {node: client, interface: i3}
+      - {node: spare, interface: i4}
+",
+        );
+        let desired = topology(
+            "
+nodes:
+  - name: client
+    loopback_addresses: [1.1.1.2]
+  - name: server
+  - name: router
+links:
+  - endpoints:
+      - {node: server, interface: i2, address: 10.0.0.2/24}
+      - {node: client, interface: i1, address: 10.0.0.1/24, mtu: 1400}
+  - endpoints:
+      - {node: server, interface: i5}
+      - {node: router, interface: i6}
+",
+        );
+
+        let diff = Topology::diff(&existing, &desired);
+        let ends = |links: &[Link]| -> Vec<String> {
+            links
+                .iter()
+                .map(|link| format!("{:?}", link.ends()))
+                .collect()
+        };
+        assert_eq!(ends(&diff.to_add), ends(&desired.links[1..]));
+        assert_eq!(ends(&diff.to_remove), ends(&existing.links[1..]));
+        assert_eq!(ends(&diff.to_modify), ends(&desired.links[..1]));
+        let nodes: Vec<&str> = diff
+            .nodes_to_set_up
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect();
+        assert_eq!(nodes, ["client", "router"]);
+        assert_eq!(diff.nodes_to_remove, ["spare"]);
+        assert_eq!(
+            diff.stale_loopback_addresses,
+            [("client".to_string(), "1.1.1.1".to_string())]
+        );
+        assert_eq!(
+            diff.stale_routes,
+            [("client".to_string(), "2.2.2.2".to_string())]
+        );
+
+        assert!(Topology::diff(&desired, &desired).is_empty());
+    }
+}
@@ -0,0 +1,55 @@
+use netns_rs::{get_from_path, NetNs};
+use std::fmt;
+
+/// Default prefix of the network namespaces, as set up by `bin/network-setup`.
+pub const DEFAULT_NAMESPACE_PREFIX: &str = "nfm-perf-test";
+
+/// Names of the network namespaces of the test topology. They share a common prefix, so that
+/// several topologies, and thus tcp-tester instances, can run side by side.
+#[derive(Clone, Debug)]
+pub struct Namespaces {
+    /// Namespace where the clients run.
+    pub client: String,
+    /// Middle-box namespace, where the traffic control programs are attached.
+    pub tcp_tester: String,
+    /// Namespace where the servers run.
+    pub server: String,
+}
+
+impl Namespaces {
+    pub fn new(prefix: &str) -> Self {
+        Namespaces {
+            client: format!("{}-client", prefix),
+            tcp_tester: format!("{}-tcp-tester", prefix),
+            server: format!("{}-server", prefix),
+        }
+    }
+}
+
+/// Network namespace where the traffic control programs are attached.
+#[derive(Clone, Debug)]
+pub enum MiddleBoxNamespace {
+    /// Named namespace, e.g. the tcp-tester one of the test topology.
+    Named(String),
+    /// Namespace of a container, entered through `/proc/<pid>/ns/net`, for the containers
+    /// which are not in a named namespace, e.g. with the Docker bridge networking.
+    Container(u32),
+}
+
+impl MiddleBoxNamespace {
+    pub fn get(&self) -> Result<NetNs, netns_rs::Error> {
+        match self {
+            MiddleBoxNamespace::Named(name) => NetNs::get(name),
+            MiddleBoxNamespace::Container(pid) => get_from_path(format!("/proc/{}/ns/net", pid)),
+        }
+    }
+}
+
+impl fmt::Display for MiddleBoxNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiddleBoxNamespace::Named(name) => write!(f, "{}", name),
+            MiddleBoxNamespace::Container(pid) => write!(f, "of container process {}", pid),
+        }
+    }
+}
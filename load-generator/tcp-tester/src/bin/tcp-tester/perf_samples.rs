@@ -0,0 +1,112 @@
+use anyhow::Context;
+use aya::maps::{AsyncPerfEventArray, Map};
+use aya::util::online_cpus;
+use bytes::BytesMut;
+use log::{error, warn};
+use std::mem::size_of;
+use tcp_tester_common::PerfSample;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+
+/// Samples read at once from the perf buffer of a CPU.
+const READ_BATCH: usize = 64;
+
+/// Returns the `PERF_SAMPLE_INTERVAL_NS` of the programs sampling at `rate_hz` on every CPU.
+pub fn perf_sample_interval_ns(rate_hz: u32) -> u64 {
+    1_000_000_000 / u64::from(rate_hz.max(1))
+}
+
+/// Spawns a task reading the samples of the perf buffer of `cpu`, sending them to the writer.
+async fn read_cpu_samples(
+    perf_output: &mut AsyncPerfEventArray<aya::maps::MapData>,
+    cpu: u32,
+    samples: mpsc::Sender<BytesMut>,
+) -> anyhow::Result<()> {
+    let mut buffer = perf_output
+        .open(cpu, None)
+        .with_context(|| format!("Failed to open the perf buffer of CPU {}", cpu))?;
+    tokio::spawn(async move {
+        let mut buffers: Vec<BytesMut> = (0..READ_BATCH)
+            .map(|_| BytesMut::with_capacity(size_of::<PerfSample>()))
+            .collect();
+        loop {
+            let events = match buffer.read_events(&mut buffers).await {
+                Ok(events) => events,
+                Err(error) => {
+                    error!("Failed to read the perf samples of CPU {}: {}", cpu, error);
+                    return;
+                }
+            };
+            if events.lost > 0 {
+                warn!("Lost {} perf samples of CPU {}", events.lost, cpu);
+            }
+            for sample in buffers.iter_mut().take(events.read) {
+                if samples.send(sample.split()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn write_samples(perf_output: Map, path: &str, rate_hz: u32) -> anyhow::Result<()> {
+    let mut perf_output = AsyncPerfEventArray::try_from(perf_output)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open perf samples file {}", path))?;
+    let mut file = BufWriter::new(file);
+
+    let (sender, mut samples) = mpsc::channel(rate_hz as usize + READ_BATCH);
+    let cpus =
+        online_cpus().map_err(|(message, error)| anyhow::anyhow!("{}: {}", message, error))?;
+    for cpu in cpus {
+        read_cpu_samples(&mut perf_output, cpu, sender.clone()).await?;
+    }
+    drop(sender);
+
+    while let Some(sample) = samples.recv().await {
+        if sample.len() != size_of::<PerfSample>() {
+            warn!("Skipping perf sample of {} bytes", sample.len());
+            continue;
+        }
+        file.write_all(&sample).await?;
+        // Flushed once the samples already received are written.
+        if samples.is_empty() {
+            file.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends the samples of the `PERF_OUTPUT` map, read by a task per CPU, to the file at `path`,
+/// for the offline analysis of the latency spikes. Every sample is written as the bytes of a
+/// `PerfSample`, 32 bytes in the native byte order.
+///
+/// # Arguments
+/// * `perf_output` - `PERF_OUTPUT` map.
+/// * `path` - path of the file the samples are appended to.
+/// * `rate_hz` - sampling rate of every CPU, sizing the channel between the readers and the
+///   writer.
+pub async fn write_perf_samples(perf_output: Map, path: String, rate_hz: u32) {
+    if let Err(error) = write_samples(perf_output, &path, rate_hz).await {
+        error!("Failed to write perf samples: {:?}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_sample_layout() {
+        // Written as is to the samples file.
+        assert_eq!(size_of::<PerfSample>(), 32);
+        assert_eq!(perf_sample_interval_ns(100), 10_000_000);
+        assert_eq!(perf_sample_interval_ns(0), 1_000_000_000);
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{bail, Context};
+use log::{error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use tokio::process::Command;
+use tokio::time::Duration;
+
+/// Interfaces of the middle-box where the traffic control programs are attached.
+const INTERFACES: [&str; 2] = ["i2", "i3"];
+
+/// Counters of a qdisc, as reported by `tc -s qdisc show`.
+#[derive(Debug, Serialize)]
+struct QdiscStats {
+    kind: String,
+    handle: String,
+    sent_bytes: u64,
+    sent_packets: u64,
+    dropped: u64,
+    overlimits: u64,
+    requeues: u64,
+}
+
+/// Parses the output of `tc -s qdisc show`, e.g.:
+/// ```text
+/// qdisc clsact ffff: dev i2 parent ffff:fff1
+///  Sent 2048 bytes 12 pkt (dropped 1, overlimits 0 requeues 0)
+/// ```
+fn parse_qdisc_stats(output: &str) -> Vec<QdiscStats> {
+    let pattern = Regex::new(
+        r"qdisc (\S+) (\S+) .*\n\s*Sent (\d+) bytes (\d+) pkt \(dropped (\d+), overlimits (\d+) requeues (\d+)\)",
+    )
+    .unwrap();
+
+    pattern
+        .captures_iter(output)
+        .map(|captures| QdiscStats {
+            kind: captures[1].to_string(),
+            handle: captures[2].to_string(),
+            sent_bytes: captures[3].parse().unwrap_or_default(),
+            sent_packets: captures[4].parse().unwrap_or_default(),
+            dropped: captures[5].parse().unwrap_or_default(),
+            overlimits: captures[6].parse().unwrap_or_default(),
+            requeues: captures[7].parse().unwrap_or_default(),
+        })
+        .collect()
+}
+
+async fn read_qdisc_stats(namespace: &str, interface: &str) -> anyhow::Result<Vec<QdiscStats>> {
+    let output = Command::new("ip")
+        .args(["netns", "exec", namespace])
+        .args(["tc", "-s", "qdisc", "show", "dev", interface])
+        .output()
+        .await
+        .context("Failed to run tc")?;
+    if !output.status.success() {
+        bail!(
+            "tc failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(parse_qdisc_stats(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Periodically logs the qdisc counters of the middle-box interfaces.
+///
+/// # Arguments
+/// * `namespace` - network namespace of the middle-box.
+/// * `interval` - time between two consecutive reports.
+/// * `drop_warn` - number of dropped packets of a qdisc above which a warning is logged.
+pub async fn log_qdisc_stats_periodically(namespace: String, interval: Duration, drop_warn: u64) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        for interface in INTERFACES {
+            let qdiscs = match read_qdisc_stats(&namespace, interface).await {
+                Ok(qdiscs) => qdiscs,
+                Err(error) => {
+                    error!("Failed to read qdisc stats of {}: {:?}", interface, error);
+                    continue;
+                }
+            };
+
+            for qdisc in qdiscs {
+                if qdisc.dropped > drop_warn {
+                    warn!(
+                        "Qdisc {} of {} dropped {} packets",
+                        qdisc.kind, interface, qdisc.dropped
+                    );
+                }
+                info!(interface, qdisc:serde; "Qdisc stats");
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{bail, Context};
+use log::{error, info};
+use serde::Serialize;
+use tokio::process::Command;
+use tokio::time::Duration;
+
+/// Queues of the sockets of a server port, as reported by `ss`.
+#[derive(Debug, Default, Serialize)]
+struct QueueDepths {
+    /// Connections waiting to be accepted by the server.
+    tcp_accept_queue_depth: u64,
+    /// Bytes received and not read by the server yet, over all its connections.
+    tcp_recv_queue_depth: u64,
+    /// Bytes not acknowledged by the clients yet, over all the server connections.
+    tcp_send_queue_depth: u64,
+}
+
+/// Parses the output of `ss -tanH`, e.g.:
+/// ```text
+/// LISTEN 0      1024   0.0.0.0:8080 0.0.0.0:*
+/// ESTAB  0      2048   2.2.2.2:8080 1.1.1.1:41234
+/// ```
+/// For listening sockets, `Recv-Q` is the number of connections waiting to be accepted.
+fn parse_queue_depths(output: &str) -> QueueDepths {
+    let mut depths = QueueDepths::default();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(state), Some(recv_q), Some(send_q)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let recv_q: u64 = recv_q.parse().unwrap_or_default();
+        let send_q: u64 = send_q.parse().unwrap_or_default();
+
+        if state == "LISTEN" {
+            depths.tcp_accept_queue_depth += recv_q;
+        } else {
+            depths.tcp_recv_queue_depth += recv_q;
+            depths.tcp_send_queue_depth += send_q;
+        }
+    }
+    depths
+}
+
+async fn read_queue_depths(namespace: &str, port: u16) -> anyhow::Result<QueueDepths> {
+    let output = Command::new("ip")
+        .args(["netns", "exec", namespace])
+        .args(["ss", "-tanH", &format!("sport = :{}", port)])
+        .output()
+        .await
+        .context("Failed to run ss")?;
+    if !output.status.success() {
+        bail!(
+            "ss failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(parse_queue_depths(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Periodically logs the queue depths of the server ports, to detect server overload.
+///
+/// # Arguments
+/// * `namespace` - network namespace of the servers.
+/// * `ports` - ports of the servers.
+/// * `interval` - time between two consecutive reports.
+/// * `warn_threshold` - bytes in the receive or send queues of a port above which an error is
+///   logged.
+pub async fn log_queue_depths_periodically(
+    namespace: String,
+    ports: Vec<u16>,
+    interval: Duration,
+    warn_threshold: Option<u64>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        for port in &ports {
+            let queues = match read_queue_depths(&namespace, *port).await {
+                Ok(queues) => queues,
+                Err(error) => {
+                    error!("Failed to read queue depths of port {}: {:?}", port, error);
+                    continue;
+                }
+            };
+
+            if let Some(threshold) = warn_threshold {
+                if queues.tcp_recv_queue_depth > threshold
+                    || queues.tcp_send_queue_depth > threshold
+                {
+                    error!(
+                        "Queues of port {} above {} bytes: receive {}, send {}",
+                        port, threshold, queues.tcp_recv_queue_depth, queues.tcp_send_queue_depth
+                    );
+                }
+            }
+            info!(port, queues:serde; "Queue depths");
+        }
+    }
+}
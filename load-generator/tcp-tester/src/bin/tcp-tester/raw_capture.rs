@@ -0,0 +1,174 @@
+use anyhow::Context;
+use log::{info, warn};
+use nix::errno::Errno;
+use nix::sys::socket::{recv, MsgFlags};
+use std::collections::VecDeque;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tcp_tester::os;
+use tokio::time::Duration;
+
+use crate::namespaces::MiddleBoxNamespace;
+
+/// Bytes kept of every packet: the Ethernet, IPv4 and TCP headers, without options.
+const HEADERS_LEN: usize = 14 + 20 + 20;
+
+/// Packets captured, the oldest ones being dropped from the ring buffer once full.
+pub const RAW_CAPTURE_CAPACITY: usize = 4096;
+
+/// Large enough for the frames of any MTU, with GRO off.
+const RECV_BUFFER_LEN: usize = 65536;
+
+/// Packet seen by the `AF_PACKET` socket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedPacket {
+    /// Monotonic time the packet was received from the socket, the clock of `bpf_ktime_get_ns`,
+    /// for the capture to be compared with the timestamps of the eBPF programs.
+    pub timestamp_ns: u64,
+    /// Length of the frame on the wire, even when longer than the receive buffer.
+    pub len: usize,
+    /// First `HEADERS_LEN` bytes of the frame.
+    pub headers: Vec<u8>,
+}
+
+/// Ring buffer of the packets captured on an interface, along with their totals.
+#[derive(Debug)]
+pub struct RawCapture {
+    packets: Mutex<VecDeque<CapturedPacket>>,
+    capacity: usize,
+    packets_total: AtomicU64,
+    bytes_total: AtomicU64,
+}
+
+impl RawCapture {
+    fn new(capacity: usize) -> RawCapture {
+        RawCapture {
+            packets: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            packets_total: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, packet: CapturedPacket) {
+        self.packets_total.fetch_add(1, Ordering::Relaxed);
+        self.bytes_total
+            .fetch_add(packet.len as u64, Ordering::Relaxed);
+        let mut packets = self.packets.lock().unwrap();
+        if packets.len() == self.capacity {
+            packets.pop_front();
+        }
+        packets.push_back(packet);
+    }
+
+    /// Returns the rate of the packets in the ring buffer, between the oldest and the newest.
+    fn recent_packets_per_sec(&self) -> f64 {
+        let packets = self.packets.lock().unwrap();
+        match (packets.front(), packets.back()) {
+            (Some(oldest), Some(newest)) if newest.timestamp_ns > oldest.timestamp_ns => {
+                let span_secs = (newest.timestamp_ns - oldest.timestamp_ns) as f64 / 1e9;
+                (packets.len() - 1) as f64 / span_secs
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Receives the packets of the socket until it fails.
+fn capture_packets(socket: OwnedFd, capture: &RawCapture) {
+    let mut buffer = vec![0; RECV_BUFFER_LEN];
+    loop {
+        // MSG_TRUNC returns the length of the frame rather than the length received.
+        let len = match recv(socket.as_raw_fd(), &mut buffer, MsgFlags::MSG_TRUNC) {
+            Ok(len) => len,
+            Err(Errno::EINTR) => continue,
+            Err(error) => {
+                warn!("Raw capture stopped: {}", error);
+                return;
+            }
+        };
+        let timestamp_ns = match os::monotonic_time_ns() {
+            Ok(now) => now,
+            Err(error) => {
+                warn!("Failed to read the monotonic clock: {}", error);
+                continue;
+            }
+        };
+        let captured = len.min(HEADERS_LEN).min(buffer.len());
+        capture.record(CapturedPacket {
+            timestamp_ns,
+            len,
+            headers: buffer[..captured].to_vec(),
+        });
+    }
+}
+
+/// Starts capturing the packets of `interface`, in the middle-box namespace, with an
+/// `AF_PACKET` socket read by a thread of its own. Unlike the traffic control programs, the
+/// socket sees the ingress packets before traffic control, and the egress ones after it.
+pub fn start_raw_capture(
+    namespace: &MiddleBoxNamespace,
+    interface: &str,
+    capacity: usize,
+) -> anyhow::Result<Arc<RawCapture>> {
+    let netns = namespace
+        .get()
+        .with_context(|| format!("Failed to get namespace {}", namespace))?;
+    let socket = netns
+        .run(|_| os::open_packet_socket(interface))
+        .with_context(|| format!("Failed to enter namespace {}", namespace))?
+        .with_context(|| format!("Failed to open an AF_PACKET socket on {}", interface))?;
+    let capture = Arc::new(RawCapture::new(capacity));
+    std::thread::Builder::new()
+        .name("raw-capture".into())
+        .spawn({
+            let capture = capture.clone();
+            move || capture_packets(socket, &capture)
+        })
+        .context("Failed to start the raw capture thread")?;
+    Ok(capture)
+}
+
+/// Periodically logs the totals of the packets captured, and their recent rate.
+pub async fn log_raw_capture_periodically(capture: Arc<RawCapture>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let raw_capture_packets_total = capture.packets_total.load(Ordering::Relaxed);
+        let raw_capture_bytes_total = capture.bytes_total.load(Ordering::Relaxed);
+        let raw_capture_packets_per_sec = capture.recent_packets_per_sec();
+        info!(
+            raw_capture_packets_total, raw_capture_bytes_total, raw_capture_packets_per_sec;
+            "Raw capture"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_capture_ring_buffer() {
+        let capture = RawCapture::new(2);
+        for timestamp_ms in 1..=3 {
+            capture.record(CapturedPacket {
+                timestamp_ns: timestamp_ms * 1_000_000,
+                len: 60,
+                headers: vec![0; HEADERS_LEN],
+            });
+        }
+        let timestamps: Vec<u64> = capture
+            .packets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|packet| packet.timestamp_ns)
+            .collect();
+        assert_eq!(timestamps, [2_000_000, 3_000_000]);
+        assert!((capture.recent_packets_per_sec() - 1000.0).abs() < 1e-6);
+        assert_eq!(capture.packets_total.load(Ordering::Relaxed), 3);
+        assert_eq!(capture.bytes_total.load(Ordering::Relaxed), 180);
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::{bail, Context};
+use aya::maps::{HashMap, Map, MapData};
+use log::{debug, error, info, warn};
+use netns_rs::NetNs;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use tcp_tester_common::{FlowKey, FlowStats};
+use tokio::time::Duration;
+
+use crate::ebpf_loader;
+
+/// State of the listening sockets in `/proc/net/tcp`, which are not flows.
+const TCP_LISTEN: &str = "0A";
+
+/// TCP connection, as its endpoints in a canonical order, so that the `FLOW_STATS` entries of
+/// both directions of a flow and its socket designate the same connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Connection(SocketAddrV4, SocketAddrV4);
+
+impl Connection {
+    fn new(a: SocketAddrV4, b: SocketAddrV4) -> Self {
+        if a <= b {
+            Connection(a, b)
+        } else {
+            Connection(b, a)
+        }
+    }
+
+    fn from_flow_key(key: &FlowKey) -> Self {
+        Connection::new(
+            SocketAddrV4::new(Ipv4Addr::from(key.sip), key.sport as u16),
+            SocketAddrV4::new(Ipv4Addr::from(key.dip), key.dport as u16),
+        )
+    }
+}
+
+/// Outcome of the comparison of the `FLOW_STATS` map with the sockets of `/proc/net/tcp`.
+/// `FLOW_STATS` outlives the flows, so the closed ones are expected in `bpf_only`, while
+/// `procfs_only` counts the connections the traffic control programs missed.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Reconciliation {
+    pub matched: usize,
+    pub bpf_only: usize,
+    pub procfs_only: usize,
+}
+
+/// Parses an address of `/proc/net/tcp`, e.g. `0100007F:1F90` for 127.0.0.1:8080: the IP is
+/// the network order word printed in host order, the port is in host order.
+fn parse_proc_address(address: &str) -> anyhow::Result<SocketAddrV4> {
+    let (ip, port) = address
+        .split_once(':')
+        .with_context(|| format!("Invalid address {}", address))?;
+    let ip = u32::from_str_radix(ip, 16).with_context(|| format!("Invalid IP {}", ip))?;
+    let port = u16::from_str_radix(port, 16).with_context(|| format!("Invalid port {}", port))?;
+    Ok(SocketAddrV4::new(Ipv4Addr::from(ip.to_ne_bytes()), port))
+}
+
+/// Parses the connections of `/proc/net/tcp`, skipping its header and the listening sockets.
+fn parse_proc_net_tcp(contents: &str) -> anyhow::Result<HashSet<Connection>> {
+    let mut connections = HashSet::new();
+    for line in contents.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, local, remote, state, ..] = fields[..] else {
+            bail!("Invalid /proc/net/tcp line: {}", line);
+        };
+        if state == TCP_LISTEN {
+            continue;
+        }
+        connections.insert(Connection::new(
+            parse_proc_address(local)?,
+            parse_proc_address(remote)?,
+        ));
+    }
+    Ok(connections)
+}
+
+/// Reads the TCP connections of the namespace.
+fn read_proc_net_tcp(namespace: &str) -> anyhow::Result<HashSet<Connection>> {
+    let netns =
+        NetNs::get(namespace).with_context(|| format!("Failed to get namespace {}", namespace))?;
+    // `/proc/thread-self/net` shows the namespace of the thread, the one entered by `run`.
+    let contents = netns
+        .run(|_| std::fs::read_to_string("/proc/thread-self/net/tcp"))
+        .with_context(|| format!("Failed to enter namespace {}", namespace))?
+        .with_context(|| format!("Failed to read /proc/net/tcp of namespace {}", namespace))?;
+    parse_proc_net_tcp(&contents)
+}
+
+fn read_bpf_connections(flow_stats: &HashMap<MapData, FlowKey, FlowStats>) -> HashSet<Connection> {
+    flow_stats
+        .keys()
+        .filter_map(|key| match key {
+            Ok(key) => Some(Connection::from_flow_key(&key)),
+            Err(error) => {
+                debug!("Failed to read flow stats key: {}", error);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Compares the connections, returning the counts and the connections only in `procfs`.
+fn compare(
+    bpf: &HashSet<Connection>,
+    procfs: &HashSet<Connection>,
+) -> (Reconciliation, HashSet<Connection>) {
+    let missed: HashSet<Connection> = procfs.difference(bpf).copied().collect();
+    let matched = procfs.len() - missed.len();
+    let reconciliation = Reconciliation {
+        matched,
+        bpf_only: bpf.len() - matched,
+        procfs_only: missed.len(),
+    };
+    (reconciliation, missed)
+}
+
+/// Compares the `FLOW_STATS` map with the TCP connections of the client namespace.
+///
+/// # Arguments
+/// * `flow_stats` - `FLOW_STATS` map populated by the traffic control programs.
+/// * `namespace` - namespace of the client sockets.
+pub fn reconcile(
+    flow_stats: &HashMap<MapData, FlowKey, FlowStats>,
+    namespace: &str,
+) -> anyhow::Result<Reconciliation> {
+    let procfs = read_proc_net_tcp(namespace)?;
+    let (reconciliation, missed) = compare(&read_bpf_connections(flow_stats), &procfs);
+    debug!(missed:?; "Connections missing from FLOW_STATS");
+    Ok(reconciliation)
+}
+
+/// Prints the comparison of the `FLOW_STATS` map pinned under `map_pin_path` by a tcp-tester
+/// running with `--pin-maps` with the TCP connections of the client namespace, as JSON.
+pub fn run_reconcile(map_pin_path: &Path, namespace: &str) -> anyhow::Result<()> {
+    let flow_stats = ebpf_loader::open_pinned_map_read_only(map_pin_path, "FLOW_STATS")?;
+    let flow_stats = HashMap::try_from(Map::LruHashMap(flow_stats))?;
+    let reconciliation = reconcile(&flow_stats, namespace)?;
+    println!("{}", serde_json::to_string_pretty(&reconciliation)?);
+    Ok(())
+}
+
+/// Periodically compares the `FLOW_STATS` map with the TCP connections of the client namespace,
+/// and logs the outcome along with `flows_missed_total`, the number of connections found
+/// missing from the map since the start.
+///
+/// # Arguments
+/// * `flow_stats` - `FLOW_STATS` map populated by the traffic control programs.
+/// * `namespace` - namespace of the client sockets.
+/// * `interval` - time between two consecutive reconciliations.
+pub async fn reconcile_periodically(
+    flow_stats: HashMap<MapData, FlowKey, FlowStats>,
+    namespace: String,
+    interval: Duration,
+) {
+    let mut flows_missed_total: u64 = 0;
+    // The connections still missing are only counted once.
+    let mut missed = HashSet::new();
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+
+        let procfs = match read_proc_net_tcp(&namespace) {
+            Ok(procfs) => procfs,
+            Err(error) => {
+                error!("Failed to reconcile flows: {:?}", error);
+                continue;
+            }
+        };
+        let (reconciliation, now_missed) = compare(&read_bpf_connections(&flow_stats), &procfs);
+        let newly_missed = now_missed.difference(&missed).count();
+        flows_missed_total += newly_missed as u64;
+        missed = now_missed;
+
+        info!(reconciliation:serde, flows_missed_total; "Flow reconciliation");
+        if newly_missed > 0 {
+            warn!(
+                missed:?;
+                "{} connections missing from FLOW_STATS",
+                newly_missed
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_net_tcp() {
+        let contents = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1 1 0 100 0 0 10 0
+   1: 0100000A:9C40 0200000A:1F90 01 00000000:00000000 00:00000000 00000000     0        0 2 1 0 20 4 30 10 -1
+";
+        let connections = parse_proc_net_tcp(contents).unwrap();
+        let client = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 40000);
+        let server = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8080);
+        assert_eq!(
+            connections,
+            HashSet::from([Connection::new(server, client)])
+        );
+    }
+
+    #[test]
+    fn test_compare_both_directions() {
+        let egress = FlowKey::builder()
+            .src_ip(Ipv4Addr::new(10, 0, 0, 1))
+            .src_port(40000)
+            .dst_ip(Ipv4Addr::new(10, 0, 0, 2))
+            .dst_port(8080)
+            .build();
+        let closed = FlowKey::builder()
+            .src_ip(Ipv4Addr::new(10, 0, 0, 1))
+            .src_port(40001)
+            .dst_ip(Ipv4Addr::new(10, 0, 0, 2))
+            .dst_port(8080)
+            .build();
+        let bpf: HashSet<Connection> = [egress, egress.reverse(), closed]
+            .iter()
+            .map(Connection::from_flow_key)
+            .collect();
+        let missed = Connection::new(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 40002),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8080),
+        );
+        let procfs = HashSet::from([Connection::from_flow_key(&egress), missed]);
+
+        let (reconciliation, only_procfs) = compare(&bpf, &procfs);
+        assert_eq!(
+            reconciliation,
+            Reconciliation {
+                matched: 1,
+                bpf_only: 1,
+                procfs_only: 1,
+            }
+        );
+        assert_eq!(only_procfs, HashSet::from([missed]));
+    }
+}
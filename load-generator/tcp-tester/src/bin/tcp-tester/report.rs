@@ -0,0 +1,88 @@
+use anyhow::Context;
+use aya::maps::{HashMap, Map, MapData, PerCpuArray};
+use aya::Ebpf;
+use log::{info, warn};
+use serde::Serialize;
+use std::path::Path;
+use tcp_tester_common::{FlowKey, FlowStats};
+use tokio::time::Duration;
+
+use crate::ebpf_loader;
+use crate::flow_stats::{self, FlowStatsEntry};
+use crate::latency_histogram::{self, LatencyBucket};
+
+/// Contents of the maps populated by the eBPF programs.
+#[derive(Debug, Serialize)]
+struct Report {
+    /// `pinned` when read from the maps of a running tcp-tester, `loaded` when the programs had
+    /// to be attached for the report.
+    source: &'static str,
+    flows: Vec<FlowStatsEntry>,
+    latency_histogram: Vec<LatencyBucket>,
+}
+
+fn read_report(
+    source: &'static str,
+    flow_stats: Map,
+    latency_histogram: Map,
+) -> anyhow::Result<Report> {
+    let flow_stats: HashMap<MapData, FlowKey, FlowStats> = HashMap::try_from(flow_stats)?;
+    let latency_histogram: PerCpuArray<MapData, u64> = PerCpuArray::try_from(latency_histogram)?;
+    Ok(Report {
+        source,
+        flows: flow_stats::read_flow_stats(&flow_stats),
+        latency_histogram: latency_histogram::read_latency_histogram(&latency_histogram),
+    })
+}
+
+/// Reads the report from the maps pinned by `--pin-maps`, which only requires read access to
+/// the pins.
+fn read_pinned_report(map_pin_path: &Path) -> anyhow::Result<Report> {
+    // The pins don't carry the map types, they are the ones declared by the programs.
+    let flow_stats = Map::LruHashMap(ebpf_loader::open_pinned_map_read_only(
+        map_pin_path,
+        "FLOW_STATS",
+    )?);
+    let latency_histogram = Map::PerCpuArray(ebpf_loader::open_pinned_map_read_only(
+        map_pin_path,
+        "LATENCY_HISTOGRAM",
+    )?);
+    read_report("pinned", flow_stats, latency_histogram)
+}
+
+/// Prints the contents of the eBPF maps as JSON. The maps pinned under `map_pin_path` are read
+/// when available, otherwise `setup_ebpf` is called to attach the programs, and the maps are read
+/// once they have been populated for `wait`. The duration histogram is also written to
+/// `histogram_output`, if any, in the HdrHistogram encoding.
+pub async fn run_report(
+    map_pin_path: &Path,
+    setup_ebpf: impl FnOnce() -> anyhow::Result<Ebpf>,
+    wait: Duration,
+    histogram_output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let report = match read_pinned_report(map_pin_path) {
+        Ok(report) => report,
+        Err(error) => {
+            warn!(
+                "Failed to read the pinned maps, attaching the eBPF programs: {:?}",
+                error
+            );
+            let mut bpf = setup_ebpf()?;
+            info!("Collecting flows for {:?}", wait);
+            tokio::time::sleep(wait).await;
+            read_report(
+                "loaded",
+                bpf.take_map("FLOW_STATS")
+                    .context("Map FLOW_STATS not found")?,
+                bpf.take_map("LATENCY_HISTOGRAM")
+                    .context("Map LATENCY_HISTOGRAM not found")?,
+            )?
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if let Some(path) = histogram_output {
+        latency_histogram::write_hdr_histogram(&report.latency_histogram, path)?;
+        info!("Duration histogram written to {}", path.display());
+    }
+    Ok(())
+}
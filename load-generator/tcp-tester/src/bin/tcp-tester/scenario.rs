@@ -0,0 +1,95 @@
+use anyhow::Context;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration};
+
+use crate::client::{self, ClientMaps, ClientOptions, FlowTotals};
+
+/// Step of a `TestScenario`.
+#[derive(Debug, Deserialize)]
+pub struct ScenarioStep {
+    /// How long the step lasts.
+    pub duration_secs: u64,
+    /// Path of the flow configuration file applied to the flows started during the step.
+    pub config_profile: String,
+}
+
+/// Sequence of flow configurations applied one after the other, e.g. a baseline without faults,
+/// then increasing packet loss, then a recovery period.
+#[derive(Debug, Deserialize)]
+pub struct TestScenario {
+    pub steps: Vec<ScenarioStep>,
+}
+
+/// Statistics of the flows that completed during a step.
+#[derive(Debug, Serialize)]
+struct StepStats<'a> {
+    step: usize,
+    config_profile: &'a str,
+    duration_secs: u64,
+    #[serde(flatten)]
+    totals: FlowTotals,
+}
+
+impl TestScenario {
+    /// Reads a scenario from a YAML file, JSON being accepted as well.
+    pub async fn from_file(path: &str) -> anyhow::Result<TestScenario> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read scenario file: {}", path))?;
+        serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse scenario file: {}", path))
+    }
+
+    /// Runs the steps one after the other, generating connections to all the servers at the
+    /// given rate. The flows started during a step use its configuration profile, the flows
+    /// still running at a transition keep the profile they started with.
+    ///
+    /// # Arguments
+    /// * `rate` - TPS of every server.
+    /// * `ports` - ports of the servers.
+    /// * `maps` - eBPF maps, only present when traffic shaping is enabled.
+    /// * `options` - settings applied to every connection, but the configuration file.
+    pub async fn run(
+        &self,
+        rate: u32,
+        ports: &[u16],
+        maps: Option<ClientMaps>,
+        options: ClientOptions,
+    ) {
+        for (step, scenario_step) in self.steps.iter().enumerate() {
+            info!(
+                step, config_profile = scenario_step.config_profile.as_str();
+                "Starting scenario step for {}s", scenario_step.duration_secs
+            );
+            let options = ClientOptions {
+                config_file_path: scenario_step.config_profile.clone(),
+                ..options.clone()
+            };
+            let start_totals = options.flow_counters.totals();
+
+            let mut clients = JoinSet::new();
+            for port in ports {
+                clients.spawn(client::start_client_at_rate(
+                    rate,
+                    *port,
+                    maps.clone(),
+                    options.clone(),
+                ));
+            }
+            sleep(Duration::from_secs(scenario_step.duration_secs)).await;
+            // Stops generating connections, the ones already started run to completion.
+            clients.abort_all();
+
+            let step_stats = StepStats {
+                step,
+                config_profile: &scenario_step.config_profile,
+                duration_secs: scenario_step.duration_secs,
+                totals: options.flow_counters.totals().since(&start_totals),
+            };
+            info!(step_stats:serde; "Scenario step completed");
+        }
+        info!("Scenario completed");
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Context};
+use aya::maps::{HashMap, MapData};
+use aya::Ebpf;
+use log::info;
+use netns_rs::NetNs;
+use std::time::{Duration, Instant};
+use tcp_tester::os;
+use tcp_tester_common::{FlowKey, FlowStats};
+
+use crate::client;
+
+/// Server port of the probe. Nothing is expected to listen on it, the SYN crossing the
+/// middle-box is all the self-test needs.
+const PROBE_PORT: u16 = 9;
+
+/// Time the traffic control programs have to account the probe in `FLOW_STATS`.
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Returns whether `key` is a direction of the probe flow, from the local `port`.
+fn is_probe(key: &FlowKey, port: u16) -> bool {
+    let (port, probe_port) = (u32::from(port), u32::from(PROBE_PORT));
+    (key.sport == port && key.dport == probe_port) || (key.sport == probe_port && key.dport == port)
+}
+
+fn has_probe(flow_stats: &HashMap<&MapData, FlowKey, FlowStats>, port: u16) -> bool {
+    flow_stats.keys().flatten().any(|key| is_probe(&key, port))
+}
+
+/// Checks that the traffic control programs process the traffic of the clients: connects from
+/// the client namespace to a server, through the middle-box, and fails unless the probe shows up
+/// in `FLOW_STATS` within `SELF_TEST_TIMEOUT`.
+///
+/// # Arguments
+/// * `bpf` - eBPF programs, attached.
+/// * `ns` - client namespace.
+pub fn ebpf_self_test(bpf: &Ebpf, ns: &NetNs) -> anyhow::Result<()> {
+    let flow_stats: HashMap<_, FlowKey, FlowStats> =
+        HashMap::try_from(bpf.map("FLOW_STATS").context("Map FLOW_STATS not found")?)?;
+    let server = client::server_addr(PROBE_PORT);
+    let (_probe, port) = ns
+        .run(|_| os::start_connect(server))
+        .context("Failed to enter the client namespace")?
+        .with_context(|| format!("Failed to send the self-test probe to {}", server))?;
+
+    let deadline = Instant::now() + SELF_TEST_TIMEOUT;
+    while !has_probe(&flow_stats, port) {
+        if Instant::now() >= deadline {
+            bail!(
+                "The traffic control programs did not account the self-test probe from port {} \
+                 to {} within {:?}",
+                port,
+                server,
+                SELF_TEST_TIMEOUT
+            );
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    info!("eBPF self-test passed");
+    Ok(())
+}
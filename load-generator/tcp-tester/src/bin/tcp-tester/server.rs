@@ -1,11 +1,58 @@
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{Request, Response};
 use log::{debug, info};
 use netns_rs::NetNs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpSocket, TcpStream};
 use tokio::time::{sleep, Duration};
 
-/// Namespace where the server runs.
-static SERVER_NAMESPACE: &str = "nfm-perf-test-server";
+/// Connection preface sent by HTTP/2 clients.
+const HTTP2_PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Returns whether the client starts the connection with the HTTP/2 preface.
+async fn is_http2(stream: &TcpStream) -> bool {
+    let mut preface = [0; HTTP2_PREFACE.len()];
+    match stream.peek(&mut preface).await {
+        Ok(n) => n == preface.len() && &preface == HTTP2_PREFACE,
+        Err(_) => false,
+    }
+}
+
+/// Responds to the request with its own body.
+async fn echo_http2_request(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) -> Result<(), h2::Error> {
+    let mut body = request.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+    }
+
+    let mut send_stream = respond.send_response(Response::new(()), data.is_empty())?;
+    if !data.is_empty() {
+        send_stream.send_data(data.into(), true)?;
+    }
+    Ok(())
+}
+
+/// Serves the requests of an HTTP/2 connection concurrently, echoing their bodies.
+async fn serve_http2(stream: TcpStream) -> Result<(), h2::Error> {
+    let mut connection = h2::server::handshake(stream).await?;
+    while let Some(request) = connection.accept().await {
+        let (request, respond) = request?;
+        tokio::spawn(async move {
+            if let Err(e) = echo_http2_request(request, respond).await {
+                debug!("Failed to respond to HTTP/2 request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
 
 // Function to handle each client connection asynchronously.
 async fn handle_client(mut stream: TcpStream, response_delay_ms: u64) {
@@ -14,6 +61,13 @@ async fn handle_client(mut stream: TcpStream, response_delay_ms: u64) {
         sleep(Duration::from_millis(response_delay_ms)).await;
     }
     stream.set_nodelay(true).unwrap();
+
+    if is_http2(&stream).await {
+        if let Err(e) = serve_http2(stream).await {
+            debug!("HTTP/2 connection failed: {}", e);
+        }
+        return;
+    }
     let mut buffer = [0; 16384];
 
     loop {
@@ -37,9 +91,15 @@ async fn handle_client(mut stream: TcpStream, response_delay_ms: u64) {
     }
 }
 
-/// Starts a server that will return the received message to the client.
-pub async fn server(port: u16, response_delay_ms: u64) {
-    let namespace = NetNs::get(SERVER_NAMESPACE).unwrap();
+/// Starts a server that will return the received message to the client. HTTP/2 connections are
+/// detected from their preface, the server then echoes the body of every request.
+///
+/// # Arguments
+/// * `port` - port the server listens on.
+/// * `response_delay_ms` - time waited before echoing the messages of a new connection.
+/// * `namespace` - namespace where the server runs.
+pub async fn server(port: u16, response_delay_ms: u64, namespace: String) {
+    let namespace = NetNs::get(namespace).unwrap();
     let server_address = format!("0.0.0.0:{}", port).parse().unwrap();
     let server_socket = namespace.run(|_| TcpSocket::new_v4().unwrap()).unwrap();
 
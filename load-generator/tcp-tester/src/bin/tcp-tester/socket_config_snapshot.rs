@@ -0,0 +1,85 @@
+use anyhow::Context;
+use log::{info, warn};
+use serde::Serialize;
+use tcp_tester_common::{Direction, FaultProfile};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::client::SocketConfigMap;
+
+/// Direction of a `SOCKET_CONFIG` key, as written in the snapshots.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SnapshotDirection {
+    Ingress,
+    Egress,
+}
+
+/// `SOCKET_CONFIG` entry of a snapshot.
+#[derive(Debug, Serialize)]
+struct SnapshotEntry {
+    cookie: u64,
+    direction: SnapshotDirection,
+    config: FaultProfile,
+}
+
+/// Contents of the `SOCKET_CONFIG` map, written as JSON on SIGTERM with `--snapshot-on-sigterm`,
+/// to inspect the configuration of the sockets open when the process was stopped. The entries
+/// are keyed by socket cookies, which are meaningless once the sockets are closed, so they can't
+/// be loaded back by the next run.
+#[derive(Debug, Serialize)]
+pub struct SocketConfigSnapshot {
+    entries: Vec<SnapshotEntry>,
+}
+
+impl SocketConfigSnapshot {
+    /// Reads the current entries of the `SOCKET_CONFIG` map.
+    pub fn take(socket_config: &SocketConfigMap) -> SocketConfigSnapshot {
+        let maps = socket_config.lock().unwrap();
+        let entries = maps
+            .config
+            .iter()
+            .filter_map(|entry| entry.ok())
+            .map(|(key, config)| SnapshotEntry {
+                cookie: key.cookie,
+                direction: if key.direction == Direction::INGRESS {
+                    SnapshotDirection::Ingress
+                } else {
+                    SnapshotDirection::Egress
+                },
+                config,
+            })
+            .collect();
+        SocketConfigSnapshot { entries }
+    }
+
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write snapshot {}", path))
+    }
+}
+
+/// Waits for SIGTERM, then writes the `SOCKET_CONFIG` snapshot to `path` and exits.
+pub async fn write_snapshot_on_sigterm(socket_config: SocketConfigMap, path: String) {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(error) => {
+            warn!(
+                "Failed to handle SIGTERM, no snapshot will be written: {}",
+                error
+            );
+            return;
+        }
+    };
+    sigterm.recv().await;
+
+    let snapshot = SocketConfigSnapshot::take(&socket_config);
+    match snapshot.write(&path) {
+        Ok(()) => info!(
+            "Wrote {} SOCKET_CONFIG entries to {}",
+            snapshot.entries.len(),
+            path
+        ),
+        Err(error) => warn!("Failed to write SOCKET_CONFIG snapshot: {:?}", error),
+    }
+    std::process::exit(0);
+}
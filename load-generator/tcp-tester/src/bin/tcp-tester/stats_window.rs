@@ -0,0 +1,135 @@
+use aya::maps::Map;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{Duration, Instant};
+
+use crate::client::{FlowEvent, FlowResult};
+use crate::ebpf_loader;
+
+/// Averages of the flows completed during the window.
+#[derive(Debug, Serialize)]
+struct WindowStats {
+    window_secs: u64,
+    flows_completed: usize,
+    flows_per_sec: f64,
+    avg_duration_ms: f64,
+    bytes_written_per_sec: f64,
+    bytes_read_per_sec: f64,
+}
+
+/// Flows completed during the last `window`, ordered by completion time. The totals are kept
+/// along with the flows, so trimming and reporting don't iterate over them.
+struct StatsWindow {
+    window: Duration,
+    started: Instant,
+    flows: VecDeque<(Instant, FlowResult)>,
+    duration_ms: u64,
+    bytes_written: u64,
+    bytes_read: u64,
+}
+
+impl StatsWindow {
+    fn new(window: Duration) -> Self {
+        StatsWindow {
+            window,
+            started: Instant::now(),
+            flows: VecDeque::new(),
+            duration_ms: 0,
+            bytes_written: 0,
+            bytes_read: 0,
+        }
+    }
+
+    fn push(&mut self, completed: Instant, flow: FlowResult) {
+        self.duration_ms += flow.duration_ms;
+        self.bytes_written += flow.stats.bytes_written;
+        self.bytes_read += flow.stats.bytes_read;
+        self.flows.push_back((completed, flow));
+    }
+
+    /// Drops the flows completed before the window.
+    fn trim(&mut self, now: Instant) {
+        while let Some((completed, flow)) = self.flows.front() {
+            if now.duration_since(*completed) <= self.window {
+                break;
+            }
+            self.duration_ms -= flow.duration_ms;
+            self.bytes_written -= flow.stats.bytes_written;
+            self.bytes_read -= flow.stats.bytes_read;
+            self.flows.pop_front();
+        }
+    }
+
+    fn stats(&self, now: Instant) -> WindowStats {
+        // The window is not full until it has elapsed once.
+        let secs = self
+            .window
+            .min(now.duration_since(self.started))
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let flows = self.flows.len();
+        WindowStats {
+            window_secs: self.window.as_secs(),
+            flows_completed: flows,
+            flows_per_sec: flows as f64 / secs,
+            avg_duration_ms: if flows > 0 {
+                self.duration_ms as f64 / flows as f64
+            } else {
+                0.0
+            },
+            bytes_written_per_sec: self.bytes_written as f64 / secs,
+            bytes_read_per_sec: self.bytes_read as f64 / secs,
+        }
+    }
+}
+
+/// Periodically logs the rolling averages of the flows completed during the last `window`, along
+/// with the utilization of the hash maps of the eBPF programs, as the
+/// `ebpf_map_utilization_percent` gauge labeled by map.
+///
+/// # Arguments
+/// * `flow_events` - lifecycle events of the flows.
+/// * `window` - period the averages are computed over.
+/// * `interval` - time between two consecutive reports.
+/// * `hash_maps` - hash maps of the eBPF programs, see `ebpf_loader::clone_hash_maps`, empty
+///   without eBPF.
+pub async fn log_stats_window_periodically(
+    mut flow_events: broadcast::Receiver<FlowEvent>,
+    window: Duration,
+    interval: Duration,
+    hash_maps: Vec<(String, Map)>,
+) {
+    let mut stats_window = StatsWindow::new(window);
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now = Instant::now();
+                stats_window.trim(now);
+                let window_stats = stats_window.stats(now);
+                info!(window_stats:serde; "Rolling flow stats");
+                for stats in ebpf_loader::dump_map_stats(&hash_maps) {
+                    let ebpf_map_utilization_percent = stats.utilization_pct;
+                    info!(
+                        map = stats.name.as_str(), ebpf_map_utilization_percent;
+                        "eBPF map utilization"
+                    );
+                }
+            }
+            event = flow_events.recv() => match event {
+                Ok(FlowEvent::Completed(flow)) => {
+                    let now = Instant::now();
+                    stats_window.push(now, *flow);
+                    stats_window.trim(now);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Rolling flow stats skipped {} flow events", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
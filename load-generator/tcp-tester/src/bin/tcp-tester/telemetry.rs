@@ -0,0 +1,130 @@
+use anyhow::Context as _;
+use log::warn;
+use opentelemetry::propagation::{Extractor, Injector, TextMapCompositePropagator};
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tcp_tester_common::FlowConfig;
+
+const TRACER_NAME: &str = "tcp-tester";
+
+/// Sets up the W3C trace context and baggage propagation, and the export of the spans to the
+/// OpenTelemetry collector at `otlp_endpoint`, over gRPC. The spans are not recorded when no
+/// endpoint is given, but the trace context of the flow configuration is propagated anyway.
+///
+/// Returns the tracer provider, to be shut down before exiting so the pending spans are
+/// exported.
+pub fn init_telemetry(otlp_endpoint: Option<&str>) -> anyhow::Result<Option<TracerProvider>> {
+    global::set_text_map_propagator(TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+        Box::new(BaggagePropagator::new()),
+    ]));
+
+    let Some(otlp_endpoint) = otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(otlp_endpoint)
+        .build()
+        .with_context(|| format!("Failed to set up the OTLP exporter to {}", otlp_endpoint))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", TRACER_NAME)]))
+        .build();
+    global::set_tracer_provider(provider.clone());
+    Ok(Some(provider))
+}
+
+/// Propagation headers of the flow configuration, read by the propagators.
+struct ConfigExtractor<'a>(&'a BTreeMap<String, String>);
+
+impl Extractor for ConfigExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+/// Headers of an HTTP request, written by the propagators.
+struct HeaderInjector<'a>(&'a mut http::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        match (
+            http::HeaderName::from_bytes(key.as_bytes()),
+            http::HeaderValue::from_str(&value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                self.0.insert(name, value);
+            }
+            _ => warn!("Invalid trace context header {}: {}", key, value),
+        }
+    }
+}
+
+/// Starts the span of a client, under the OpenTelemetry context of the flow configuration when
+/// it has one, and returns the context holding it. The span ends once the context and all its
+/// clones are dropped.
+pub fn client_context(config: Option<&FlowConfig>) -> Context {
+    let parent = match config.and_then(|config| config.opentelemetry_context.as_ref()) {
+        Some(headers) => global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ConfigExtractor(headers))
+        }),
+        None => Context::new(),
+    };
+    let span = global::tracer(TRACER_NAME).start_with_context("client", &parent);
+    parent.with_span(span)
+}
+
+/// Starts the span of a connection to `addr`, child of the span of `parent`.
+pub fn start_connect_span(parent: &Context, addr: SocketAddr) -> global::BoxedSpan {
+    let mut span = global::tracer(TRACER_NAME).start_with_context("connect", parent);
+    span.set_attribute(KeyValue::new("server.address", addr.ip().to_string()));
+    span.set_attribute(KeyValue::new("server.port", i64::from(addr.port())));
+    span
+}
+
+/// Ends the span of a connection, recording whether it succeeded.
+pub fn end_connect_span<T, E: std::fmt::Debug>(mut span: global::BoxedSpan, result: &Result<T, E>) {
+    if let Err(error) = result {
+        span.set_status(Status::error(format!("{:?}", error)));
+    }
+    span.end();
+}
+
+/// Injects the OpenTelemetry context in the headers of an HTTP request.
+pub fn inject_context(context: &Context, headers: &mut http::HeaderMap) {
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_context_propagates_config_traceparent() {
+        init_telemetry(None).unwrap();
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut config =
+            FlowConfig::from_json(include_str!("../../config/no_faults.json")).unwrap();
+        config.opentelemetry_context =
+            Some(BTreeMap::from([("traceparent".into(), traceparent.into())]));
+
+        let mut headers = http::HeaderMap::new();
+        inject_context(&client_context(Some(&config)), &mut headers);
+
+        // Without an exporter, the client span is the one of the configuration.
+        assert_eq!(headers["traceparent"], traceparent);
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{bail, Context};
+use pcap_file::pcap::{PcapHeader, PcapPacket, PcapWriter};
+use pcap_file::DataLink;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Placeholder of the capture path replaced with the local port of the flow, so that the flows
+/// sharing a configuration write distinct files.
+pub const PORT_PLACEHOLDER: &str = "{port}";
+
+const IPV4_HEADER_LEN: usize = 20;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_FLAG_PSH: u8 = 0x08;
+const TCP_FLAG_ACK: u8 = 0x10;
+const IPPROTO_TCP: u8 = 6;
+
+/// Largest payload framed in a single segment, for the IPv4 total length to fit in 16 bits.
+const MAX_SEGMENT_PAYLOAD: usize = u16::MAX as usize - IPV4_HEADER_LEN - TCP_HEADER_LEN;
+
+/// Direction of the data recorded by a `FlowCapture`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureDirection {
+    Sent,
+    Received,
+}
+
+/// pcap capture of the data of a flow. The actual packets are not available to the stream, so
+/// every read and write is framed as TCP segments with headers synthesized from the flow
+/// addresses, the sequence numbers counting the bytes in each direction. The packets are
+/// buffered, and written to the file at the latest when the capture is dropped.
+pub struct FlowCapture {
+    writer: PcapWriter<BufWriter<File>>,
+    local: SocketAddrV4,
+    peer: SocketAddrV4,
+    sent: u32,
+    received: u32,
+}
+
+impl FlowCapture {
+    /// Creates the capture file at `path`, `{port}` being replaced with the local port.
+    pub fn create(path: &str, local: SocketAddr, peer: SocketAddr) -> anyhow::Result<Self> {
+        let (SocketAddr::V4(local), SocketAddr::V4(peer)) = (local, peer) else {
+            bail!("Only IPv4 flows can be captured");
+        };
+        let path = path.replace(PORT_PLACEHOLDER, &local.port().to_string());
+        let file =
+            File::create(&path).with_context(|| format!("Failed to create capture {}", path))?;
+        let header = PcapHeader {
+            datalink: DataLink::RAW,
+            ..Default::default()
+        };
+        let writer = PcapWriter::with_header(BufWriter::new(file), header)
+            .with_context(|| format!("Failed to write capture header {}", path))?;
+        Ok(FlowCapture {
+            writer,
+            local,
+            peer,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    /// Records data sent or received on the flow.
+    pub fn record(&mut self, direction: CaptureDirection, data: &[u8]) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        for payload in data.chunks(MAX_SEGMENT_PAYLOAD) {
+            let (src, dst, seq, ack) = match direction {
+                CaptureDirection::Sent => (self.local, self.peer, self.sent, self.received),
+                CaptureDirection::Received => (self.peer, self.local, self.received, self.sent),
+            };
+            let packet = segment(src, dst, seq, ack, payload);
+            self.writer
+                .write_packet(&PcapPacket::new(timestamp, packet.len() as u32, &packet))
+                .context("Failed to write capture packet")?;
+
+            let length = payload.len() as u32;
+            match direction {
+                CaptureDirection::Sent => self.sent = self.sent.wrapping_add(length),
+                CaptureDirection::Received => self.received = self.received.wrapping_add(length),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds an IPv4 packet holding a TCP segment with the payload.
+fn segment(src: SocketAddrV4, dst: SocketAddrV4, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    let tcp_len = TCP_HEADER_LEN + payload.len();
+    let mut tcp = Vec::with_capacity(tcp_len);
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(((TCP_HEADER_LEN / 4) as u8) << 4);
+    tcp.push(TCP_FLAG_PSH | TCP_FLAG_ACK);
+    tcp.extend_from_slice(&u16::MAX.to_be_bytes()); // Window
+    tcp.extend_from_slice(&[0; 4]); // Checksum and urgent pointer
+    tcp.extend_from_slice(payload);
+
+    let mut pseudo_header = Vec::with_capacity(12);
+    pseudo_header.extend_from_slice(&src.ip().octets());
+    pseudo_header.extend_from_slice(&dst.ip().octets());
+    pseudo_header.extend_from_slice(&[0, IPPROTO_TCP]);
+    pseudo_header.extend_from_slice(&(tcp_len as u16).to_be_bytes());
+    let checksum_value = checksum(&[&pseudo_header, &tcp]);
+    tcp[16..18].copy_from_slice(&checksum_value.to_be_bytes());
+
+    let mut packet = Vec::with_capacity(IPV4_HEADER_LEN + tcp_len);
+    packet.push(0x45); // Version and header length
+    packet.push(0); // DSCP and ECN
+    packet.extend_from_slice(&((IPV4_HEADER_LEN + tcp_len) as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0x40, 0]); // Identification, don't fragment
+    packet.push(64); // TTL
+    packet.push(IPPROTO_TCP);
+    packet.extend_from_slice(&[0, 0]); // Checksum
+    packet.extend_from_slice(&src.ip().octets());
+    packet.extend_from_slice(&dst.ip().octets());
+    let checksum_value = checksum(&[&packet]);
+    packet[10..12].copy_from_slice(&checksum_value.to_be_bytes());
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+/// Internet checksum of the concatenated parts, every part but the last being of even length.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            let word = match word {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                [high] => u16::from_be_bytes([*high, 0]),
+                _ => unreachable!(),
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcap_file::pcap::PcapReader;
+
+    #[test]
+    fn test_checksum_of_valid_header_is_zero() {
+        let local = "1.1.1.1:40000".parse().unwrap();
+        let peer = "2.2.2.2:5001".parse().unwrap();
+        let packet = segment(local, peer, 1, 2, b"odd");
+
+        assert_eq!(checksum(&[&packet[..IPV4_HEADER_LEN]]), 0);
+        let mut pseudo_header = vec![1, 1, 1, 1, 2, 2, 2, 2, 0, IPPROTO_TCP];
+        pseudo_header.extend_from_slice(&((TCP_HEADER_LEN + 3) as u16).to_be_bytes());
+        assert_eq!(checksum(&[&pseudo_header, &packet[IPV4_HEADER_LEN..]]), 0);
+    }
+
+    #[test]
+    fn test_record_tracks_sequence_numbers() {
+        let path = std::env::temp_dir().join(format!("capture-{}.pcap", uuid::Uuid::new_v4()));
+        let local: SocketAddr = "1.1.1.1:40000".parse().unwrap();
+        let peer: SocketAddr = "2.2.2.2:5001".parse().unwrap();
+
+        let mut capture = FlowCapture::create(path.to_str().unwrap(), local, peer).unwrap();
+        capture.record(CaptureDirection::Sent, b"request").unwrap();
+        capture
+            .record(CaptureDirection::Received, b"reply")
+            .unwrap();
+        capture.record(CaptureDirection::Sent, b"again").unwrap();
+        drop(capture);
+
+        let mut reader = PcapReader::new(File::open(&path).unwrap()).unwrap();
+        let mut segments = Vec::new();
+        while let Some(packet) = reader.next_packet() {
+            let data = packet.unwrap().data.into_owned();
+            let tcp = &data[IPV4_HEADER_LEN..];
+            let seq = u32::from_be_bytes(tcp[4..8].try_into().unwrap());
+            let ack = u32::from_be_bytes(tcp[8..12].try_into().unwrap());
+            segments.push((data[15], seq, ack, tcp[TCP_HEADER_LEN..].to_vec()));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            segments,
+            vec![
+                (1, 0, 0, b"request".to_vec()),
+                (2, 0, 7, b"reply".to_vec()),
+                (1, 7, 5, b"again".to_vec()),
+            ]
+        );
+    }
+}
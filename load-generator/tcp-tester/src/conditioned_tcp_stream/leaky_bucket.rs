@@ -0,0 +1,87 @@
+use std::task::{Context, Poll};
+use tokio::time::{interval, Duration, Instant, Interval, MissedTickBehavior};
+
+/// Period of the timer waking up the writes waiting for budget.
+const TICK: Duration = Duration::from_micros(100);
+
+/// Bytes the bucket holds at most, as the time they take to drain at the bandwidth. Bounds the
+/// burst after an idle period.
+const BURST: Duration = Duration::from_millis(1);
+
+/// Leaky bucket pacing the writes of a stream to a bandwidth: every write takes its bytes from a
+/// budget, refilled with the bytes the bandwidth allows since the last write, up to `BURST`
+/// worth of bytes.
+///
+/// The budget is computed from the elapsed time rather than counted in ticks, so the bandwidth
+/// holds whatever the actual resolution of the timer.
+pub struct LeakyBucket {
+    bytes_per_sec: f64,
+    capacity: f64,
+    budget: f64,
+    refilled_at: Instant,
+    tick: Interval,
+}
+
+impl LeakyBucket {
+    pub fn new(bandwidth_kbps: u64) -> Self {
+        let bytes_per_sec = bandwidth_kbps as f64 * 1000.0 / 8.0;
+        let capacity = (bytes_per_sec * BURST.as_secs_f64()).max(1.0);
+        let mut tick = interval(TICK);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        LeakyBucket {
+            bytes_per_sec,
+            capacity,
+            budget: capacity,
+            refilled_at: Instant::now(),
+            tick,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.budget = (self.budget + elapsed * self.bytes_per_sec).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Returns the number of bytes that can be written right away, at least one, or registers
+    /// the task to be woken up at the next tick when there are none.
+    pub fn poll_budget(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        self.refill();
+        if self.budget >= 1.0 {
+            return Poll::Ready(self.budget as usize);
+        }
+        // Consumes the ticks already elapsed, for the waker to be registered on the next one.
+        while self.tick.poll_tick(cx).is_ready() {}
+        Poll::Pending
+    }
+
+    /// Takes `bytes` written from the budget.
+    pub fn consume(&mut self, bytes: usize) {
+        self.budget -= bytes as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+
+    #[tokio::test]
+    async fn test_leaky_bucket_paces_to_bandwidth() {
+        // 8 Mbps: 1000 bytes per ms, the burst.
+        let mut bucket = LeakyBucket::new(8000);
+        let start = Instant::now();
+        let mut written = 0;
+        while written < 10_000 {
+            let budget = poll_fn(|cx| bucket.poll_budget(cx)).await;
+            assert!(budget <= 1000);
+            bucket.consume(budget);
+            written += budget;
+        }
+        // The first 1000 bytes are the initial burst, the timer wakes up the writes late.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(9), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(100), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,653 @@
+mod capture;
+mod leaky_bucket;
+mod tx_timestamps;
+
+use crate::os;
+use capture::{CaptureDirection, FlowCapture};
+use clap::ValueEnum;
+use leaky_bucket::LeakyBucket;
+use log::{debug, info, warn};
+use nix::sys::socket::{self as sockopt, sockopt::RcvBuf};
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tcp_tester_common::CorruptionSpec;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Sleep};
+use tx_timestamps::TxTimestamps;
+
+/// Transmit timestamps collected with `SO_TIMESTAMPING`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+pub enum SockTimestamping {
+    /// Taken by the kernel when the packet is passed to the network device.
+    Sw,
+    /// Taken by the network card, which must have transmit timestamping enabled, e.g. with
+    /// `hwstamp_ctl`. They are in the clock of the card.
+    Hw,
+}
+
+/// I/O statistics of a `ConditionedTcpStream`.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Stats {
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub writes: u64,
+    pub reads: u64,
+    /// Delays injected by the stream itself. The ones injected by the traffic control programs
+    /// are not known per stream.
+    pub delays_injected: u64,
+    /// Drops injected by the stream itself. The ones injected by the traffic control programs
+    /// are not known per stream.
+    pub drops_injected: u64,
+    /// Sends matched with their transmit timestamp, with `--enable-sock-timestamping`.
+    pub tx_timestamps: u64,
+    /// Total and maximum time between the sends and their transmit timestamp, i.e. the queuing
+    /// delay in the network stack.
+    pub tx_queuing_delay_us_total: u64,
+    pub tx_queuing_delay_us_max: u64,
+    pub read_stats: ReadStats,
+}
+
+/// Ratio between the largest and the smallest read above which `ReadStats::record` reports the
+/// segmentation.
+const CHUNK_VARIATION_RATIO: usize = 10;
+
+/// Sizes of the chunks the data was read in, which differ from the sizes it was sent in when the
+/// TCP stack splits or coalesces the segments. The end of stream is not accounted.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ReadStats {
+    pub max_chunk: usize,
+    pub min_chunk: usize,
+    pub total_reads: u64,
+}
+
+impl ReadStats {
+    fn varies(&self) -> bool {
+        self.max_chunk / self.min_chunk.max(1) > CHUNK_VARIATION_RATIO
+    }
+
+    /// Records a read of `len` bytes, returning whether the chunk sizes just started to vary by
+    /// more than `CHUNK_VARIATION_RATIO`.
+    fn record(&mut self, len: usize) -> bool {
+        let varied = self.varies();
+        self.min_chunk = match self.total_reads {
+            0 => len,
+            _ => self.min_chunk.min(len),
+        };
+        self.max_chunk = self.max_chunk.max(len);
+        self.total_reads += 1;
+        !varied && self.varies()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    writes: AtomicU64,
+    reads: AtomicU64,
+    delays_injected: AtomicU64,
+    drops_injected: AtomicU64,
+    tx_timestamps: AtomicU64,
+    tx_queuing_delay_us_total: AtomicU64,
+    tx_queuing_delay_us_max: AtomicU64,
+}
+
+/// Zero window simulation: once `after_bytes` have been written, the receive buffer of the
+/// socket is shrunk to its minimum so the peer sees a zero receive window.
+struct ZeroWindow {
+    after_bytes: u64,
+    duration: Option<Duration>,
+    state: ZeroWindowState,
+}
+
+enum ZeroWindowState {
+    Armed,
+    Stalled {
+        original_rcvbuf: usize,
+        restore: Option<Pin<Box<Sleep>>>,
+    },
+    Done,
+}
+
+/// Fault injected on demand with `ConditionedTcpStream::inject_fault_now`.
+#[derive(Clone, Copy, Debug)]
+pub enum InstantFault {
+    /// Discards the next write, reported as written but never sent.
+    Drop,
+    /// Holds the next write for the given duration.
+    Delay(Duration),
+    /// Aborts the connection with a RST.
+    Reset,
+    /// Flips the bits of the first byte of the next write.
+    CorruptNext,
+}
+
+/// Fault waiting for the next write.
+enum PendingFault {
+    Drop,
+    Delay(Pin<Box<Sleep>>),
+    CorruptNext,
+}
+
+/// Returns `data`, written at `offset` of the stream, with the bytes in the ranges of `specs`
+/// overwritten by their pattern, or `None` when it overlaps none of them.
+fn apply_targeted_corruption(
+    specs: &[CorruptionSpec],
+    offset: u64,
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    let end = offset + data.len() as u64;
+    let mut corrupted: Option<Vec<u8>> = None;
+    for spec in specs.iter().filter(|spec| !spec.pattern.is_empty()) {
+        let start = spec.offset.max(offset);
+        let stop = spec.offset.saturating_add(spec.length).min(end);
+        if start >= stop {
+            continue;
+        }
+        let corrupted = corrupted.get_or_insert_with(|| data.to_vec());
+        let pattern = spec
+            .pattern
+            .iter()
+            .cycle()
+            .skip(((start - spec.offset) % spec.pattern.len() as u64) as usize);
+        for (byte, pattern_byte) in corrupted[(start - offset) as usize..(stop - offset) as usize]
+            .iter_mut()
+            .zip(pattern)
+        {
+            *byte = *pattern_byte;
+        }
+    }
+    corrupted
+}
+
+/// TCP stream keeping track of the I/O done through its `AsyncRead` and `AsyncWrite`
+/// implementations. I/O done directly on `stream` is not accounted.
+pub struct ConditionedTcpStream {
+    pub stream: TcpStream,
+    counters: Counters,
+    zero_window: Option<ZeroWindow>,
+    pending_fault: Option<PendingFault>,
+    capture: Option<FlowCapture>,
+    tx_timestamps: Option<TxTimestamps>,
+    bandwidth_limit: Option<LeakyBucket>,
+    targeted_corruption: Vec<CorruptionSpec>,
+    read_stats: ReadStats,
+}
+
+impl ConditionedTcpStream {
+    pub fn new(stream: TcpStream) -> Self {
+        ConditionedTcpStream {
+            stream,
+            counters: Counters::default(),
+            zero_window: None,
+            pending_fault: None,
+            capture: None,
+            tx_timestamps: None,
+            bandwidth_limit: None,
+            targeted_corruption: Vec::new(),
+            read_stats: ReadStats::default(),
+        }
+    }
+
+    /// Records the data read from and written to the stream, after fault injection, in a pcap
+    /// file at `path`. See `FlowCapture::create` for the path. The stream is left uncaptured if
+    /// the file can't be created.
+    pub fn with_capture(mut self, path: &str) -> Self {
+        let capture = match (self.stream.local_addr(), self.stream.peer_addr()) {
+            (Ok(local), Ok(peer)) => FlowCapture::create(path, local, peer),
+            (Err(error), _) | (_, Err(error)) => Err(error.into()),
+        };
+        match capture {
+            Ok(capture) => self.capture = Some(capture),
+            Err(error) => warn!("Failed to set up the flow capture: {:?}", error),
+        }
+        self
+    }
+
+    /// Collects the transmit timestamps of the data written to the stream, to account the
+    /// queuing delay in the stats. The timestamps only count the data written from now on, and
+    /// are read as the stream is used, so the last ones may be missed when it gets closed.
+    pub fn with_tx_timestamping(mut self, mode: SockTimestamping) -> Self {
+        match TxTimestamps::enable(self.stream.as_raw_fd(), mode) {
+            Ok(tx_timestamps) => self.tx_timestamps = Some(tx_timestamps),
+            Err(error) => warn!("Failed to enable transmit timestamps: {}", error),
+        }
+        self
+    }
+
+    /// Paces the writes to `bandwidth_kbps`, with a leaky bucket. The writes exceeding the
+    /// budget are cut short, or wait for it to be refilled when it is empty.
+    pub fn with_bandwidth_limit(mut self, bandwidth_kbps: u64) -> Self {
+        self.bandwidth_limit = Some(LeakyBucket::new(bandwidth_kbps));
+        self
+    }
+
+    /// Overwrites the byte ranges of `specs`, offsets of the data written to the stream, with
+    /// their pattern.
+    pub fn with_targeted_corruption(mut self, specs: Vec<CorruptionSpec>) -> Self {
+        self.targeted_corruption = specs;
+        self
+    }
+
+    /// Stalls the peer with a zero receive window once `after_bytes` have been written to the
+    /// stream. The receive buffer is restored after `duration`, if set, or when the connection
+    /// is closed otherwise.
+    pub fn with_zero_window(mut self, after_bytes: u64, duration: Option<Duration>) -> Self {
+        self.zero_window = Some(ZeroWindow {
+            after_bytes,
+            duration,
+            state: ZeroWindowState::Armed,
+        });
+        self
+    }
+
+    /// Injects a fault right away, for tests to trigger faults at precise points of the
+    /// exchange. The faults other than `Reset` apply to the next write, replacing the fault
+    /// still pending if any.
+    pub fn inject_fault_now(&mut self, fault: InstantFault) -> anyhow::Result<()> {
+        self.pending_fault = match fault {
+            InstantFault::Drop => Some(PendingFault::Drop),
+            InstantFault::Delay(duration) => Some(PendingFault::Delay(Box::pin(sleep(duration)))),
+            InstantFault::CorruptNext => Some(PendingFault::CorruptNext),
+            InstantFault::Reset => {
+                os::reset_connection(self.stream.as_raw_fd())?;
+                None
+            }
+        };
+        info!(fault:?; "Fault injected");
+        Ok(())
+    }
+
+    /// Waits for `duration`, then holds the next write for `duration` as well. Lets the tests
+    /// stall the exchange at a precise point by awaiting it before writing, instead of
+    /// configuring the delay in the `FlowConfig` ahead of time.
+    pub async fn inject_delay(&mut self, duration: Duration) -> anyhow::Result<()> {
+        sleep(duration).await;
+        self.inject_fault_now(InstantFault::Delay(duration))
+    }
+
+    /// Returns whether the connection was set up with TCP Fast Open, its SYN data having been
+    /// acknowledged.
+    pub fn used_fast_open(&self) -> nix::Result<bool> {
+        let info = os::tcp_info(self.stream.as_raw_fd())?;
+        Ok(info.tcpi_options & os::TCPI_OPT_SYN_DATA != 0)
+    }
+
+    /// Returns the smoothed RTT of the connection, as estimated by the kernel.
+    pub fn rtt(&self) -> nix::Result<Duration> {
+        let info = os::tcp_info(self.stream.as_raw_fd())?;
+        Ok(Duration::from_micros(info.tcpi_rtt.into()))
+    }
+
+    /// Returns the I/O statistics since the stream was created.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            bytes_written: self.counters.bytes_written.load(Ordering::Relaxed),
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            writes: self.counters.writes.load(Ordering::Relaxed),
+            reads: self.counters.reads.load(Ordering::Relaxed),
+            delays_injected: self.counters.delays_injected.load(Ordering::Relaxed),
+            drops_injected: self.counters.drops_injected.load(Ordering::Relaxed),
+            tx_timestamps: self.counters.tx_timestamps.load(Ordering::Relaxed),
+            tx_queuing_delay_us_total: self
+                .counters
+                .tx_queuing_delay_us_total
+                .load(Ordering::Relaxed),
+            tx_queuing_delay_us_max: self
+                .counters
+                .tx_queuing_delay_us_max
+                .load(Ordering::Relaxed),
+            read_stats: self.read_stats,
+        }
+    }
+
+    /// Starts the zero window stall if the write threshold has been reached.
+    fn start_zero_window(&mut self) {
+        let bytes_written = self.counters.bytes_written.load(Ordering::Relaxed);
+        let Some(zero_window) = self.zero_window.as_mut() else {
+            return;
+        };
+        if !matches!(zero_window.state, ZeroWindowState::Armed)
+            || bytes_written < zero_window.after_bytes
+        {
+            return;
+        }
+
+        let fd = self.stream.as_raw_fd();
+        // The value is clamped to the kernel minimum.
+        let result = sockopt::getsockopt(fd, RcvBuf)
+            .and_then(|original| sockopt::setsockopt(fd, RcvBuf, &0).map(|_| original));
+        zero_window.state = match result {
+            Ok(original_rcvbuf) => {
+                info!(bytes_written; "Zero window stall started");
+                self.counters
+                    .delays_injected
+                    .fetch_add(1, Ordering::Relaxed);
+                ZeroWindowState::Stalled {
+                    original_rcvbuf,
+                    restore: zero_window
+                        .duration
+                        .map(|duration| Box::pin(sleep(duration))),
+                }
+            }
+            Err(error) => {
+                warn!("Failed to start zero window stall: {}", error);
+                ZeroWindowState::Done
+            }
+        };
+    }
+
+    /// Records the data in the capture, if any. The capture stops at the first failure.
+    fn capture(&mut self, direction: CaptureDirection, data: &[u8]) {
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+        if let Err(error) = capture.record(direction, data) {
+            warn!(
+                "Failed to capture flow data, stopping the capture: {:?}",
+                error
+            );
+            self.capture = None;
+        }
+    }
+
+    /// Accounts the transmit timestamps available, if enabled.
+    fn collect_tx_timestamps(&mut self) {
+        let Some(tx_timestamps) = self.tx_timestamps.as_mut() else {
+            return;
+        };
+        let counters = &self.counters;
+        tx_timestamps.collect(self.stream.as_raw_fd(), |queuing_delay| {
+            let queuing_delay_us = queuing_delay.as_micros() as u64;
+            counters.tx_timestamps.fetch_add(1, Ordering::Relaxed);
+            counters
+                .tx_queuing_delay_us_total
+                .fetch_add(queuing_delay_us, Ordering::Relaxed);
+            counters
+                .tx_queuing_delay_us_max
+                .fetch_max(queuing_delay_us, Ordering::Relaxed);
+        });
+    }
+
+    /// Restores the receive buffer once the zero window stall is over. The restore timer is
+    /// polled from the I/O paths, so the task is woken up when it expires.
+    fn poll_zero_window_restore(&mut self, cx: &mut Context<'_>) {
+        let Some(zero_window) = self.zero_window.as_mut() else {
+            return;
+        };
+        let ZeroWindowState::Stalled {
+            original_rcvbuf,
+            restore: Some(restore),
+        } = &mut zero_window.state
+        else {
+            return;
+        };
+        if restore.as_mut().poll(cx).is_pending() {
+            return;
+        }
+
+        // The kernel doubles the value set, to account for bookkeeping overhead, while
+        // getsockopt returns the doubled value.
+        match sockopt::setsockopt(self.stream.as_raw_fd(), RcvBuf, &(*original_rcvbuf / 2)) {
+            Ok(()) => info!("Zero window stall ended"),
+            Err(error) => warn!("Failed to end zero window stall: {}", error),
+        }
+        zero_window.state = ZeroWindowState::Done;
+    }
+}
+
+impl AsyncRead for ConditionedTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_zero_window_restore(cx);
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.stream).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[filled..];
+            this.counters.reads.fetch_add(1, Ordering::Relaxed);
+            this.counters
+                .bytes_read
+                .fetch_add(read.len() as u64, Ordering::Relaxed);
+            if !read.is_empty() && this.read_stats.record(read.len()) {
+                debug!(
+                    max_chunk = this.read_stats.max_chunk, min_chunk = this.read_stats.min_chunk;
+                    "TCP segmentation significantly varies"
+                );
+            }
+            this.capture(CaptureDirection::Received, read);
+            this.collect_tx_timestamps();
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for ConditionedTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_zero_window_restore(cx);
+        let buf = match this
+            .bandwidth_limit
+            .as_mut()
+            .map(|limit| limit.poll_budget(cx))
+        {
+            Some(Poll::Ready(budget)) => &buf[..buf.len().min(budget)],
+            Some(Poll::Pending) => return Poll::Pending,
+            None => buf,
+        };
+
+        let mut corrupted = None;
+        let mut corrupting_next = false;
+        match this.pending_fault.take() {
+            Some(PendingFault::Drop) => {
+                this.counters.drops_injected.fetch_add(1, Ordering::Relaxed);
+                return Poll::Ready(Ok(buf.len()));
+            }
+            Some(PendingFault::Delay(mut delay)) => {
+                if delay.as_mut().poll(cx).is_pending() {
+                    this.pending_fault = Some(PendingFault::Delay(delay));
+                    return Poll::Pending;
+                }
+                this.counters
+                    .delays_injected
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            Some(PendingFault::CorruptNext) => {
+                let mut data = buf.to_vec();
+                if let Some(byte) = data.first_mut() {
+                    *byte ^= 0xff;
+                }
+                corrupted = Some(data);
+                corrupting_next = true;
+            }
+            None => {}
+        }
+        let offset = this.counters.bytes_written.load(Ordering::Relaxed);
+        if let Some(data) = apply_targeted_corruption(
+            &this.targeted_corruption,
+            offset,
+            corrupted.as_deref().unwrap_or(buf),
+        ) {
+            corrupted = Some(data);
+        }
+
+        let data = corrupted.as_deref().unwrap_or(buf);
+        let poll = Pin::new(&mut this.stream).poll_write(cx, data);
+        if corrupting_next && !matches!(poll, Poll::Ready(Ok(written)) if written > 0) {
+            // Nothing has been sent yet, corrupt the next attempt instead.
+            this.pending_fault = Some(PendingFault::CorruptNext);
+        }
+        if let Poll::Ready(Ok(written)) = poll {
+            this.counters.writes.fetch_add(1, Ordering::Relaxed);
+            this.counters
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
+            if let Some(limit) = this.bandwidth_limit.as_mut() {
+                limit.consume(written);
+            }
+            this.capture(CaptureDirection::Sent, &data[..written]);
+            if let Some(tx_timestamps) = this.tx_timestamps.as_mut() {
+                tx_timestamps.on_sent(written);
+            }
+            this.collect_tx_timestamps();
+            this.start_zero_window();
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.collect_tx_timestamps();
+        Pin::new(&mut this.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::Instant;
+
+    /// Returns a client stream and the server side of a loopback connection.
+    async fn connect() -> (ConditionedTcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (ConditionedTcpStream::new(client), server)
+    }
+
+    #[test]
+    fn test_read_stats_chunk_variation() {
+        let mut read_stats = ReadStats::default();
+        assert!(!read_stats.record(1000));
+        assert!(!read_stats.record(100));
+        // Reported once, when the ratio goes over 10.
+        assert!(read_stats.record(90));
+        assert!(!read_stats.record(1));
+        assert_eq!(read_stats.min_chunk, 1);
+        assert_eq!(read_stats.max_chunk, 1000);
+        assert_eq!(read_stats.total_reads, 4);
+    }
+
+    #[tokio::test]
+    async fn test_drop_discards_next_write() {
+        let (mut client, mut server) = connect().await;
+
+        client.inject_fault_now(InstantFault::Drop).unwrap();
+        client.write_all(b"dropped").await.unwrap();
+        client.write_all(b"sent").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"sent");
+        assert_eq!(client.stats().drops_injected, 1);
+        assert_eq!(client.stats().bytes_written, 4);
+    }
+
+    #[tokio::test]
+    async fn test_delay_holds_next_write() {
+        let (mut client, mut server) = connect().await;
+
+        let start = Instant::now();
+        client
+            .inject_fault_now(InstantFault::Delay(Duration::from_millis(100)))
+            .unwrap();
+        client.write_all(b"delayed").await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let mut received = [0; 7];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"delayed");
+        assert_eq!(client.stats().delays_injected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_inject_delay_waits_then_holds_next_write() {
+        let (mut client, mut server) = connect().await;
+
+        let start = Instant::now();
+        client
+            .inject_delay(Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        client.write_all(b"delayed").await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let mut received = [0; 7];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"delayed");
+        assert_eq!(client.stats().delays_injected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_next_flips_first_byte() {
+        let (mut client, mut server) = connect().await;
+
+        client.inject_fault_now(InstantFault::CorruptNext).unwrap();
+        client.write_all(b"ab").await.unwrap();
+        client.write_all(b"cd").await.unwrap();
+
+        let mut received = [0; 4];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, [b'a' ^ 0xff, b'b', b'c', b'd']);
+    }
+
+    #[tokio::test]
+    async fn test_targeted_corruption_overwrites_ranges() {
+        let (client, mut server) = connect().await;
+        let mut client = client.with_targeted_corruption(vec![
+            CorruptionSpec {
+                offset: 0,
+                length: 2,
+                pattern: b"XY".to_vec(),
+            },
+            // Across two writes, the pattern being repeated.
+            CorruptionSpec {
+                offset: 3,
+                length: 3,
+                pattern: b"z".to_vec(),
+            },
+        ]);
+
+        client.write_all(b"abcd").await.unwrap();
+        client.write_all(b"efgh").await.unwrap();
+
+        let mut received = [0; 8];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"XYczzzgh");
+    }
+
+    #[tokio::test]
+    async fn test_reset_aborts_connection() {
+        let (mut client, mut server) = connect().await;
+
+        client.inject_fault_now(InstantFault::Reset).unwrap();
+
+        let mut received = [0; 1];
+        let error = server.read(&mut received).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    }
+}
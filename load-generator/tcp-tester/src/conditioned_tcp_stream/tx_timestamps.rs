@@ -0,0 +1,151 @@
+use crate::os::{self, TxTimestamp};
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::os::fd::RawFd;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::SockTimestamping;
+
+/// Sends whose transmit timestamp has not been read, beyond which the oldest ones are forgotten
+/// so that a socket losing its timestamps doesn't grow them without bound.
+const MAX_PENDING_SENDS: usize = 1024;
+
+/// Matches the transmit timestamps of a socket with the time the data was sent by the
+/// application. With `SOF_TIMESTAMPING_OPT_ID`, the timestamp of a send is identified by the
+/// offset of its last byte, counted from when the timestamps were enabled.
+pub struct TxTimestamps {
+    mode: SockTimestamping,
+    /// Offset of the last byte and application time of the sends waiting for their timestamp.
+    pending: VecDeque<(u32, SystemTime)>,
+    /// Bytes sent since the timestamps were enabled.
+    sent: u32,
+}
+
+impl TxTimestamps {
+    /// Enables the transmit timestamps on the socket, which must be connected for the sends to
+    /// be counted from now.
+    pub fn enable(fd: RawFd, mode: SockTimestamping) -> nix::Result<TxTimestamps> {
+        let flags = match mode {
+            SockTimestamping::Sw => {
+                libc::SOF_TIMESTAMPING_TX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE
+            }
+            SockTimestamping::Hw => {
+                libc::SOF_TIMESTAMPING_TX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+            }
+        };
+        // The payload of the packets is not needed, only the identifier of the send.
+        os::set_timestamping(
+            fd,
+            flags | libc::SOF_TIMESTAMPING_OPT_ID | libc::SOF_TIMESTAMPING_OPT_TSONLY,
+        )?;
+        Ok(TxTimestamps {
+            mode,
+            pending: VecDeque::new(),
+            sent: 0,
+        })
+    }
+
+    /// Records a send of `len` bytes by the application.
+    pub fn on_sent(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        self.sent = self.sent.wrapping_add(len as u32);
+        if self.pending.len() == MAX_PENDING_SENDS {
+            self.pending.pop_front();
+        }
+        self.pending
+            .push_back((self.sent.wrapping_sub(1), SystemTime::now()));
+    }
+
+    /// Reads the timestamps available on the socket, and calls `record` with the queuing delay
+    /// of every send they match.
+    pub fn collect(&mut self, fd: RawFd, mut record: impl FnMut(Duration)) {
+        loop {
+            let timestamp = match os::read_tx_timestamp(fd) {
+                Ok(Some(timestamp)) => timestamp,
+                Ok(None) => return,
+                Err(error) => {
+                    warn!("Failed to read transmit timestamps: {}", error);
+                    return;
+                }
+            };
+            let Some(kernel_time) = self.kernel_time(&timestamp) else {
+                continue;
+            };
+
+            // The sends before the timestamped one lost their timestamp.
+            while let Some(&(last_byte, send_time)) = self.pending.front() {
+                if timestamp.id.wrapping_sub(last_byte) as i32 <= 0 {
+                    if last_byte == timestamp.id {
+                        self.pending.pop_front();
+                        let queuing_delay = kernel_time
+                            .duration_since(send_time)
+                            .unwrap_or(Duration::ZERO);
+                        debug!(
+                            send_time_ns = nanos(send_time),
+                            kernel_tx_time_ns = nanos(kernel_time),
+                            queuing_delay_us = queuing_delay.as_micros() as u64;
+                            "Transmit timestamp"
+                        );
+                        record(queuing_delay);
+                    }
+                    break;
+                }
+                self.pending.pop_front();
+            }
+        }
+    }
+
+    /// Returns the timestamp of the mode, None when not reported.
+    fn kernel_time(&self, timestamp: &TxTimestamp) -> Option<SystemTime> {
+        let time = match self.mode {
+            SockTimestamping::Sw => timestamp.software,
+            SockTimestamping::Hw => timestamp.hardware,
+        };
+        if time.tv_sec == 0 && time.tv_nsec == 0 {
+            return None;
+        }
+        Some(UNIX_EPOCH + Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn test_software_timestamps_match_sends() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let _server = listener.accept().await.unwrap();
+        let fd = client.as_raw_fd();
+
+        let mut timestamps = TxTimestamps::enable(fd, SockTimestamping::Sw).unwrap();
+        for data in [&b"first"[..], b"second"] {
+            client.write_all(data).await.unwrap();
+            timestamps.on_sent(data.len());
+        }
+
+        let mut delays = Vec::new();
+        for _ in 0..100 {
+            timestamps.collect(fd, |delay| delays.push(delay));
+            if timestamps.pending.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(delays.len(), 2);
+    }
+}
@@ -1 +1,3 @@
+pub mod conditioned_tcp_stream;
 pub mod os;
+pub mod socket_audit;
@@ -1,8 +1,15 @@
 use libc;
 use nix;
 use nix::errno::Errno;
-use nix::sys::socket::GetSockOpt;
+use nix::sys::socket::{
+    bind, connect, getsockname, socket, AddressFamily, GetSockOpt, InetAddr, SockAddr, SockFlag,
+    SockType,
+};
+use nix::time::{clock_gettime, ClockId};
 use nix::Result;
+use std::ffi::CString;
+use std::net::SocketAddr;
+use std::os::fd::{FromRawFd, OwnedFd};
 use std::os::unix::io::RawFd;
 
 // Define the SO_COOKIE option
@@ -27,3 +34,247 @@ impl GetSockOpt for SoCookie {
         }
     }
 }
+
+fn getsockopt_int(fd: RawFd, level: libc::c_int, name: libc::c_int) -> Result<libc::c_int> {
+    unsafe {
+        let mut val: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = libc::getsockopt(
+            fd,
+            level,
+            name,
+            &mut val as *mut _ as *mut libc::c_void,
+            &mut len,
+        );
+        Errno::result(ret).map(|_| val)
+    }
+}
+
+// Define the IP_TOS option
+#[derive(Debug, Clone, Copy)]
+pub struct IpTos;
+
+impl GetSockOpt for IpTos {
+    type Val = libc::c_int;
+
+    fn get(&self, fd: RawFd) -> Result<Self::Val> {
+        getsockopt_int(fd, libc::IPPROTO_IP, libc::IP_TOS)
+    }
+}
+
+// Define the SO_PRIORITY option
+#[derive(Debug, Clone, Copy)]
+pub struct SoPriority;
+
+impl GetSockOpt for SoPriority {
+    type Val = libc::c_int;
+
+    fn get(&self, fd: RawFd) -> Result<Self::Val> {
+        getsockopt_int(fd, libc::SOL_SOCKET, libc::SO_PRIORITY)
+    }
+}
+
+/// Aborts the TCP connection of the socket right away: connecting a socket to an `AF_UNSPEC`
+/// address disconnects it, and the kernel sends a RST to the peer when the connection is
+/// established.
+pub fn reset_connection(fd: RawFd) -> Result<()> {
+    unsafe {
+        let mut addr: libc::sockaddr = std::mem::zeroed();
+        addr.sa_family = libc::AF_UNSPEC as libc::sa_family_t;
+        let ret = libc::connect(
+            fd,
+            &addr,
+            std::mem::size_of::<libc::sockaddr>() as libc::socklen_t,
+        );
+        Errno::result(ret).map(drop)
+    }
+}
+
+/// Starts connecting a non-blocking socket to `addr`, which sends the SYN right away, without
+/// waiting for the connection to be established. Returns the socket along with its local port.
+pub fn start_connect(addr: SocketAddr) -> Result<(OwnedFd, u16)> {
+    let fd = socket(
+        AddressFamily::Inet,
+        SockType::Stream,
+        SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+        None,
+    )?;
+    // Owned right away, for the socket to be closed on errors.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let any = SocketAddr::from(([0, 0, 0, 0], 0));
+    bind(fd, &SockAddr::new_inet(InetAddr::from_std(&any)))?;
+    let port = match getsockname(fd)? {
+        SockAddr::Inet(local) => local.port(),
+        _ => return Err(Errno::EAFNOSUPPORT),
+    };
+    match connect(fd, &SockAddr::new_inet(InetAddr::from_std(&addr))) {
+        Ok(()) | Err(Errno::EINPROGRESS) => Ok((socket, port)),
+        Err(error) => Err(error),
+    }
+}
+
+/// Opens an `AF_PACKET` socket receiving all the frames of `interface`, in both directions,
+/// before traffic control on ingress. Requires `CAP_NET_RAW`.
+pub fn open_packet_socket(interface: &str) -> Result<OwnedFd> {
+    let protocol = (libc::ETH_P_ALL as u16).to_be();
+    let name = CString::new(interface).map_err(|_| Errno::EINVAL)?;
+    unsafe {
+        let fd = Errno::result(libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::c_int::from(protocol),
+        ))?;
+        // Owned right away, for the socket to be closed on errors.
+        let socket = OwnedFd::from_raw_fd(fd);
+        let index = libc::if_nametoindex(name.as_ptr());
+        if index == 0 {
+            return Err(Errno::last());
+        }
+        let mut addr: libc::sockaddr_ll = std::mem::zeroed();
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = index as i32;
+        let ret = libc::bind(
+            fd,
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        );
+        Errno::result(ret)?;
+        Ok(socket)
+    }
+}
+
+/// Returns the monotonic time in nanoseconds, the clock of `bpf_ktime_get_ns`.
+pub fn monotonic_time_ns() -> Result<u64> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+    Ok(now.tv_sec() as u64 * 1_000_000_000 + now.tv_nsec() as u64)
+}
+
+/// Enables the `SO_TIMESTAMPING` timestamps of the given `SOF_TIMESTAMPING_*` flags.
+pub fn set_timestamping(fd: RawFd, flags: libc::c_uint) -> Result<()> {
+    unsafe {
+        let ret = libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+        );
+        Errno::result(ret).map(drop)
+    }
+}
+
+/// `tcpi_options` flag of a connection whose SYN data was acknowledged by the peer, i.e. which
+/// was set up with TCP Fast Open.
+pub const TCPI_OPT_SYN_DATA: u8 = 32;
+
+/// Defers the connection of the socket to its first write, whose data is sent in the SYN along
+/// with the TCP Fast Open cookie when the kernel has one for the peer (Linux 4.11 or later).
+/// Without a cookie, the connection is set up normally and requests one.
+pub fn set_tcp_fastopen_connect(fd: RawFd) -> Result<()> {
+    let enabled: libc::c_int = 1;
+    unsafe {
+        let ret = libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enabled as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+        Errno::result(ret).map(drop)
+    }
+}
+
+/// Restricts the calling thread to the given CPUs.
+pub fn set_thread_affinity(cpus: &[usize]) -> Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(Errno::EINVAL);
+            }
+            libc::CPU_SET(cpu, &mut set);
+        }
+        let ret = libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+        Errno::result(ret).map(drop)
+    }
+}
+
+/// Returns the `TCP_INFO` of the socket.
+pub fn tcp_info(fd: RawFd) -> Result<libc::tcp_info> {
+    unsafe {
+        let mut info: libc::tcp_info = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        let ret = libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut _ as *mut libc::c_void,
+            &mut len,
+        );
+        Errno::result(ret).map(|_| info)
+    }
+}
+
+/// Transmit timestamp read from the error queue of a socket.
+#[derive(Debug, Clone, Copy)]
+pub struct TxTimestamp {
+    /// Identifier of the send, with `SOF_TIMESTAMPING_OPT_ID`. For TCP sockets, offset of the
+    /// last byte of the send in the stream.
+    pub id: u32,
+    /// Software timestamp, in `CLOCK_REALTIME`, zero when not reported.
+    pub software: libc::timespec,
+    /// Raw hardware timestamp, in the clock of the network card, zero when not reported.
+    pub hardware: libc::timespec,
+}
+
+/// Reads the next transmit timestamp from the error queue of the socket, without blocking.
+/// Returns `None` when the queue is empty.
+pub fn read_tx_timestamp(fd: RawFd) -> Result<Option<TxTimestamp>> {
+    // Large enough for the SCM_TIMESTAMPING and IP_RECVERR messages, u64 for the alignment.
+    let mut control = [0u64; 64];
+    loop {
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let ret = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+        match Errno::result(ret) {
+            Ok(_) => {}
+            Err(Errno::EAGAIN) => return Ok(None),
+            Err(error) => return Err(error),
+        }
+
+        let mut timestamps = None;
+        let mut id = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::SOL_SOCKET, libc::SCM_TIMESTAMPING) => {
+                        timestamps =
+                            Some(std::ptr::read_unaligned(data as *const [libc::timespec; 3]));
+                    }
+                    (libc::SOL_IP, libc::IP_RECVERR) => {
+                        let error =
+                            std::ptr::read_unaligned(data as *const libc::sock_extended_err);
+                        if error.ee_origin == libc::SO_EE_ORIGIN_TIMESTAMPING {
+                            id = Some(error.ee_data);
+                        }
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        // Other errors of the queue are skipped, they are reported on the socket anyway.
+        if let (Some([software, _, hardware]), Some(id)) = (timestamps, id) {
+            return Ok(Some(TxTimestamp {
+                id,
+                software,
+                hardware,
+            }));
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::os::{IpTos, SoPriority};
+use nix::sys::socket::{getsockopt, sockopt};
+use serde::Serialize;
+use std::os::unix::io::RawFd;
+
+/// Options applied to a socket, as reported by `getsockopt`. Options that could not be read are
+/// left empty.
+#[derive(Debug, Default, Serialize)]
+pub struct SocketAuditReport {
+    pub tos: Option<i32>,
+    pub priority: Option<i32>,
+    pub mark: Option<u32>,
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<bool>,
+    pub reuse_addr: Option<bool>,
+    pub reuse_port: Option<bool>,
+    pub recv_buffer: Option<usize>,
+    pub send_buffer: Option<usize>,
+}
+
+/// Reads all the supported options of the given socket.
+pub fn audit_socket(fd: RawFd) -> SocketAuditReport {
+    SocketAuditReport {
+        tos: getsockopt(fd, IpTos).ok(),
+        priority: getsockopt(fd, SoPriority).ok(),
+        mark: getsockopt(fd, sockopt::Mark).ok(),
+        nodelay: getsockopt(fd, sockopt::TcpNoDelay).ok(),
+        keepalive: getsockopt(fd, sockopt::KeepAlive).ok(),
+        reuse_addr: getsockopt(fd, sockopt::ReuseAddr).ok(),
+        reuse_port: getsockopt(fd, sockopt::ReusePort).ok(),
+        recv_buffer: getsockopt(fd, sockopt::RcvBuf).ok(),
+        send_buffer: getsockopt(fd, sockopt::SndBuf).ok(),
+    }
+}